@@ -6,8 +6,53 @@ pub const DEFAULT_BASE_FEE_POINTS: u32 = 1_000; // 10%
 ///Max % cut for the partner
 pub const DEFAULT_MAX_FEE_POINTS: u32 = 10_000; // 100%
 
-pub const DEFAULT_OPERATION_FEE_POINTS: u8 = 0; // 0%
-pub const MAX_OPERATION_FEE_POINTS: u8 = 50; // 0.5%
+///Upper bound of the partner % cut
+pub const MAX_REWARD_FEE_POINTS: u32 = 10_000; // 100%
+
+pub const DEFAULT_OPERATION_FEE_POINTS: u16 = 0; // 0%
+
+///Operation fee cap set at initialization, admin-configurable per operation in GlobalState
+pub const DEFAULT_MAX_OPERATION_FEE_POINTS: u16 = 50; // 0.5%
+
+///Upper bound of the operation fee caps
+pub const MAX_OPERATION_FEE_CAP_POINTS: u16 = 10_000; // 100%
 
 ///Net stake target for max %
 pub const DEFAULT_MAX_NET_STAKE: u64 = 1_000_000 * LAMPORTS_PER_SOL;
+
+///PDA seed of the global state, one per program
+pub const GLOBAL_STATE_SEED: &[u8] = b"mr_global_state";
+
+///PDA seed of the referral state, combined with the partner account
+pub const REFERRAL_STATE_SEED: &[u8] = b"mr_referral_state";
+
+///PDA seed of the referee record, combined with the referral state and the user wallet
+pub const REFEREE_RECORD_SEED: &[u8] = b"mr_referee_record";
+
+///PDA seed of the ticket record, combined with the delayed-unstake ticket account
+pub const TICKET_RECORD_SEED: &[u8] = b"mr_ticket_record";
+
+///Layout version of the GlobalState account
+pub const GLOBAL_STATE_VERSION: u8 = 1;
+
+///Layout version of the ReferralState account, v0.2 accounts have no version field
+pub const REFERRAL_STATE_VERSION: u8 = 1;
+
+///Bits of ReferralState.pause, the paused operations of a partner.
+///The all-operations bit is the former bool flag, it also pauses the operations without their own bit
+pub const PAUSE_ALL_OPERATIONS: u8 = 1 << 0;
+pub const PAUSE_DEPOSIT_SOL: u8 = 1 << 1;
+pub const PAUSE_DEPOSIT_STAKE_ACCOUNT: u8 = 1 << 2;
+pub const PAUSE_LIQUID_UNSTAKE: u8 = 1 << 3;
+pub const PAUSE_DELAYED_UNSTAKE: u8 = 1 << 4;
+pub const PAUSE_BITS_MASK: u8 = PAUSE_ALL_OPERATIONS
+    | PAUSE_DEPOSIT_SOL
+    | PAUSE_DEPOSIT_STAKE_ACCOUNT
+    | PAUSE_LIQUID_UNSTAKE
+    | PAUSE_DELAYED_UNSTAKE;
+
+///Max length of the partner name
+pub const MAX_PARTNER_NAME_LENGTH: usize = 20;
+
+///PDA seed of the authority owning the mSOL treasury vault that pays partner rewards
+pub const TREASURY_VAULT_AUTHORITY_SEED: &[u8] = b"treasury_vault_authority";
@@ -0,0 +1,80 @@
+use anchor_lang::prelude::*;
+
+// Marinade instruction accounts not provided by marinade_onchain_helper::cpi_context_accounts
+// field order must match the marinade-finance instruction accounts
+
+//-----------------------------------------------------
+#[derive(Accounts)]
+pub struct MarinadeOrderUnstake<'info> {
+    #[account(mut)]
+    pub state: AccountInfo<'info>,
+    #[account(mut)]
+    pub msol_mint: AccountInfo<'info>,
+    #[account(mut)]
+    pub burn_msol_from: AccountInfo<'info>,
+    #[account(signer)]
+    pub burn_msol_authority: AccountInfo<'info>,
+    #[account(mut)]
+    pub new_ticket_account: AccountInfo<'info>,
+    pub clock: AccountInfo<'info>,
+    pub rent: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+}
+
+//-----------------------------------------------------
+#[derive(Accounts)]
+pub struct MarinadeClaim<'info> {
+    #[account(mut)]
+    pub state: AccountInfo<'info>,
+    #[account(mut)]
+    pub reserve_pda: AccountInfo<'info>,
+    #[account(mut)]
+    pub ticket_account: AccountInfo<'info>,
+    #[account(mut)]
+    pub transfer_sol_to: AccountInfo<'info>,
+    pub clock: AccountInfo<'info>,
+    pub system_program: AccountInfo<'info>,
+}
+
+//-----------------------------------------------------
+#[derive(Accounts)]
+pub struct MarinadeAddLiquidity<'info> {
+    #[account(mut)]
+    pub state: AccountInfo<'info>,
+    #[account(mut)]
+    pub lp_mint: AccountInfo<'info>,
+    pub lp_mint_authority: AccountInfo<'info>,
+    pub liq_pool_msol_leg: AccountInfo<'info>,
+    #[account(mut)]
+    pub liq_pool_sol_leg_pda: AccountInfo<'info>,
+    #[account(mut, signer)]
+    pub transfer_from: AccountInfo<'info>,
+    #[account(mut)]
+    pub mint_to: AccountInfo<'info>,
+    pub system_program: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+}
+
+//-----------------------------------------------------
+#[derive(Accounts)]
+pub struct MarinadeRemoveLiquidity<'info> {
+    #[account(mut)]
+    pub state: AccountInfo<'info>,
+    #[account(mut)]
+    pub lp_mint: AccountInfo<'info>,
+    #[account(mut)]
+    pub burn_from: AccountInfo<'info>,
+    #[account(signer)]
+    pub burn_from_authority: AccountInfo<'info>,
+    #[account(mut)]
+    pub transfer_sol_to: AccountInfo<'info>,
+    #[account(mut)]
+    pub transfer_msol_to: AccountInfo<'info>,
+    #[account(mut)]
+    pub liq_pool_sol_leg_pda: AccountInfo<'info>,
+    #[account(mut)]
+    pub liq_pool_msol_leg: AccountInfo<'info>,
+    pub liq_pool_msol_leg_authority: AccountInfo<'info>,
+    pub system_program: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+}
@@ -37,6 +37,59 @@ pub enum ReferralError {
     StakeAccountAuthMustBePartnerAccount,
     #[msg("Only allowed for stake-as-collateral-partners")]
     OnlyAllowedForStakeAsCollateralPartner,
-    #[msg("Invalid Global Account")]
-    InvalidGlobalAccount,
+    // retired, the global state PDA is checked by its seeds; the slot keeps the later codes stable
+    #[msg("Invalid Global Account (retired)")]
+    RetiredInvalidGlobalAccount,
+    #[msg("Ticket account still exists, claim the ticket first")]
+    TicketAccountNotClosed,
+    #[msg("LP mint is not the liquidity pool mint of the Marinade state")]
+    InvalidLpMint,
+    #[msg("Output amount is below the requested minimum")]
+    SlippageExceeded,
+    #[msg("Invalid treasury vault authority")]
+    InvalidTreasuryVaultAuthority,
+    #[msg("Not enough mSOL in the treasury vault")]
+    InsufficientTreasuryVaultBalance,
+    #[msg("Reward curve must satisfy base_fee <= max_fee <= 10000bp")]
+    RewardCurveFeeOutOfRange,
+    #[msg("Reward curve max_net_stake must be greater than zero")]
+    RewardCurveZeroNetStake,
+    #[msg("Signer is not the pending admin")]
+    NotPendingAdmin,
+    #[msg("Partner account of a referral state can't be changed")]
+    PartnerAccountChangeNotAllowed,
+    #[msg("Calculation failure")]
+    CalculationFailure,
+    #[msg("Account is not a v0.2 referral state")]
+    NotMigratableReferralState,
+    #[msg("Partner account does not match the migrated referral state")]
+    MigrationPartnerMismatch,
+    #[msg("Referral account has unsettled liquid-unstake fees")]
+    ReferralNotSettled,
+    #[msg("Operation fee cap was set over the maximum permitted amount")]
+    OperationFeeCapOverMax,
+    #[msg("Fee beneficiaries need a non-zero share, all shares up to 10000bp")]
+    InvalidFeeBeneficiaries,
+    #[msg("Remaining accounts do not match the fee beneficiaries")]
+    FeeBeneficiaryAccountsMismatch,
+    #[msg("Referee record is not the PDA of the referral state and user or has no payer")]
+    InvalidRefereeRecord,
+    #[msg("Stake-as-collateral deposit must split off the kept stake")]
+    KeepSelfStakeSplitRequired,
+    #[msg("Marinade state does not match the global state")]
+    InvalidMarinadeState,
+    #[msg("Paused by the global emergency pause")]
+    GlobalPaused,
+    #[msg("Unknown paused operation bits")]
+    InvalidPauseBits,
+    #[msg("Partner mSOL token account is not an mSOL token account")]
+    InvalidPartnerMsolAccount,
+    #[msg("Fee source is not a token account of the operation mint")]
+    InvalidFeeSourceAccount,
+    #[msg("Fee authority is neither the owner nor the delegate of the fee source")]
+    InvalidFeeAuthority,
+    #[msg("Operation fee is over the amount delegated to the fee authority")]
+    FeeOverDelegatedAmount,
+    #[msg("Deposit over the partner total or per-epoch cap")]
+    DepositCapExceeded,
 }
@@ -0,0 +1,114 @@
+use anchor_lang::prelude::*;
+
+// events are emitted to the program logs for the off-chain indexers,
+// the accumulator fields hold the referral state values after the operation
+
+//-----------------------------------------------------
+#[event]
+pub struct DepositEvent {
+    pub referral_state: Pubkey,
+    // transfer_from, the SOL depositor
+    pub user: Pubkey,
+    pub lamports: u64,
+    pub minted_msol: u64,
+    // part of minted_msol transferred to the partner
+    pub operation_fee: u64,
+
+    pub deposit_sol_amount: u128,
+    pub deposit_sol_operations: u64,
+    pub accum_deposit_sol_fee: u64,
+}
+
+//-----------------------------------------------------
+#[event]
+pub struct DepositStakeAccountEvent {
+    pub referral_state: Pubkey,
+    // stake_authority, the stake account owner
+    pub user: Pubkey,
+    pub stake_account: Pubkey,
+    // delegated stake of the deposited stake account
+    pub lamports: u64,
+    // stake split off and kept delegated to the partner validator (stake-as-collateral)
+    pub kept_stake: u64,
+    pub minted_msol: u64,
+    // part of minted_msol transferred to the partner
+    pub operation_fee: u64,
+
+    pub deposit_stake_account_amount: u128,
+    pub deposit_stake_account_operations: u64,
+    pub accum_deposit_stake_account_fee: u64,
+    pub keep_self_stake_amount: u128,
+}
+
+//-----------------------------------------------------
+#[event]
+pub struct LiquidUnstakeEvent {
+    pub referral_state: Pubkey,
+    // get_msol_from_authority, the mSOL owner
+    pub user: Pubkey,
+    // mSOL sent to Marinade, operation fee already deducted
+    pub msol_amount: u64,
+    // SOL value of msol_amount before the liquid-unstake fee
+    pub lamports: u64,
+    // mSOL transferred to the partner
+    pub operation_fee: u64,
+    pub treasury_msol_cut: u64,
+
+    pub liq_unstake_msol_amount: u128,
+    pub liq_unstake_sol_amount: u128,
+    pub liq_unstake_operations: u64,
+    pub liq_unstake_msol_fees: u64,
+    pub accum_liquid_unstake_fee: u64,
+}
+
+//-----------------------------------------------------
+#[event]
+pub struct RefreshCollateralEvent {
+    pub referral_state: Pubkey,
+    pub msol_token_partner_account: Pubkey,
+    pub msol_amount: u64,
+    // SOL value of msol_amount at the Marinade mSOL price
+    pub current_collateral_lamports: u64,
+    pub slot: u64,
+
+    pub total_deposit_stake_account_amount: u128,
+    // deposited stake not covered by the collateral, 0 when covered
+    pub collateral_shortfall_lamports: u128,
+    pub keep_self_stake_amount: u128,
+}
+
+//-----------------------------------------------------
+#[event]
+pub struct CloseReferralAccountEvent {
+    pub referral_state: Pubkey,
+    pub partner_account: Pubkey,
+    // final snapshot of the accumulators
+    pub period_snapshot: Pubkey,
+    // receiver of the referral state rent
+    pub rent_destination: Pubkey,
+    pub lamports: u64,
+}
+
+//-----------------------------------------------------
+#[event]
+pub struct AdminChangeEvent {
+    pub global_state: Pubkey,
+    pub old_admin: Pubkey,
+    pub new_admin: Pubkey,
+}
+
+//-----------------------------------------------------
+#[event]
+pub struct FeeUpdateEvent {
+    pub referral_state: Pubkey,
+    // admin or foreman
+    pub signer: Pubkey,
+
+    // operation fees after the update, in basis points
+    pub operation_deposit_sol_fee: u16,
+    pub operation_deposit_stake_account_fee: u16,
+    pub operation_liquid_unstake_fee: u16,
+    pub operation_delayed_unstake_fee: u16,
+    pub operation_add_liquidity_fee: u16,
+    pub operation_remove_liquidity_fee: u16,
+}
@@ -0,0 +1,129 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::TokenAccount;
+
+use marinade_onchain_helper::cpi_util;
+
+use super::common::{
+    apply_fee, check_delegated_fee, check_marinade_state, check_token_fee_source,
+    load_marinade_state, token_balance, transfer_lp_fee,
+};
+use crate::constant::{GLOBAL_STATE_SEED, PAUSE_ALL_OPERATIONS};
+use crate::cpi_context_accounts::MarinadeAddLiquidity;
+use crate::error::ReferralError::*;
+use crate::states::{GlobalState, ReferralState};
+
+//-----------------------------------------------------
+#[derive(Accounts)]
+pub struct AddLiquidity<'info> {
+    // this part is equivalent to marinade-finance add-liquidity instructions
+    // Marinade state, checked against the global state by process
+    #[account(mut)]
+    pub state: AccountInfo<'info>,
+    #[account(mut)]
+    pub lp_mint: AccountInfo<'info>, // liquidity pool mint of the Marinade state, checked in process
+    pub lp_mint_authority: AccountInfo<'info>,
+    pub liq_pool_msol_leg: AccountInfo<'info>,
+    #[account(mut)]
+    pub liq_pool_sol_leg_pda: AccountInfo<'info>,
+    #[account(mut, signer)]
+    pub transfer_from: AccountInfo<'info>,
+    #[account(mut)]
+    pub mint_to: AccountInfo<'info>,
+    pub system_program: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+
+    // accounts added are: Marinade main program ID, global state, referral_state, partner LP token account
+    #[account(address = marinade_finance::ID)]
+    pub marinade_finance_program: AccountInfo<'info>,
+    #[account(seeds = [GLOBAL_STATE_SEED], bump = global_state.bump)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+    #[account(mut, constraint = !referral_state.is_paused(PAUSE_ALL_OPERATIONS))]
+    pub referral_state: ProgramAccount<'info, ReferralState>,
+    #[account(
+        mut,
+        constraint = lp_token_partner_account.owner == referral_state.partner_account
+            && lp_token_partner_account.mint == *lp_mint.key
+    )]
+    pub lp_token_partner_account: CpiAccount<'info, TokenAccount>,
+}
+
+impl<'info> AddLiquidity<'info> {
+    pub fn process(&mut self, lamports: u64) -> ProgramResult {
+        msg!("enter AddLiquidity::process {}", lamports);
+
+        check_marinade_state(&self.global_state, &self.state)?;
+
+        // disallow for stake-as-collateral mode
+        if self.referral_state.validator_vote_key.is_some() {
+            return Err(NotAllowedForStakeAsCollateralPartner.into());
+        };
+
+        let marinade_state =
+            load_marinade_state(&self.state, &self.global_state.msol_mint_account)?;
+        if *self.lp_mint.key != marinade_state.liq_pool.lp_mint {
+            msg!(
+                "LP mint {} is not the liquidity pool mint {}",
+                self.lp_mint.key,
+                marinade_state.liq_pool.lp_mint
+            );
+            return Err(InvalidLpMint.into());
+        }
+        // mint_to receives the minted LP tokens and pays the fee signed by transfer_from
+        check_token_fee_source(&self.mint_to, self.lp_mint.key, &self.transfer_from)?;
+
+        // LP balance before add-liquidity call
+        let lp_before = token_balance(&self.mint_to)?;
+
+        // call Marinade
+        let cpi_ctx = self.into_add_liquidity_cpi_ctx();
+        let data = marinade_finance::instruction::AddLiquidity { lamports };
+        cpi_util::invoke_signed(cpi_ctx, data)?;
+
+        // LP balance after add-liquidity call
+        let lp_after = token_balance(&self.mint_to)?;
+        // add-liquidity fee is transferred in LP tokens to the partner LP token account
+        let minted_lp = lp_after.checked_sub(lp_before).ok_or(CalculationFailure)?;
+        msg!(
+            "minted LP {} after adding {} lamports of liquidity",
+            minted_lp,
+            lamports
+        );
+        // the minted LP amount is only known after the call
+        check_delegated_fee(
+            &self.mint_to,
+            &self.transfer_from,
+            apply_fee(self.referral_state.operation_add_liquidity_fee, minted_lp)?,
+        )?;
+        let operation_fee = transfer_lp_fee(
+            minted_lp,
+            self.referral_state.operation_add_liquidity_fee,
+            &self.token_program,
+            &self.mint_to,
+            &self.lp_token_partner_account.to_account_info(),
+            &self.transfer_from,
+        )?;
+
+        // update accumulators
+        self.referral_state
+            .record_add_liquidity(lamports, operation_fee)?;
+        Ok(())
+    }
+
+    pub fn into_add_liquidity_cpi_ctx(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, MarinadeAddLiquidity<'info>> {
+        let cpi_accounts = MarinadeAddLiquidity {
+            state: self.state.clone(),
+            lp_mint: self.lp_mint.clone(),
+            lp_mint_authority: self.lp_mint_authority.clone(),
+            liq_pool_msol_leg: self.liq_pool_msol_leg.clone(),
+            liq_pool_sol_leg_pda: self.liq_pool_sol_leg_pda.clone(),
+            transfer_from: self.transfer_from.clone(),
+            mint_to: self.mint_to.clone(),
+            system_program: self.system_program.clone(),
+            token_program: self.token_program.clone(),
+        };
+
+        CpiContext::new(self.marinade_finance_program.clone(), cpi_accounts)
+    }
+}
@@ -1,71 +1,105 @@
-use std::str::FromStr;
-
 use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
 use anchor_spl::token::{Mint, TokenAccount};
 use solana_program::program_pack::IsInitialized;
 
+use super::common::{check_fee_beneficiary_accounts, load_marinade_state};
 use crate::constant::*;
 use crate::error::ReferralError::*;
 use crate::error::*;
-use crate::states::{GlobalState, ReferralState};
+use crate::events::{AdminChangeEvent, CloseReferralAccountEvent, FeeUpdateEvent};
+use crate::states::{
+    FeeBeneficiary, GlobalState, PeriodSnapshot, ReferralState, ReferralStateV0_2,
+};
 
 //-----------------------------------------------------
 #[derive(Accounts)]
+#[instruction(bump: u8)]
 pub struct Initialize<'info> {
-    #[account(signer)]
+    #[account(mut, signer)]
     pub admin_account: AccountInfo<'info>,
 
-    #[account(zero)]
+    // the only global state, PDA derived from GLOBAL_STATE_SEED
+    #[account(
+        init,
+        seeds = [GLOBAL_STATE_SEED],
+        bump = bump,
+        payer = admin_account,
+        space = 8 + GlobalState::LEN
+    )]
     pub global_state: ProgramAccount<'info, GlobalState>,
 
     #[account()]
     pub msol_mint_account: CpiAccount<'info, Mint>,
 
+    // Marinade state minting msol_mint_account, the wrapper instructions are pinned to it
+    pub marinade_state: AccountInfo<'info>,
+
     pub foreman_1: AccountInfo<'info>,
     pub foreman_2: AccountInfo<'info>,
+
+    pub system_program: AccountInfo<'info>,
 }
 impl<'info> Initialize<'info> {
-    pub fn process(&mut self, min_keep_pct: u8, max_keep_pct: u8) -> ProgramResult {
-        check_global_state_address(self.global_state.key())?;
+    pub fn process(&mut self, bump: u8, min_keep_pct: u8, max_keep_pct: u8) -> ProgramResult {
+        self.global_state.version = GLOBAL_STATE_VERSION;
+        self.global_state.bump = bump;
+
+        self.global_state.max_operation_deposit_sol_fee = DEFAULT_MAX_OPERATION_FEE_POINTS;
+        self.global_state.max_operation_deposit_stake_account_fee =
+            DEFAULT_MAX_OPERATION_FEE_POINTS;
+        self.global_state.max_operation_liquid_unstake_fee = DEFAULT_MAX_OPERATION_FEE_POINTS;
+        self.global_state.max_operation_delayed_unstake_fee = DEFAULT_MAX_OPERATION_FEE_POINTS;
+        self.global_state.max_operation_add_liquidity_fee = DEFAULT_MAX_OPERATION_FEE_POINTS;
+        self.global_state.max_operation_remove_liquidity_fee = DEFAULT_MAX_OPERATION_FEE_POINTS;
+        self.global_state.paused = false;
 
         self.global_state.admin_account = self.admin_account.key();
+        self.global_state.pending_admin = None;
         self.global_state.msol_mint_account = self.msol_mint_account.key();
         self.global_state.foreman_1 = self.foreman_1.key();
         self.global_state.foreman_2 = self.foreman_2.key();
 
-        if min_keep_pct > max_keep_pct {
-            return Err(MinMaxKeepPctOutOfRange.into());
-        }
+        check_keep_range(min_keep_pct, max_keep_pct)?;
         self.global_state.min_keep_pct = min_keep_pct;
-        if max_keep_pct > 100 {
-            return Err(MaxKeepPctOutOfRange.into());
-        }
         self.global_state.max_keep_pct = max_keep_pct;
 
         // verify if the account that should be considered as MSOL mint is an active mint account
         if !self.msol_mint_account.is_initialized() {
             return Err(ReferralError::NotInitializedMintAccount.into());
         }
+        load_marinade_state(&self.marinade_state, &self.msol_mint_account.key())?;
+        self.global_state.marinade_state = *self.marinade_state.key;
         Ok(())
     }
 }
 
 //-----------------------------------------------------
 #[derive(Accounts)]
+#[instruction(partner_name: String, validator_vote_key: Option<Pubkey>, keep_self_stake_pct: u8, bump: u8)]
 pub struct InitReferralAccount<'info> {
     // global state
     // note if this constraint is not satisfied the err is: 0x8f/143: A raw constraint was violated
     // TODO: can we provide a better message? as "signer is authority to create a referral-account"
     #[account(
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
         constraint = *signer.key == global_state.admin_account || *signer.key == global_state.foreman_1 || *signer.key == global_state.foreman_2
     )]
     pub global_state: ProgramAccount<'info, GlobalState>,
 
-    // admin account or foreman account
-    #[account(signer)]
+    // admin account or foreman account, pays for the referral state account
+    #[account(mut, signer)]
     pub signer: AccountInfo<'info>,
 
-    #[account(zero)] // must be created but empty, ready to be initialized
+    // PDA of the partner account, only one referral state per partner
+    #[account(
+        init,
+        seeds = [REFERRAL_STATE_SEED, partner_account.key.as_ref()],
+        bump = bump,
+        payer = signer,
+        space = 8 + ReferralState::LEN
+    )]
     pub referral_state: ProgramAccount<'info, ReferralState>,
 
     // partner main account
@@ -74,6 +108,8 @@ pub struct InitReferralAccount<'info> {
     // partner mSOL beneficiary token account
     #[account()]
     pub msol_token_partner_account: CpiAccount<'info, TokenAccount>,
+
+    pub system_program: AccountInfo<'info>,
 }
 
 impl<'info> InitReferralAccount<'info> {
@@ -82,11 +118,11 @@ impl<'info> InitReferralAccount<'info> {
         partner_name: String,
         validator_vote_key: Option<Pubkey>,
         keep_self_stake_pct: u8,
+        bump: u8,
     ) -> ProgramResult {
-        check_global_state_address(self.global_state.key())?; // double-check
         msg!("process_init_referral_account");
-        if partner_name.len() > 20 {
-            msg!("max partner_name.len() is 20");
+        if partner_name.len() > MAX_PARTNER_NAME_LENGTH {
+            msg!("max partner_name.len() is {}", MAX_PARTNER_NAME_LENGTH);
             return Err(ReferralError::PartnerNameTooLong.into());
         }
 
@@ -97,22 +133,13 @@ impl<'info> InitReferralAccount<'info> {
             &self.global_state.msol_mint_account,
         )?;
 
+        self.referral_state.version = REFERRAL_STATE_VERSION;
         self.referral_state.partner_name = partner_name.clone();
 
         self.referral_state.validator_vote_key = validator_vote_key;
         // if stake-as-collateral mode
         if validator_vote_key.is_some() {
-            if !(keep_self_stake_pct >= self.global_state.min_keep_pct
-                && keep_self_stake_pct <= self.global_state.max_keep_pct)
-            {
-                msg!(
-                    "keep_pct {} must be >= {} and <= {}",
-                    keep_self_stake_pct,
-                    self.global_state.min_keep_pct,
-                    self.global_state.max_keep_pct
-                );
-                return Err(KeepPctOutOfRange.into());
-            };
+            check_keep_pct(&self.global_state, keep_self_stake_pct)?;
             self.referral_state.keep_self_stake_pct = keep_self_stake_pct
         };
 
@@ -137,7 +164,7 @@ impl<'info> InitReferralAccount<'info> {
         self.referral_state.base_fee = DEFAULT_BASE_FEE_POINTS;
         self.referral_state.max_fee = DEFAULT_MAX_FEE_POINTS;
 
-        self.referral_state.pause = false;
+        self.referral_state.pause = 0;
 
         self.referral_state.operation_deposit_sol_fee = DEFAULT_OPERATION_FEE_POINTS;
         self.referral_state.operation_deposit_stake_account_fee = DEFAULT_OPERATION_FEE_POINTS;
@@ -149,10 +176,54 @@ impl<'info> InitReferralAccount<'info> {
         self.referral_state.accum_liquid_unstake_fee = 0;
         self.referral_state.accum_delayed_unstake_fee = 0;
 
+        self.referral_state.delayed_unstake_claimed_lamports = 0;
+        self.referral_state.delayed_unstake_claim_operations = 0;
+
+        self.referral_state.add_liquidity_sol_amount = 0;
+        self.referral_state.add_liquidity_operations = 0;
+        self.referral_state.remove_liquidity_sol_amount = 0;
+        self.referral_state.remove_liquidity_msol_amount = 0;
+        self.referral_state.remove_liquidity_operations = 0;
+
+        self.referral_state.operation_add_liquidity_fee = DEFAULT_OPERATION_FEE_POINTS;
+        self.referral_state.operation_remove_liquidity_fee = DEFAULT_OPERATION_FEE_POINTS;
+
+        self.referral_state.accum_add_liquidity_fee = 0;
+        self.referral_state.accum_remove_liquidity_fee = 0;
+
+        self.referral_state.claimed_liq_unstake_share = 0;
+
+        self.referral_state.bump = bump;
+
         Ok(())
     }
 }
 
+fn check_keep_range(min_keep_pct: u8, max_keep_pct: u8) -> ProgramResult {
+    if min_keep_pct > max_keep_pct {
+        return Err(MinMaxKeepPctOutOfRange.into());
+    }
+    if max_keep_pct > 100 {
+        return Err(MaxKeepPctOutOfRange.into());
+    }
+    Ok(())
+}
+
+fn check_keep_pct(global_state: &GlobalState, keep_self_stake_pct: u8) -> ProgramResult {
+    if !(keep_self_stake_pct >= global_state.min_keep_pct
+        && keep_self_stake_pct <= global_state.max_keep_pct)
+    {
+        msg!(
+            "keep_pct {} must be >= {} and <= {}",
+            keep_self_stake_pct,
+            global_state.min_keep_pct,
+            global_state.max_keep_pct
+        );
+        return Err(KeepPctOutOfRange.into());
+    };
+    Ok(())
+}
+
 fn check_partner_accounts<'info>(
     partner_account: &AccountInfo<'info>,
     msol_token_partner_account: &CpiAccount<'info, TokenAccount>,
@@ -180,26 +251,94 @@ fn check_partner_accounts<'info>(
 
 //--------------------------------------
 #[derive(Accounts)]
-pub struct ChangeAuthority<'info> {
+pub struct ProposeAuthority<'info> {
     // global state
-    #[account(mut, has_one = admin_account)]
+    #[account(mut, seeds = [GLOBAL_STATE_SEED], bump = global_state.bump, has_one = admin_account)]
     pub global_state: ProgramAccount<'info, GlobalState>,
 
     // current admin account (must match the one in GlobalState)
     #[account(signer)]
     pub admin_account: AccountInfo<'info>,
 
-    // new admin account
+    // proposed admin account, must sign AcceptAuthority to become the admin
     pub new_admin_account: AccountInfo<'info>,
+}
+impl<'info> ProposeAuthority<'info> {
+    pub fn process(&mut self) -> ProgramResult {
+        self.global_state.pending_admin = Some(*self.new_admin_account.key);
+        Ok(())
+    }
+}
+
+//--------------------------------------
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    // global state
+    #[account(mut, seeds = [GLOBAL_STATE_SEED], bump = global_state.bump)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+
+    // proposed admin account (must match the pending one in GlobalState)
+    #[account(signer)]
+    pub new_admin_account: AccountInfo<'info>,
+}
+impl<'info> AcceptAuthority<'info> {
+    pub fn process(&mut self) -> ProgramResult {
+        if self.global_state.pending_admin != Some(*self.new_admin_account.key) {
+            msg!(
+                "{} is not the pending admin {:?}",
+                self.new_admin_account.key,
+                self.global_state.pending_admin
+            );
+            return Err(NotPendingAdmin.into());
+        }
+        let old_admin = self.global_state.admin_account;
+        self.global_state.admin_account = *self.new_admin_account.key;
+        self.global_state.pending_admin = None;
+
+        emit!(AdminChangeEvent {
+            global_state: self.global_state.key(),
+            old_admin,
+            new_admin: self.global_state.admin_account,
+        });
+        Ok(())
+    }
+}
+
+//--------------------------------------
+#[derive(Accounts)]
+pub struct CancelAuthorityProposal<'info> {
+    // global state
+    #[account(mut, seeds = [GLOBAL_STATE_SEED], bump = global_state.bump, has_one = admin_account)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+
+    // current admin account (must match the one in GlobalState)
+    #[account(signer)]
+    pub admin_account: AccountInfo<'info>,
+}
+impl<'info> CancelAuthorityProposal<'info> {
+    pub fn process(&mut self) -> ProgramResult {
+        self.global_state.pending_admin = None;
+        Ok(())
+    }
+}
+
+//--------------------------------------
+#[derive(Accounts)]
+pub struct UpdateForemen<'info> {
+    // global state
+    #[account(mut, seeds = [GLOBAL_STATE_SEED], bump = global_state.bump, has_one = admin_account)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+
+    // current admin account (must match the one in GlobalState)
+    #[account(signer)]
+    pub admin_account: AccountInfo<'info>,
 
     // new foremen accounts
     pub new_foreman_1: AccountInfo<'info>,
     pub new_foreman_2: AccountInfo<'info>,
 }
-impl<'info> ChangeAuthority<'info> {
+impl<'info> UpdateForemen<'info> {
     pub fn process(&mut self) -> ProgramResult {
-        check_global_state_address(self.global_state.key())?; // double-check
-        self.global_state.admin_account = *self.new_admin_account.key;
         self.global_state.foreman_1 = *self.new_foreman_1.key;
         self.global_state.foreman_2 = *self.new_foreman_2.key;
         Ok(())
@@ -210,7 +349,7 @@ impl<'info> ChangeAuthority<'info> {
 #[derive(Accounts)]
 pub struct UpdateReferral<'info> {
     // global state
-    #[account(has_one = admin_account)]
+    #[account(seeds = [GLOBAL_STATE_SEED], bump = global_state.bump, has_one = admin_account)]
     pub global_state: ProgramAccount<'info, GlobalState>,
 
     // admin account
@@ -229,22 +368,33 @@ pub struct UpdateReferral<'info> {
     pub new_msol_token_partner_account: CpiAccount<'info, TokenAccount>,
 }
 impl<'info> UpdateReferral<'info> {
-    pub fn process(&mut self, pause: bool) -> ProgramResult {
+    // pause is the bitmask of the paused operations, 0 resumes all of them
+    pub fn process(&mut self, pause: u8) -> ProgramResult {
+        if pause & !PAUSE_BITS_MASK != 0 {
+            return Err(InvalidPauseBits.into());
+        }
         self.referral_state.pause = pause;
-        check_global_state_address(self.global_state.key())?; // double-check
 
-        if *self.new_partner_account.key != self.referral_state.partner_account
-            || self.new_msol_token_partner_account.key()
-                != self.referral_state.msol_token_partner_account
+        // the referral state is the PDA of its partner account, a new partner needs its own
+        if *self.new_partner_account.key != self.referral_state.partner_account {
+            msg!(
+                "partner account {} of referral state {} can't be changed to {}",
+                self.referral_state.partner_account,
+                self.referral_state.key(),
+                self.new_partner_account.key
+            );
+            return Err(PartnerAccountChangeNotAllowed.into());
+        }
+        if self.new_msol_token_partner_account.key()
+            != self.referral_state.msol_token_partner_account
         {
-            self.referral_state.partner_account = *self.new_partner_account.key;
-            self.referral_state.msol_token_partner_account =
-                self.new_msol_token_partner_account.key();
             check_partner_accounts(
                 &self.new_partner_account,
                 &self.new_msol_token_partner_account,
                 &self.global_state.msol_mint_account,
             )?;
+            self.referral_state.msol_token_partner_account =
+                self.new_msol_token_partner_account.key();
         }
 
         Ok(())
@@ -256,6 +406,8 @@ impl<'info> UpdateReferral<'info> {
 pub struct UpdateOperationFees<'info> {
     // global state
     #[account(
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
         constraint = *signer.key == global_state.admin_account || *signer.key == global_state.foreman_1 || *signer.key == global_state.foreman_2
     )]
     pub global_state: ProgramAccount<'info, GlobalState>,
@@ -271,49 +423,77 @@ pub struct UpdateOperationFees<'info> {
 impl<'info> UpdateOperationFees<'info> {
     pub fn process(
         &mut self,
-        operation_deposit_sol_fee: Option<u8>,
-        operation_deposit_stake_account_fee: Option<u8>,
-        operation_liquid_unstake_fee: Option<u8>,
-        operation_delayed_unstake_fee: Option<u8>,
+        operation_deposit_sol_fee: Option<u16>,
+        operation_deposit_stake_account_fee: Option<u16>,
+        operation_liquid_unstake_fee: Option<u16>,
+        operation_delayed_unstake_fee: Option<u16>,
+        operation_add_liquidity_fee: Option<u16>,
+        operation_remove_liquidity_fee: Option<u16>,
     ) -> ProgramResult {
         // disallow for stake-as-collateral mode, fees must be zero in that mode
         if self.referral_state.validator_vote_key.is_some() {
             return Err(NotAllowedForStakeAsCollateralPartner.into());
         };
-        check_global_state_address(self.global_state.key())?; // double-check
 
         set_fee_checked(
             &mut self.referral_state.operation_deposit_sol_fee,
             operation_deposit_sol_fee,
+            self.global_state.max_operation_deposit_sol_fee,
         )?;
         set_fee_checked(
             &mut self.referral_state.operation_deposit_stake_account_fee,
             operation_deposit_stake_account_fee,
+            self.global_state.max_operation_deposit_stake_account_fee,
         )?;
         set_fee_checked(
             &mut self.referral_state.operation_liquid_unstake_fee,
             operation_liquid_unstake_fee,
+            self.global_state.max_operation_liquid_unstake_fee,
         )?;
         set_fee_checked(
             &mut self.referral_state.operation_delayed_unstake_fee,
             operation_delayed_unstake_fee,
+            self.global_state.max_operation_delayed_unstake_fee,
+        )?;
+        set_fee_checked(
+            &mut self.referral_state.operation_add_liquidity_fee,
+            operation_add_liquidity_fee,
+            self.global_state.max_operation_add_liquidity_fee,
+        )?;
+        set_fee_checked(
+            &mut self.referral_state.operation_remove_liquidity_fee,
+            operation_remove_liquidity_fee,
+            self.global_state.max_operation_remove_liquidity_fee,
         )?;
 
+        emit!(FeeUpdateEvent {
+            referral_state: self.referral_state.key(),
+            signer: *self.signer.key,
+            operation_deposit_sol_fee: self.referral_state.operation_deposit_sol_fee,
+            operation_deposit_stake_account_fee: self
+                .referral_state
+                .operation_deposit_stake_account_fee,
+            operation_liquid_unstake_fee: self.referral_state.operation_liquid_unstake_fee,
+            operation_delayed_unstake_fee: self.referral_state.operation_delayed_unstake_fee,
+            operation_add_liquidity_fee: self.referral_state.operation_add_liquidity_fee,
+            operation_remove_liquidity_fee: self.referral_state.operation_remove_liquidity_fee,
+        });
         Ok(())
     }
 }
 
 fn set_fee_checked(
-    current_value: &mut u8,
-    new_value: Option<u8>,
+    current_value: &mut u16,
+    new_value: Option<u16>,
+    max_fee: u16,
 ) -> std::result::Result<(), ReferralError> {
     if let Some(new_fee) = new_value {
-        // the fee is calculated as basis points
-        if new_fee > MAX_OPERATION_FEE_POINTS {
+        // the fee is calculated as basis points, capped per operation in the global state
+        if new_fee > max_fee {
             msg!(
                 "Operation fee value {}bp is over maximal permitted {}bp",
                 new_fee,
-                MAX_OPERATION_FEE_POINTS
+                max_fee
             );
             return Err(ReferralOperationFeeOverMax);
         }
@@ -322,14 +502,317 @@ fn set_fee_checked(
     Ok(())
 }
 
-fn check_global_state_address(key: Pubkey) -> ProgramResult {
-    // Note: the referral accounts are not linked explicitly to the global account
-    // so we need to allow only and only one specific global account to avoid the simple attack
-    // of creating *a fake* global account in another address where the attacker is admin and use it as authorization
-    // to alter data from real referral accounts
-    if key != Pubkey::from_str("MRSh4rUNrpn7mjAq9ENHV4rvwwPKMij113ScZq3twp2").unwrap() {
-        Err(ReferralError::InvalidGlobalAccount.into())
-    } else {
+//-----------------------------------------------------
+// stake-as-collateral terms of a partner: validator and kept stake percentage
+#[derive(Accounts)]
+pub struct UpdateCollateralConfig<'info> {
+    // global state
+    #[account(
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+        constraint = *signer.key == global_state.admin_account || *signer.key == global_state.foreman_1 || *signer.key == global_state.foreman_2
+    )]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+
+    // admin or foreman account
+    #[account(signer)]
+    pub signer: AccountInfo<'info>,
+
+    // referral state of a stake-as-collateral partner
+    #[account(mut)]
+    pub referral_state: ProgramAccount<'info, ReferralState>,
+}
+impl<'info> UpdateCollateralConfig<'info> {
+    pub fn process(
+        &mut self,
+        validator_vote_key: Pubkey,
+        keep_self_stake_pct: u8,
+    ) -> ProgramResult {
+        // the mode itself is set at registration, operation fees depend on it
+        if self.referral_state.validator_vote_key.is_none() {
+            return Err(OnlyAllowedForStakeAsCollateralPartner.into());
+        }
+        check_keep_pct(&self.global_state, keep_self_stake_pct)?;
+
+        self.referral_state.validator_vote_key = Some(validator_vote_key);
+        self.referral_state.keep_self_stake_pct = keep_self_stake_pct;
+        Ok(())
+    }
+}
+
+//-----------------------------------------------------
+#[derive(Accounts)]
+pub struct UpdateGlobalKeepRange<'info> {
+    // global state
+    #[account(mut, seeds = [GLOBAL_STATE_SEED], bump = global_state.bump, has_one = admin_account)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+
+    // admin account
+    #[account(signer)]
+    pub admin_account: AccountInfo<'info>,
+}
+impl<'info> UpdateGlobalKeepRange<'info> {
+    // the range applies to new terms, keep_pct already set in the referral states is not changed
+    pub fn process(&mut self, min_keep_pct: u8, max_keep_pct: u8) -> ProgramResult {
+        check_keep_range(min_keep_pct, max_keep_pct)?;
+        self.global_state.min_keep_pct = min_keep_pct;
+        self.global_state.max_keep_pct = max_keep_pct;
+        Ok(())
+    }
+}
+
+//-----------------------------------------------------
+#[derive(Accounts)]
+pub struct SetGlobalPause<'info> {
+    // global state
+    #[account(
+        mut,
+        seeds = [GLOBAL_STATE_SEED],
+        bump = global_state.bump,
+        constraint = *signer.key == global_state.admin_account || *signer.key == global_state.foreman_1 || *signer.key == global_state.foreman_2
+    )]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+
+    // admin or foreman account
+    #[account(signer)]
+    pub signer: AccountInfo<'info>,
+}
+impl<'info> SetGlobalPause<'info> {
+    // the partner pause flags are kept, unpausing does not resume paused partners
+    pub fn process(&mut self, paused: bool) -> ProgramResult {
+        self.global_state.paused = paused;
+        msg!("global pause set to {} by {}", paused, self.signer.key);
+        Ok(())
+    }
+}
+
+//-----------------------------------------------------
+#[derive(Accounts)]
+pub struct UpdateMarinadeState<'info> {
+    // global state
+    #[account(mut, seeds = [GLOBAL_STATE_SEED], bump = global_state.bump, has_one = admin_account)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+
+    // admin account
+    #[account(signer)]
+    pub admin_account: AccountInfo<'info>,
+
+    // Marinade state minting the mSOL of the global state
+    pub new_marinade_state: AccountInfo<'info>,
+}
+impl<'info> UpdateMarinadeState<'info> {
+    pub fn process(&mut self) -> ProgramResult {
+        load_marinade_state(
+            &self.new_marinade_state,
+            &self.global_state.msol_mint_account,
+        )?;
+        self.global_state.marinade_state = *self.new_marinade_state.key;
+        msg!("Marinade state set to {}", self.new_marinade_state.key);
+        Ok(())
+    }
+}
+
+//-----------------------------------------------------
+#[derive(Accounts)]
+pub struct UpdateDepositCaps<'info> {
+    // global state
+    #[account(seeds = [GLOBAL_STATE_SEED], bump = global_state.bump, has_one = admin_account)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+
+    // admin account
+    #[account(signer)]
+    pub admin_account: AccountInfo<'info>,
+
+    // referral state
+    #[account(mut)]
+    pub referral_state: ProgramAccount<'info, ReferralState>,
+}
+impl<'info> UpdateDepositCaps<'info> {
+    // caps in lamports, 0 = unlimited; lowering a cap under the deposited amount only
+    // blocks the next deposits
+    pub fn process(&mut self, max_total_deposit: u64, max_deposit_per_epoch: u64) -> ProgramResult {
+        self.referral_state.max_total_deposit = max_total_deposit;
+        self.referral_state.max_deposit_per_epoch = max_deposit_per_epoch;
+        msg!(
+            "deposit caps of {} set to total {} and per epoch {}",
+            self.referral_state.key(),
+            max_total_deposit,
+            max_deposit_per_epoch
+        );
+        Ok(())
+    }
+}
+
+//-----------------------------------------------------
+#[derive(Accounts)]
+pub struct UpdateOperationFeeCaps<'info> {
+    // global state
+    #[account(mut, seeds = [GLOBAL_STATE_SEED], bump = global_state.bump, has_one = admin_account)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+
+    // admin account
+    #[account(signer)]
+    pub admin_account: AccountInfo<'info>,
+}
+impl<'info> UpdateOperationFeeCaps<'info> {
+    // lowering a cap does not change the fees already set in the referral states
+    pub fn process(
+        &mut self,
+        max_operation_deposit_sol_fee: Option<u16>,
+        max_operation_deposit_stake_account_fee: Option<u16>,
+        max_operation_liquid_unstake_fee: Option<u16>,
+        max_operation_delayed_unstake_fee: Option<u16>,
+        max_operation_add_liquidity_fee: Option<u16>,
+        max_operation_remove_liquidity_fee: Option<u16>,
+    ) -> ProgramResult {
+        set_fee_cap_checked(
+            &mut self.global_state.max_operation_deposit_sol_fee,
+            max_operation_deposit_sol_fee,
+        )?;
+        set_fee_cap_checked(
+            &mut self.global_state.max_operation_deposit_stake_account_fee,
+            max_operation_deposit_stake_account_fee,
+        )?;
+        set_fee_cap_checked(
+            &mut self.global_state.max_operation_liquid_unstake_fee,
+            max_operation_liquid_unstake_fee,
+        )?;
+        set_fee_cap_checked(
+            &mut self.global_state.max_operation_delayed_unstake_fee,
+            max_operation_delayed_unstake_fee,
+        )?;
+        set_fee_cap_checked(
+            &mut self.global_state.max_operation_add_liquidity_fee,
+            max_operation_add_liquidity_fee,
+        )?;
+        set_fee_cap_checked(
+            &mut self.global_state.max_operation_remove_liquidity_fee,
+            max_operation_remove_liquidity_fee,
+        )?;
+        Ok(())
+    }
+}
+
+fn set_fee_cap_checked(
+    current_value: &mut u16,
+    new_value: Option<u16>,
+) -> std::result::Result<(), ReferralError> {
+    if let Some(new_cap) = new_value {
+        if new_cap > MAX_OPERATION_FEE_CAP_POINTS {
+            msg!(
+                "Operation fee cap {}bp is over maximal permitted {}bp",
+                new_cap,
+                MAX_OPERATION_FEE_CAP_POINTS
+            );
+            return Err(OperationFeeCapOverMax);
+        }
+        *current_value = new_cap;
+    }
+    Ok(())
+}
+
+//-----------------------------------------------------
+// sets the sub-partners sharing the mSOL operation fees,
+// their token accounts are passed in the same order as remaining accounts
+#[derive(Accounts)]
+pub struct UpdateFeeBeneficiaries<'info> {
+    // global state
+    #[account(seeds = [GLOBAL_STATE_SEED], bump = global_state.bump, has_one = admin_account)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+
+    // admin account
+    #[account(signer)]
+    pub admin_account: AccountInfo<'info>,
+
+    // referral state
+    #[account(mut)]
+    pub referral_state: ProgramAccount<'info, ReferralState>,
+}
+impl<'info> UpdateFeeBeneficiaries<'info> {
+    pub fn process(
+        &mut self,
+        fee_beneficiaries: Vec<FeeBeneficiary>,
+        beneficiary_accounts: &[AccountInfo<'info>],
+    ) -> ProgramResult {
+        let total_fee_share: u32 = fee_beneficiaries
+            .iter()
+            .map(|beneficiary| beneficiary.fee_share as u32)
+            .sum();
+        if fee_beneficiaries.len() > self.referral_state.fee_beneficiaries.len()
+            || fee_beneficiaries
+                .iter()
+                .any(|beneficiary| beneficiary.fee_share == 0)
+            || total_fee_share > 10_000
+        {
+            msg!(
+                "Up to {} fee beneficiaries with non-zero shares are permitted, shares sum up to {}bp",
+                self.referral_state.fee_beneficiaries.len(),
+                total_fee_share
+            );
+            return Err(InvalidFeeBeneficiaries.into());
+        }
+
+        // the beneficiary token accounts have to be mSOL token accounts
+        check_fee_beneficiary_accounts(&fee_beneficiaries, beneficiary_accounts)?;
+        for beneficiary_account in beneficiary_accounts {
+            let token_account: CpiAccount<TokenAccount> =
+                CpiAccount::try_from(beneficiary_account)?;
+            if token_account.mint != self.global_state.msol_mint_account {
+                msg!(
+                    "mint of fee beneficiary token account {} has to be same as global state mint account {}",
+                    beneficiary_account.key,
+                    self.global_state.msol_mint_account
+                );
+                return Err(InvalidPartnerAccountMint.into());
+            }
+        }
+
+        self.referral_state.fee_beneficiaries = Default::default();
+        for (entry, beneficiary) in self
+            .referral_state
+            .fee_beneficiaries
+            .iter_mut()
+            .zip(fee_beneficiaries)
+        {
+            *entry = beneficiary;
+        }
+        Ok(())
+    }
+}
+
+//-----------------------------------------------------
+#[derive(Accounts)]
+pub struct UpdateRewardCurve<'info> {
+    // global state
+    #[account(seeds = [GLOBAL_STATE_SEED], bump = global_state.bump, has_one = admin_account)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+
+    // admin account
+    #[account(signer)]
+    pub admin_account: AccountInfo<'info>,
+
+    // referral state
+    #[account(mut)]
+    pub referral_state: ProgramAccount<'info, ReferralState>,
+}
+impl<'info> UpdateRewardCurve<'info> {
+    pub fn process(&mut self, base_fee: u32, max_fee: u32, max_net_stake: u64) -> ProgramResult {
+        if base_fee > max_fee || max_fee > MAX_REWARD_FEE_POINTS {
+            msg!(
+                "Reward curve base fee {}bp and max fee {}bp must be base <= max <= {}bp",
+                base_fee,
+                max_fee,
+                MAX_REWARD_FEE_POINTS
+            );
+            return Err(RewardCurveFeeOutOfRange.into());
+        }
+        if max_net_stake == 0 {
+            return Err(RewardCurveZeroNetStake.into());
+        }
+
+        self.referral_state.base_fee = base_fee;
+        self.referral_state.max_fee = max_fee;
+        self.referral_state.max_net_stake = max_net_stake;
         Ok(())
     }
 }
@@ -344,7 +827,7 @@ pub struct AdminRecognizeDeposit<'info> {
     pub signer: AccountInfo<'info>,
 
     // global state, signer must be admin
-    #[account(constraint = *signer.key == global_state.admin_account)]
+    #[account(seeds = [GLOBAL_STATE_SEED], bump = global_state.bump, constraint = *signer.key == global_state.admin_account)]
     pub global_state: ProgramAccount<'info, GlobalState>,
 
     // referral state
@@ -355,12 +838,199 @@ pub struct AdminRecognizeDeposit<'info> {
 impl<'info> AdminRecognizeDeposit<'info> {
     pub fn process(&mut self, lamports: u64) -> ProgramResult {
         // only allow for stake-as-collateral mode
-        check_global_state_address(self.global_state.key())?; // double-check
         if self.referral_state.validator_vote_key.is_none() {
             return Err(OnlyAllowedForStakeAsCollateralPartner.into());
         };
-        self.referral_state.deposit_sol_amount += lamports;
-        self.referral_state.deposit_sol_operations += 1;
+        self.referral_state.record_deposit_sol(lamports, 0)?;
+        Ok(())
+    }
+}
+
+//-----------------------------------------------------
+// closes a settlement period: snapshots the accumulators and resets them
+#[derive(Accounts)]
+pub struct SettlePeriod<'info> {
+    // global state
+    #[account(seeds = [GLOBAL_STATE_SEED], bump = global_state.bump, has_one = admin_account)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+
+    // admin account
+    #[account(signer)]
+    pub admin_account: AccountInfo<'info>,
+
+    // referral state
+    #[account(mut)]
+    pub referral_state: ProgramAccount<'info, ReferralState>,
+
+    #[account(zero)] // must be created but empty, ready to be initialized
+    pub period_snapshot: ProgramAccount<'info, PeriodSnapshot>,
+
+    pub clock: Sysvar<'info, Clock>,
+}
+
+impl<'info> SettlePeriod<'info> {
+    pub fn process(&mut self) -> ProgramResult {
+        self.period_snapshot.record(
+            self.referral_state.key(),
+            &self.referral_state,
+            &self.clock,
+        )?;
+        msg!(
+            "referral {} settled at epoch {}, partner share {}",
+            self.referral_state.key(),
+            self.clock.epoch,
+            self.period_snapshot.liq_unstake_share_amount
+        );
+
+        self.referral_state.reset_accumulators();
+        Ok(())
+    }
+}
+
+//-----------------------------------------------------
+// retires a paused partner: writes the final snapshot and closes the referral account
+#[derive(Accounts)]
+pub struct CloseReferralAccount<'info> {
+    // global state
+    #[account(seeds = [GLOBAL_STATE_SEED], bump = global_state.bump, has_one = admin_account)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+
+    // admin account
+    #[account(signer)]
+    pub admin_account: AccountInfo<'info>,
+
+    // referral state, all operations must be paused before closing
+    #[account(
+        mut,
+        constraint = referral_state.pause & PAUSE_ALL_OPERATIONS != 0,
+        close = rent_destination
+    )]
+    pub referral_state: ProgramAccount<'info, ReferralState>,
+
+    #[account(zero)] // must be created but empty, ready to be initialized
+    pub period_snapshot: ProgramAccount<'info, PeriodSnapshot>,
+
+    // receives the referral state rent lamports
+    #[account(mut)]
+    pub rent_destination: AccountInfo<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+}
+
+impl<'info> CloseReferralAccount<'info> {
+    // require_settled: fail if the liquid-unstake fees were neither settled nor claimed
+    pub fn process(&mut self, require_settled: bool) -> ProgramResult {
+        if require_settled && self.referral_state.liq_unstake_msol_fees != 0 {
+            msg!(
+                "referral {} has {} mSOL of unsettled liquid-unstake fees",
+                self.referral_state.key(),
+                self.referral_state.liq_unstake_msol_fees
+            );
+            return Err(ReferralNotSettled.into());
+        }
+
+        self.period_snapshot.record(
+            self.referral_state.key(),
+            &self.referral_state,
+            &self.clock,
+        )?;
+
+        emit!(CloseReferralAccountEvent {
+            referral_state: self.referral_state.key(),
+            partner_account: self.referral_state.partner_account,
+            period_snapshot: self.period_snapshot.key(),
+            rent_destination: *self.rent_destination.key,
+            lamports: self.referral_state.to_account_info().lamports(),
+        });
+        // the account is closed by anchor on exit (close = rent_destination)
+        Ok(())
+    }
+}
+
+//-----------------------------------------------------
+// moves a v0.2 referral account (keypair account without version) into the partner PDA
+// with the current layout. The runtime can't reallocate accounts and the v0.2 account
+// is shorter than the current layout, so the data is copied to the PDA and the old
+// account is closed, its lamports go to the admin. Clients find the new address
+// from the partner wallet with ReferralState::find_address
+#[derive(Accounts)]
+#[instruction(bump: u8)]
+pub struct MigrateReferralState<'info> {
+    // global state
+    #[account(seeds = [GLOBAL_STATE_SEED], bump = global_state.bump, has_one = admin_account)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+
+    // admin account, pays for the new referral state account
+    #[account(mut, signer)]
+    pub admin_account: AccountInfo<'info>,
+
+    // v0.2 referral state, closed by the migration
+    #[account(mut, constraint = *old_referral_state.owner == crate::marinade_referral::ID)]
+    pub old_referral_state: AccountInfo<'info>,
+
+    // new referral state, PDA of the partner account
+    #[account(
+        init,
+        seeds = [REFERRAL_STATE_SEED, partner_account.key.as_ref()],
+        bump = bump,
+        payer = admin_account,
+        space = 8 + ReferralState::LEN
+    )]
+    pub referral_state: ProgramAccount<'info, ReferralState>,
+
+    // partner main account of the old referral state
+    pub partner_account: AccountInfo<'info>,
+
+    pub system_program: AccountInfo<'info>,
+}
+
+impl<'info> MigrateReferralState<'info> {
+    pub fn process(&mut self, bump: u8) -> ProgramResult {
+        {
+            let data = self.old_referral_state.try_borrow_data()?;
+            if data.len() < 8 || data[..8] != ReferralState::discriminator() {
+                return Err(NotMigratableReferralState.into());
+            }
+            // the v0.2 layout has no version, accounts of the current layout are longer
+            let len = data.len();
+            if len < 8 + ReferralStateV0_2::LEN || len >= 8 + ReferralState::LEN {
+                msg!("referral state of {} bytes can't be migrated", len);
+                return Err(NotMigratableReferralState.into());
+            }
+            let old_referral_state = ReferralStateV0_2::deserialize(&mut &data[8..])
+                .map_err(|_| NotMigratableReferralState)?;
+            self.referral_state
+                .migrate_from_v0_2(old_referral_state, bump);
+        }
+        if self.referral_state.partner_account != *self.partner_account.key {
+            msg!(
+                "partner account {} does not match {} of the migrated referral state",
+                self.partner_account.key,
+                self.referral_state.partner_account
+            );
+            return Err(MigrationPartnerMismatch.into());
+        }
+
+        close_account(&self.old_referral_state, &self.admin_account)?;
+        msg!(
+            "referral state {} migrated to {}",
+            self.old_referral_state.key,
+            self.referral_state.key()
+        );
         Ok(())
     }
 }
+
+// moves all lamports of a program account to the destination and clears its data
+fn close_account(account: &AccountInfo, destination: &AccountInfo) -> ProgramResult {
+    let destination_lamports = destination
+        .lamports()
+        .checked_add(account.lamports())
+        .ok_or(CalculationFailure)?;
+    **destination.try_borrow_mut_lamports()? = destination_lamports;
+    **account.try_borrow_mut_lamports()? = 0;
+    for byte in account.try_borrow_mut_data()?.iter_mut() {
+        *byte = 0;
+    }
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use anchor_lang::prelude::*;
+use marinade_finance::ticket_account::TicketAccountData;
+
+use marinade_onchain_helper::cpi_util;
+
+use super::common::check_marinade_state;
+use crate::constant::{GLOBAL_STATE_SEED, PAUSE_DELAYED_UNSTAKE, TICKET_RECORD_SEED};
+use crate::cpi_context_accounts::MarinadeClaim;
+use crate::error::ReferralError::*;
+use crate::states::{GlobalState, ReferralState, TicketRecord};
+
+//-----------------------------------------------------
+#[derive(Accounts)]
+pub struct Claim<'info> {
+    // this part is equivalent to marinade-finance claim instructions
+    // Marinade state, checked against the global state by process
+    #[account(mut)]
+    pub state: AccountInfo<'info>,
+    #[account(mut)]
+    pub reserve_pda: AccountInfo<'info>,
+    #[account(mut)]
+    pub ticket_account: AccountInfo<'info>,
+    #[account(mut)]
+    pub transfer_sol_to: AccountInfo<'info>,
+    pub clock: AccountInfo<'info>,
+    pub system_program: AccountInfo<'info>,
+
+    // accounts added are: Marinade main program ID, global state, referral_state & ticket record
+    #[account(address = marinade_finance::ID)]
+    pub marinade_finance_program: AccountInfo<'info>,
+    #[account(seeds = [GLOBAL_STATE_SEED], bump = global_state.bump)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+    #[account(mut, constraint = !referral_state.is_paused(PAUSE_DELAYED_UNSTAKE))]
+    pub referral_state: ProgramAccount<'info, ReferralState>,
+    // written by OrderUnstake, the ticket has to be claimed through the referral state it was
+    // ordered through; closed by the claim, its rent goes with the ticket lamports
+    #[account(
+        mut,
+        seeds = [TICKET_RECORD_SEED, ticket_account.key.as_ref()],
+        bump = ticket_record.bump,
+        has_one = referral_state,
+        close = transfer_sol_to
+    )]
+    pub ticket_record: ProgramAccount<'info, TicketRecord>,
+}
+
+impl<'info> Claim<'info> {
+    pub fn process(&mut self) -> ProgramResult {
+        check_marinade_state(&self.global_state, &self.state)?;
+
+        // We parse manually self.ticket_account to avoid making the IDL more complex by including TicketAccountData
+        let ticket: ProgramAccount<TicketAccountData> =
+            ProgramAccount::try_from(&self.marinade_finance_program.key(), &self.ticket_account)?;
+        let claimed_lamports = ticket.lamports_amount;
+
+        // prepare claim cpi
+        let cpi_ctx = self.into_claim_cpi_ctx();
+        let instruction_data = marinade_finance::instruction::Claim {};
+        // call Marinade
+        cpi_util::invoke_signed(cpi_ctx, instruction_data)?;
+        msg!(
+            "claimed {} lamports from ticket {}",
+            claimed_lamports,
+            self.ticket_account.key
+        );
+
+        // update accumulators
+        self.referral_state.record_claim(claimed_lamports)?;
+
+        Ok(())
+    }
+
+    pub fn into_claim_cpi_ctx(&self) -> CpiContext<'_, '_, '_, 'info, MarinadeClaim<'info>> {
+        let cpi_accounts = MarinadeClaim {
+            state: self.state.clone(),
+            reserve_pda: self.reserve_pda.clone(),
+            ticket_account: self.ticket_account.clone(),
+            transfer_sol_to: self.transfer_sol_to.clone(),
+            clock: self.clock.clone(),
+            system_program: self.system_program.clone(),
+        };
+
+        CpiContext::new(self.marinade_finance_program.clone(), cpi_accounts)
+    }
+}
+
+//-----------------------------------------------------
+// a ticket claimed directly through Marinade leaves its ticket record behind,
+// anyone can close the record once the ticket account is gone, the rent goes back to its payer
+#[derive(Accounts)]
+pub struct CloseTicketRecord<'info> {
+    pub ticket_account: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [TICKET_RECORD_SEED, ticket_account.key.as_ref()],
+        bump = ticket_record.bump,
+        has_one = rent_payer,
+        close = rent_payer
+    )]
+    pub ticket_record: ProgramAccount<'info, TicketRecord>,
+    #[account(mut)]
+    pub rent_payer: AccountInfo<'info>,
+}
+
+impl<'info> CloseTicketRecord<'info> {
+    pub fn process(&mut self) -> ProgramResult {
+        // Marinade claim empties the ticket account, it is gone at the end of that transaction
+        if self.ticket_account.lamports() > 0 {
+            msg!(
+                "Ticket account {} of ticket record {} still exists",
+                self.ticket_account.key,
+                self.ticket_record.to_account_info().key
+            );
+            return Err(TicketAccountNotClosed.into());
+        }
+        msg!(
+            "Ticket record {} closed, rent back to {}",
+            self.ticket_record.to_account_info().key,
+            self.rent_payer.key
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{transfer, TokenAccount, Transfer};
+
+use crate::constant::{GLOBAL_STATE_SEED, PAUSE_ALL_OPERATIONS, TREASURY_VAULT_AUTHORITY_SEED};
+use crate::error::ReferralError::*;
+use crate::marinade_referral::ID;
+use crate::states::{GlobalState, ReferralState};
+
+//-----------------------------------------------------
+// pays the partner share of the liquid-unstake treasury fees
+// from the mSOL treasury vault controlled by the program
+#[derive(Accounts)]
+pub struct ClaimReferralRewards<'info> {
+    // global state, mSOL mint reference
+    #[account(seeds = [GLOBAL_STATE_SEED], bump = global_state.bump)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+
+    // referral state
+    #[account(mut, constraint = !referral_state.is_paused(PAUSE_ALL_OPERATIONS))]
+    pub referral_state: ProgramAccount<'info, ReferralState>,
+
+    // mSOL treasury vault, token account owned by the treasury vault authority PDA
+    #[account(
+        mut,
+        constraint = treasury_vault.owner == *treasury_vault_authority.key
+            && treasury_vault.mint == global_state.msol_mint_account
+    )]
+    pub treasury_vault: CpiAccount<'info, TokenAccount>,
+    pub treasury_vault_authority: AccountInfo<'info>,
+
+    // partner mSOL beneficiary token account
+    #[account(mut, address = referral_state.msol_token_partner_account)]
+    pub msol_token_partner_account: AccountInfo<'info>,
+
+    pub token_program: AccountInfo<'info>,
+}
+
+impl<'info> ClaimReferralRewards<'info> {
+    pub fn process(&mut self) -> ProgramResult {
+        let (treasury_vault_authority, bump) =
+            Pubkey::find_program_address(&[TREASURY_VAULT_AUTHORITY_SEED], &ID);
+        if *self.treasury_vault_authority.key != treasury_vault_authority {
+            return Err(InvalidTreasuryVaultAuthority.into());
+        }
+
+        let share_amount = self.referral_state.get_liq_unstake_share_amount()?;
+        if share_amount > self.treasury_vault.amount {
+            msg!(
+                "Partner share {} is over the treasury vault balance {}",
+                share_amount,
+                self.treasury_vault.amount
+            );
+            return Err(InsufficientTreasuryVaultBalance.into());
+        }
+
+        if share_amount > 0 {
+            transfer(
+                CpiContext::new_with_signer(
+                    self.token_program.clone(),
+                    Transfer {
+                        from: self.treasury_vault.to_account_info(),
+                        to: self.msol_token_partner_account.clone(),
+                        authority: self.treasury_vault_authority.clone(),
+                    },
+                    &[&[TREASURY_VAULT_AUTHORITY_SEED, &[bump]]],
+                ),
+                share_amount,
+            )?;
+        }
+        msg!(
+            "Partner obtains {} mSOL as share of {} mSOL liquid-unstake treasury fees",
+            share_amount,
+            self.referral_state.liq_unstake_msol_fees
+        );
+
+        // the treasury fees are consumed by the payout
+        self.referral_state
+            .record_referral_rewards_claim(share_amount)
+    }
+}
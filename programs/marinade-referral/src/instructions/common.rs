@@ -1,44 +1,254 @@
-use anchor_lang::prelude::{msg, AccountInfo, CpiContext, ProgramError};
+use anchor_lang::prelude::{
+    msg, AccountDeserialize, AccountInfo, AccountSerialize, Clock, CpiContext, ProgramAccount,
+    ProgramError, ProgramResult, Pubkey, Rent,
+};
+use anchor_lang::Key;
 use anchor_spl::token::{transfer, Transfer};
 use marinade_finance::{calc::proportional, error::CommonError};
-use solana_program::program_pack::Pack;
+use solana_program::{
+    program::{invoke, invoke_signed},
+    program_option::COption,
+    program_pack::Pack,
+    system_instruction,
+    sysvar::Sysvar,
+};
 use std::ops::Deref;
 
-pub fn msol_balance<'info>(mint_to: &AccountInfo<'info>) -> Result<u64, ProgramError> {
-    Ok(spl_token::state::Account::unpack_from_slice(mint_to.try_borrow_data()?.deref())?.amount)
+use crate::constant::REFEREE_RECORD_SEED;
+use crate::error::ReferralError::*;
+use crate::marinade_referral::ID;
+use crate::states::{FeeBeneficiary, GlobalState, RefereeRecord, ReferralState};
+
+// the global emergency pause applies to all partners, reported apart from the partner pause
+// of the operation (one of the PAUSE_* bits)
+pub fn check_not_paused(
+    global_state: &GlobalState,
+    referral_state: &ReferralState,
+    operation: u8,
+) -> ProgramResult {
+    if global_state.paused {
+        return Err(GlobalPaused.into());
+    }
+    if referral_state.is_paused(operation) {
+        return Err(Paused.into());
+    }
+    Ok(())
+}
+
+// the wrapped Marinade state is the one set in the global state
+pub fn check_marinade_state(global_state: &GlobalState, state: &AccountInfo) -> ProgramResult {
+    if *state.key != global_state.marinade_state {
+        msg!(
+            "Marinade state {} is not the state {} of the global state",
+            state.key,
+            global_state.marinade_state
+        );
+        return Err(InvalidMarinadeState.into());
+    }
+    Ok(())
+}
+
+// parses a Marinade state and checks it mints the mSOL of the global state
+pub fn load_marinade_state<'info>(
+    state: &AccountInfo<'info>,
+    msol_mint: &Pubkey,
+) -> Result<ProgramAccount<'info, marinade_finance::State>, ProgramError> {
+    // We parse manually the state to avoid making the IDL more complex by including marinade_finance::State
+    let marinade_state: ProgramAccount<marinade_finance::State> =
+        ProgramAccount::try_from(&marinade_finance::ID, state)?;
+    if marinade_state.msol_mint != *msol_mint {
+        msg!(
+            "Marinade state {} mints {}, expected {}",
+            state.key,
+            marinade_state.msol_mint,
+            msol_mint
+        );
+        return Err(InvalidMarinadeState.into());
+    }
+    Ok(marinade_state)
+}
+
+// the partner mSOL account was checked when set, it could have been closed and reopened since
+pub fn check_partner_msol_account(
+    global_state: &GlobalState,
+    msol_token_partner_account: &AccountInfo,
+) -> ProgramResult {
+    if *msol_token_partner_account.owner != spl_token::ID {
+        msg!(
+            "msol token partner account {} is not a token account",
+            msol_token_partner_account.key
+        );
+        return Err(InvalidPartnerMsolAccount.into());
+    }
+    let token_account = spl_token::state::Account::unpack_from_slice(
+        msol_token_partner_account.try_borrow_data()?.deref(),
+    )?;
+    if token_account.mint != global_state.msol_mint_account {
+        msg!(
+            "mint of msol token partner account {} has to be same as global state mint account {}",
+            msol_token_partner_account.key,
+            global_state.msol_mint_account
+        );
+        return Err(InvalidPartnerAccountMint.into());
+    }
+    Ok(())
 }
 
-pub fn apply_fee(fee_basis_points: u8, amount: u64) -> Result<u64, CommonError> {
+// mint_to receives the minted mSOL and pays the operation fee signed by fee_authority,
+// checked before calling Marinade so a wrong account fails before the mint
+pub fn check_fee_source(
+    global_state: &GlobalState,
+    mint_to: &AccountInfo,
+    fee_authority: &AccountInfo,
+) -> ProgramResult {
+    check_token_fee_source(mint_to, &global_state.msol_mint_account, fee_authority)
+}
+
+// the fee source has to be a token account of mint, fee_authority its owner or delegate
+pub fn check_token_fee_source(
+    fee_source: &AccountInfo,
+    mint: &Pubkey,
+    fee_authority: &AccountInfo,
+) -> ProgramResult {
+    if *fee_source.owner != spl_token::ID {
+        msg!("fee source {} is not a token account", fee_source.key);
+        return Err(InvalidFeeSourceAccount.into());
+    }
+    let token_account =
+        spl_token::state::Account::unpack_from_slice(fee_source.try_borrow_data()?.deref())?;
+    if token_account.mint != *mint {
+        msg!(
+            "mint of fee source {} has to be {}, got {}",
+            fee_source.key,
+            mint,
+            token_account.mint
+        );
+        return Err(InvalidFeeSourceAccount.into());
+    }
+    if token_account.owner != *fee_authority.key
+        && token_account.delegate != COption::Some(*fee_authority.key)
+    {
+        msg!(
+            "fee authority {} is neither the owner {} nor the delegate of fee source {}",
+            fee_authority.key,
+            token_account.owner,
+            fee_source.key
+        );
+        return Err(InvalidFeeAuthority.into());
+    }
+    Ok(())
+}
+
+// a delegate signing the fee transfer has to be approved for the whole fee,
+// checked before calling Marinade so a short delegation fails before the operation
+pub fn check_delegated_fee(
+    fee_source: &AccountInfo,
+    fee_authority: &AccountInfo,
+    fee: u64,
+) -> ProgramResult {
+    let token_account =
+        spl_token::state::Account::unpack_from_slice(fee_source.try_borrow_data()?.deref())?;
+    if token_account.owner != *fee_authority.key && token_account.delegated_amount < fee {
+        msg!(
+            "fee {} is over the amount {} delegated to {} from fee source {}",
+            fee,
+            token_account.delegated_amount,
+            fee_authority.key,
+            fee_source.key
+        );
+        return Err(FeeOverDelegatedAmount.into());
+    }
+    Ok(())
+}
+
+pub fn token_balance<'info>(token_account: &AccountInfo<'info>) -> Result<u64, ProgramError> {
+    Ok(
+        spl_token::state::Account::unpack_from_slice(token_account.try_borrow_data()?.deref())?
+            .amount,
+    )
+}
+
+pub fn apply_fee(fee_basis_points: u16, amount: u64) -> Result<u64, CommonError> {
     // fee_basis_points, 10_000 = 100%
     proportional(amount, fee_basis_points as u64, 10_000u64)
 }
 
-pub fn transfer_msol_fee<'info>(
+// fee of the liquidity operations, taken in LP tokens and paid to the partner LP token account
+pub fn transfer_lp_fee<'info>(
+    whole_lp_amount: u64,
+    fee_basis_points: u16,
+    token_program: &AccountInfo<'info>,
+    transfer_from: &AccountInfo<'info>,
+    transfer_to: &AccountInfo<'info>,
+    transfer_authority: &AccountInfo<'info>,
+) -> Result<u64, ProgramError> {
+    let lp_fee_amount = apply_fee(fee_basis_points, whole_lp_amount)?;
+    transfer_token_amount(
+        lp_fee_amount,
+        token_program,
+        transfer_from,
+        transfer_to,
+        transfer_authority,
+    )?;
+    msg!(
+        "Partner obtains {} LP tokens as of fee: {}bp",
+        lp_fee_amount,
+        fee_basis_points
+    );
+    Ok(lp_fee_amount)
+}
+
+// the fee beneficiaries get their share of the fee, the partner (transfer_to) gets the rest
+// including the rounding remainder
+pub fn transfer_split_msol_fee<'info>(
     whole_msol_amount: u64,
-    fee_basis_points: u8,
+    fee_basis_points: u16,
+    fee_beneficiaries: &[FeeBeneficiary],
+    beneficiary_accounts: &[AccountInfo<'info>],
     token_program: &AccountInfo<'info>,
     transfer_from: &AccountInfo<'info>,
     transfer_to: &AccountInfo<'info>,
     transfer_authority: &AccountInfo<'info>,
 ) -> Result<u64, ProgramError> {
+    check_fee_beneficiary_accounts(fee_beneficiaries, beneficiary_accounts)?;
+
     if whole_msol_amount > 0 {
         let referral_msol_amount = apply_fee(fee_basis_points, whole_msol_amount)?;
-        if referral_msol_amount > 0 {
-            transfer(
-                CpiContext::new(
-                    token_program.clone(),
-                    Transfer {
-                        from: transfer_from.clone(),
-                        to: transfer_to.clone(),
-                        authority: transfer_authority.clone(),
-                    },
-                ),
+        let mut partner_msol_amount = referral_msol_amount;
+        for (beneficiary, beneficiary_account) in fee_beneficiaries.iter().zip(beneficiary_accounts)
+        {
+            let share_msol_amount = proportional(
                 referral_msol_amount,
+                beneficiary.fee_share as u64,
+                10_000u64,
+            )?;
+            partner_msol_amount = partner_msol_amount
+                .checked_sub(share_msol_amount)
+                .ok_or(CalculationFailure)?;
+            transfer_token_amount(
+                share_msol_amount,
+                token_program,
+                transfer_from,
+                beneficiary_account,
+                transfer_authority,
             )?;
+            msg!(
+                "Fee beneficiary {} obtains {} mSOL tokens as of share: {}bp",
+                beneficiary_account.key,
+                share_msol_amount,
+                beneficiary.fee_share
+            );
         }
+        transfer_token_amount(
+            partner_msol_amount,
+            token_program,
+            transfer_from,
+            transfer_to,
+            transfer_authority,
+        )?;
         msg!(
             "Partner obtains {} mSOL tokens as of fee: {}bp",
-            referral_msol_amount,
+            partner_msol_amount,
             fee_basis_points
         );
         Ok(referral_msol_amount)
@@ -50,3 +260,194 @@ pub fn transfer_msol_fee<'info>(
         Ok(0)
     }
 }
+
+// the remaining accounts have to be the token accounts of the fee beneficiaries, in order
+pub fn check_fee_beneficiary_accounts(
+    fee_beneficiaries: &[FeeBeneficiary],
+    beneficiary_accounts: &[AccountInfo],
+) -> ProgramResult {
+    if fee_beneficiaries.len() != beneficiary_accounts.len()
+        || fee_beneficiaries
+            .iter()
+            .zip(beneficiary_accounts)
+            .any(|(beneficiary, account)| beneficiary.msol_token_account != *account.key)
+    {
+        msg!(
+            "Expected the token accounts of {} fee beneficiaries as remaining accounts, got {}",
+            fee_beneficiaries.len(),
+            beneficiary_accounts.len()
+        );
+        return Err(FeeBeneficiaryAccountsMismatch.into());
+    }
+    Ok(())
+}
+
+fn transfer_token_amount<'info>(
+    amount: u64,
+    token_program: &AccountInfo<'info>,
+    transfer_from: &AccountInfo<'info>,
+    transfer_to: &AccountInfo<'info>,
+    transfer_authority: &AccountInfo<'info>,
+) -> ProgramResult {
+    if amount > 0 {
+        transfer(
+            CpiContext::new(
+                token_program.clone(),
+                Transfer {
+                    from: transfer_from.clone(),
+                    to: transfer_to.clone(),
+                    authority: transfer_authority.clone(),
+                },
+            ),
+            amount,
+        )?;
+    }
+    Ok(())
+}
+
+// remaining accounts of the mSOL operations, in this order:
+// - the token accounts of the fee beneficiaries
+// - optionally the referee record of the user, the program id in its place skips the record
+// - optionally a signer of the operation: the fee authority of the deposits
+//   or the referee record payer of the liquid-unstake
+pub struct RemainingAccounts<'a, 'info> {
+    pub beneficiary_accounts: &'a [AccountInfo<'info>],
+    pub referee_record: Option<&'a AccountInfo<'info>>,
+    pub signer: Option<&'a AccountInfo<'info>>,
+}
+
+pub fn split_remaining_accounts<'a, 'info>(
+    fee_beneficiaries: &[FeeBeneficiary],
+    remaining_accounts: &'a [AccountInfo<'info>],
+) -> Result<RemainingAccounts<'a, 'info>, ProgramError> {
+    // missing beneficiary accounts are reported by check_fee_beneficiary_accounts
+    let (beneficiary_accounts, other_accounts) =
+        remaining_accounts.split_at(fee_beneficiaries.len().min(remaining_accounts.len()));
+    let (referee_record, signer) = match other_accounts {
+        [] => (None, None),
+        [referee_record] => (Some(referee_record), None),
+        [referee_record, signer] => (Some(referee_record), Some(signer)),
+        _ => {
+            msg!(
+                "Expected at most 2 accounts after the {} fee beneficiary accounts, got {}",
+                fee_beneficiaries.len(),
+                other_accounts.len()
+            );
+            return Err(FeeBeneficiaryAccountsMismatch.into());
+        }
+    };
+    if let Some(signer) = signer {
+        if !signer.is_signer {
+            msg!("Trailing account {} has to be a signer", signer.key);
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+    }
+    Ok(RemainingAccounts {
+        beneficiary_accounts,
+        referee_record: referee_record.filter(|referee_record| *referee_record.key != ID),
+        signer,
+    })
+}
+
+// reads the referee record of the user, creating it at the first operation
+// (the payer has to be a writable signer then)
+pub fn load_referee_record<'info>(
+    referee_record: &AccountInfo<'info>,
+    referral_state: &mut ProgramAccount<'info, ReferralState>,
+    user: &Pubkey,
+    payer: Option<&AccountInfo<'info>>,
+    system_program: &AccountInfo<'info>,
+) -> Result<RefereeRecord, ProgramError> {
+    let referral_state_key = referral_state.key();
+    let (referee_record_address, bump) = RefereeRecord::find_address(&referral_state_key, user);
+    if *referee_record.key != referee_record_address {
+        msg!(
+            "Referee record {} has to be {} for user {}",
+            referee_record.key,
+            referee_record_address,
+            user
+        );
+        return Err(InvalidRefereeRecord.into());
+    }
+    if *referee_record.owner == ID {
+        return RefereeRecord::try_deserialize(&mut &referee_record.try_borrow_data()?[..]);
+    }
+
+    // first operation of the user through the referral account
+    let payer = match payer {
+        Some(payer) => payer,
+        None => {
+            msg!(
+                "Referee record {} has to be created, no payer given",
+                referee_record.key
+            );
+            return Err(InvalidRefereeRecord.into());
+        }
+    };
+    let space = 8 + RefereeRecord::LEN;
+    let rent_lamports = Rent::get()?.minimum_balance(space);
+    let referee_record_seeds: &[&[u8]] = &[
+        REFEREE_RECORD_SEED,
+        referral_state_key.as_ref(),
+        user.as_ref(),
+        &[bump],
+    ];
+    if referee_record.lamports() == 0 {
+        invoke_signed(
+            &system_instruction::create_account(
+                payer.key,
+                referee_record.key,
+                rent_lamports,
+                space as u64,
+                &ID,
+            ),
+            &[
+                payer.clone(),
+                referee_record.clone(),
+                system_program.clone(),
+            ],
+            &[referee_record_seeds],
+        )?;
+    } else {
+        // anyone can send lamports to the address beforehand, create_account fails then
+        let top_up_lamports = rent_lamports.saturating_sub(referee_record.lamports());
+        if top_up_lamports > 0 {
+            invoke(
+                &system_instruction::transfer(payer.key, referee_record.key, top_up_lamports),
+                &[
+                    payer.clone(),
+                    referee_record.clone(),
+                    system_program.clone(),
+                ],
+            )?;
+        }
+        invoke_signed(
+            &system_instruction::allocate(referee_record.key, space as u64),
+            &[referee_record.clone(), system_program.clone()],
+            &[referee_record_seeds],
+        )?;
+        invoke_signed(
+            &system_instruction::assign(referee_record.key, &ID),
+            &[referee_record.clone(), system_program.clone()],
+            &[referee_record_seeds],
+        )?;
+    }
+    referral_state.record_new_referee();
+    msg!("Referee record {} created", referee_record.key);
+    Ok(RefereeRecord {
+        referral_state: referral_state_key,
+        user: *user,
+        first_seen_slot: Clock::get()?.slot,
+        bump,
+        ..Default::default()
+    })
+}
+
+pub fn store_referee_record(
+    referee_record: &AccountInfo,
+    referee_record_data: &RefereeRecord,
+) -> ProgramResult {
+    let mut data = referee_record.try_borrow_mut_data()?;
+    let mut writer: &mut [u8] = &mut data[..];
+    referee_record_data.try_serialize(&mut writer)
+}
@@ -2,17 +2,24 @@ use anchor_lang::prelude::*;
 
 use marinade_onchain_helper::{cpi_context_accounts::MarinadeDeposit, cpi_util};
 
-use super::common::{msol_balance, transfer_msol_fee};
+use super::common::{
+    apply_fee, check_delegated_fee, check_fee_source, check_marinade_state, check_not_paused,
+    check_partner_msol_account, load_marinade_state, load_referee_record, split_remaining_accounts,
+    store_referee_record, token_balance, transfer_split_msol_fee,
+};
+use crate::constant::{GLOBAL_STATE_SEED, PAUSE_DEPOSIT_SOL};
 use crate::error::ReferralError::*;
-use crate::states::ReferralState;
+use crate::events::DepositEvent;
+use crate::states::{GlobalState, ReferralState};
 
 //-----------------------------------------------------
 #[derive(Accounts)]
 pub struct Deposit<'info> {
     // this part is equivalent to marinade-finance deposit instructions
+    // Marinade state, checked against the global state by process
     #[account(mut)]
     pub state: AccountInfo<'info>, // marinade state
-    #[account(mut)]
+    #[account(mut, address = global_state.msol_mint_account)]
     pub msol_mint: AccountInfo<'info>,
     #[account(mut)]
     pub liq_pool_sol_leg_pda: AccountInfo<'info>,
@@ -29,55 +36,124 @@ pub struct Deposit<'info> {
     pub system_program: AccountInfo<'info>,
     pub token_program: AccountInfo<'info>,
 
-    // accounts added are: Marinade main program ID, referral_state, partner token account
+    // accounts added are: Marinade main program ID, global state, referral_state, partner token account
     #[account(address = marinade_finance::ID)]
     pub marinade_finance_program: AccountInfo<'info>,
-    #[account(mut, constraint = !referral_state.pause)]
+    #[account(seeds = [GLOBAL_STATE_SEED], bump = global_state.bump)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+    #[account(mut)]
     pub referral_state: ProgramAccount<'info, ReferralState>,
     #[account(mut, address = referral_state.msol_token_partner_account)]
     pub msol_token_partner_account: AccountInfo<'info>,
+    // token accounts of the referral fee beneficiaries follow as remaining accounts,
+    // then optionally the referee record of the user (or the program id to skip it)
+    // and the fee authority (signer owning or delegated mint_to, transfer_from when not given)
 }
 
 impl<'info> Deposit<'info> {
-    pub fn process(&mut self, lamports: u64) -> ProgramResult {
+    // min_msol_out = 0 means no slippage check
+    pub fn process(
+        &mut self,
+        lamports: u64,
+        min_msol_out: u64,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> ProgramResult {
         msg!("enter Deposit::process {}", lamports);
 
+        check_marinade_state(&self.global_state, &self.state)?;
+        check_not_paused(&self.global_state, &self.referral_state, PAUSE_DEPOSIT_SOL)?;
+        check_partner_msol_account(&self.global_state, &self.msol_token_partner_account)?;
+        let fee_beneficiaries = self.referral_state.active_fee_beneficiaries();
+        let remaining_accounts = split_remaining_accounts(&fee_beneficiaries, remaining_accounts)?;
+        let fee_authority = remaining_accounts
+            .signer
+            .unwrap_or(&self.transfer_from)
+            .clone();
+        check_fee_source(&self.global_state, &self.mint_to, &fee_authority)?;
+        // the mSOL minted for the lamports, the fee on it covers the fee on the actual mint
+        let marinade_state =
+            load_marinade_state(&self.state, &self.global_state.msol_mint_account)?;
+        let expected_fee = apply_fee(
+            self.referral_state.operation_deposit_sol_fee,
+            marinade_state.calc_msol_from_lamports(lamports)?,
+        )?;
+        check_delegated_fee(&self.mint_to, &fee_authority, expected_fee)?;
+
         // disallow for stake-as-collateral mode
         if self.referral_state.validator_vote_key.is_some() {
             return Err(NotAllowedForStakeAsCollateralPartner.into());
         };
 
+        self.referral_state
+            .record_capped_deposit(lamports, Clock::get()?.epoch)?;
+
         let cpi_ctx = self.into_marinade_deposit_cpi_ctx();
         let data = marinade_finance::instruction::Deposit { lamports };
 
         // msol balance before deposit call
-        let msol_before = msol_balance(&self.mint_to)?;
+        let msol_before = token_balance(&self.mint_to)?;
 
         // call Marinade
         cpi_util::invoke_signed(cpi_ctx, data)?;
 
         // msol balance after deposit call
-        let msol_after = msol_balance(&self.mint_to)?;
+        let msol_after = token_balance(&self.mint_to)?;
         // deposit fee is transferred to referral token account
-        let minted_msol = msol_after - msol_before;
+        let minted_msol = msol_after
+            .checked_sub(msol_before)
+            .ok_or(CalculationFailure)?;
         msg!(
             "minted msol {} after depositing {} lamports",
             minted_msol,
             lamports
         );
-        let operation_fee = transfer_msol_fee(
+        let operation_fee = transfer_split_msol_fee(
             minted_msol,
             self.referral_state.operation_deposit_sol_fee,
+            &fee_beneficiaries,
+            remaining_accounts.beneficiary_accounts,
             &self.token_program,
             &self.mint_to,
             &self.msol_token_partner_account,
-            &self.transfer_from,
+            &fee_authority,
         )?;
+        let user_msol = minted_msol
+            .checked_sub(operation_fee)
+            .ok_or(CalculationFailure)?;
+        if user_msol < min_msol_out {
+            msg!(
+                "user receives {} msol, less than required minimum {}",
+                user_msol,
+                min_msol_out
+            );
+            return Err(SlippageExceeded.into());
+        }
 
         // update accumulators
-        self.referral_state.deposit_sol_amount += lamports;
-        self.referral_state.deposit_sol_operations += 1;
-        self.referral_state.accum_deposit_sol_fee += operation_fee;
+        self.referral_state
+            .record_deposit_sol(lamports, operation_fee)?;
+        if let Some(referee_record) = remaining_accounts.referee_record {
+            let mut referee_record_data = load_referee_record(
+                referee_record,
+                &mut self.referral_state,
+                self.transfer_from.key,
+                Some(&self.transfer_from),
+                &self.system_program,
+            )?;
+            referee_record_data.record_deposit_sol(lamports, operation_fee)?;
+            store_referee_record(referee_record, &referee_record_data)?;
+        }
+
+        emit!(DepositEvent {
+            referral_state: self.referral_state.key(),
+            user: *self.transfer_from.key,
+            lamports,
+            minted_msol,
+            operation_fee,
+            deposit_sol_amount: self.referral_state.deposit_sol_amount,
+            deposit_sol_operations: self.referral_state.deposit_sol_operations,
+            accum_deposit_sol_fee: self.referral_state.accum_deposit_sol_fee,
+        });
         Ok(())
     }
 
@@ -1,15 +1,23 @@
 use anchor_lang::prelude::*;
-use marinade_finance::stake_wrapper::StakeWrapper;
+use anchor_lang::solana_program::{program::invoke, stake};
+use marinade_finance::{calc::proportional, stake_wrapper::StakeWrapper};
 
-use super::common::{msol_balance, transfer_msol_fee};
+use super::common::{
+    apply_fee, check_delegated_fee, check_fee_source, check_marinade_state, check_not_paused,
+    check_partner_msol_account, load_marinade_state, load_referee_record, split_remaining_accounts,
+    store_referee_record, token_balance, transfer_split_msol_fee,
+};
+use crate::constant::{GLOBAL_STATE_SEED, PAUSE_DEPOSIT_STAKE_ACCOUNT};
 use crate::error::ReferralError::*;
-use crate::states::ReferralState;
+use crate::events::DepositStakeAccountEvent;
+use crate::states::{GlobalState, ReferralState};
 use marinade_onchain_helper::{cpi_context_accounts::MarinadeDepositStakeAccount, cpi_util};
 
 //-----------------------------------------------------
 #[derive(Accounts)]
 pub struct DepositStakeAccount<'info> {
     // this part is equivalent to marinade-finance deposit-stake-account instructions
+    // Marinade state, checked against the global state by process
     #[account(mut)]
     pub state: AccountInfo<'info>,
     #[account(mut)]
@@ -24,7 +32,7 @@ pub struct DepositStakeAccount<'info> {
     pub duplication_flag: AccountInfo<'info>,
     #[account(mut, signer)]
     pub rent_payer: AccountInfo<'info>,
-    #[account(mut)]
+    #[account(mut, address = global_state.msol_mint_account)]
     pub msol_mint: AccountInfo<'info>,
     #[account(mut)]
     pub mint_to: AccountInfo<'info>,
@@ -35,20 +43,121 @@ pub struct DepositStakeAccount<'info> {
     pub token_program: AccountInfo<'info>,
     pub stake_program: AccountInfo<'info>,
 
-    // accounts added are: Marinade main program ID, referral_state, partner token account
+    // accounts added are: Marinade main program ID, global state, referral_state, partner token account
     #[account(address = marinade_finance::ID)]
     pub marinade_finance_program: AccountInfo<'info>,
-    #[account(mut, constraint = !referral_state.pause)]
+    #[account(seeds = [GLOBAL_STATE_SEED], bump = global_state.bump)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+    #[account(mut)]
     pub referral_state: ProgramAccount<'info, ReferralState>,
     #[account(mut, address = referral_state.msol_token_partner_account)]
     pub msol_token_partner_account: AccountInfo<'info>,
+    // token accounts of the referral fee beneficiaries follow as remaining accounts,
+    // then optionally the referee record of the user (or the program id to skip it)
+    // and the fee authority (signer owning or delegated mint_to, stake_authority when not given)
+}
+
+//-----------------------------------------------------
+// deposit of a stake-as-collateral partner: keep_self_stake_pct of the stake is split off
+// to split_stake_account, which stays delegated to the partner validator,
+// and only the remainder goes to Marinade
+#[derive(Accounts)]
+pub struct DepositStakeAccountAsCollateral<'info> {
+    pub deposit_stake_account: DepositStakeAccount<'info>,
+    // new stake account receiving the kept stake, with the authorities of the deposited one
+    // (empty system account, signer to be allocated to the stake program)
+    #[account(mut, signer)]
+    pub split_stake_account: AccountInfo<'info>,
+}
+
+impl<'info> DepositStakeAccountAsCollateral<'info> {
+    pub fn process(
+        &mut self,
+        validator_index: u32,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> ProgramResult {
+        let deposit = &mut self.deposit_stake_account;
+        check_marinade_state(&deposit.global_state, &deposit.state)?;
+        if deposit.referral_state.validator_vote_key.is_none() {
+            return Err(OnlyAllowedForStakeAsCollateralPartner.into());
+        }
+        check_not_paused(
+            &deposit.global_state,
+            &deposit.referral_state,
+            PAUSE_DEPOSIT_STAKE_ACCOUNT,
+        )?;
+
+        let stake = deposit.delegated_stake(&deposit.stake_account)?;
+        let split_lamports = proportional(
+            stake,
+            deposit.referral_state.keep_self_stake_pct as u64,
+            100,
+        )?;
+        // allocate, assign and split, the stake authority (partner) signed this transaction
+        for instruction in stake::instruction::split(
+            deposit.stake_account.key,
+            deposit.stake_authority.key,
+            split_lamports,
+            self.split_stake_account.key,
+        )
+        .iter()
+        {
+            invoke(
+                instruction,
+                &[
+                    deposit.stake_account.clone(),
+                    self.split_stake_account.clone(),
+                    deposit.stake_authority.clone(),
+                    deposit.system_program.clone(),
+                    deposit.stake_program.clone(),
+                ],
+            )?;
+        }
+        // the rent reserve of the split account is taken from the split lamports
+        // unless it was funded beforehand, record what is actually kept delegated
+        let kept_stake = deposit.delegated_stake(&self.split_stake_account)?;
+        msg!(
+            "{} of stake {} kept in {}",
+            kept_stake,
+            deposit.stake_account.key,
+            self.split_stake_account.key
+        );
+
+        deposit.deposit(validator_index, kept_stake, remaining_accounts)
+    }
 }
 
 impl<'info> DepositStakeAccount<'info> {
-    pub fn process(&mut self, validator_index: u32) -> ProgramResult {
-        // compute deposit stake account amount
-        // We are parsing self.stake_account manually to avoid making the IDL more complex by including StakeWrapper
-        let stake_account: CpiAccount<StakeWrapper> = CpiAccount::try_from(&self.stake_account)?;
+    pub fn process(
+        &mut self,
+        validator_index: u32,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> ProgramResult {
+        check_marinade_state(&self.global_state, &self.state)?;
+        check_not_paused(
+            &self.global_state,
+            &self.referral_state,
+            PAUSE_DEPOSIT_STAKE_ACCOUNT,
+        )?;
+
+        // a stake-as-collateral partner keeping part of the stake needs the split stake account
+        if self.referral_state.validator_vote_key.is_some()
+            && self.referral_state.keep_self_stake_pct > 0
+        {
+            msg!(
+                "{}% of the stake is kept by the partner, use deposit_stake_account_as_collateral",
+                self.referral_state.keep_self_stake_pct
+            );
+            return Err(KeepSelfStakeSplitRequired.into());
+        }
+        self.deposit(validator_index, 0, remaining_accounts)
+    }
+
+    // delegated stake of the deposited stake account (or of its split part),
+    // checking the partner validator and authority in stake-as-collateral mode
+    fn delegated_stake(&self, stake_account: &AccountInfo<'info>) -> Result<u64, ProgramError> {
+        // We are parsing the stake account manually to avoid making the IDL more complex by including StakeWrapper
+        let stake_account: CpiAccount<StakeWrapper> = CpiAccount::try_from(stake_account)?;
         let delegation = stake_account.delegation().ok_or_else(|| {
             msg!(
                 "Deposited stake {} must be delegated",
@@ -67,9 +176,40 @@ impl<'info> DepositStakeAccount<'info> {
                 return Err(StakeAccountAuthMustBePartnerAccount.into());
             }
         }
+        Ok(delegation.stake)
+    }
+
+    // kept_stake: stake split off to the partner before the deposit
+    fn deposit(
+        &mut self,
+        validator_index: u32,
+        kept_stake: u64,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> ProgramResult {
+        check_partner_msol_account(&self.global_state, &self.msol_token_partner_account)?;
+        let fee_beneficiaries = self.referral_state.active_fee_beneficiaries();
+        let remaining_accounts = split_remaining_accounts(&fee_beneficiaries, remaining_accounts)?;
+        let fee_authority = remaining_accounts
+            .signer
+            .unwrap_or(&self.stake_authority)
+            .clone();
+        check_fee_source(&self.global_state, &self.mint_to, &fee_authority)?;
+
+        // compute deposit stake account amount
+        let stake = self.delegated_stake(&self.stake_account)?;
+        // the mSOL minted for the stake, the fee on it covers the fee on the actual mint
+        let marinade_state =
+            load_marinade_state(&self.state, &self.global_state.msol_mint_account)?;
+        let expected_fee = apply_fee(
+            self.referral_state.operation_deposit_stake_account_fee,
+            marinade_state.calc_msol_from_lamports(stake)?,
+        )?;
+        check_delegated_fee(&self.mint_to, &fee_authority, expected_fee)?;
+        self.referral_state
+            .record_capped_deposit(stake, Clock::get()?.epoch)?;
 
         // msol balance before call
-        let msol_before = msol_balance(&self.mint_to)?;
+        let msol_before = token_balance(&self.mint_to)?;
 
         // prepare deposit-stake-account cpi
         let cpi_ctx = self.into_deposit_stake_account_cpi_ctx();
@@ -79,29 +219,58 @@ impl<'info> DepositStakeAccount<'info> {
         cpi_util::invoke_signed(cpi_ctx, instruction_data)?;
 
         // msol balance after call
-        let msol_after = msol_balance(&self.mint_to)?;
+        let msol_after = token_balance(&self.mint_to)?;
         // deposit fee is transferred to referral token account
-        let minted_msol = msol_after - msol_before;
+        let minted_msol = msol_after
+            .checked_sub(msol_before)
+            .ok_or(CalculationFailure)?;
         msg!(
             "minted msol {} after depositing stake account {}",
             minted_msol,
-            stake_account.key()
+            self.stake_account.key
         );
-        let operation_fee = transfer_msol_fee(
+        let operation_fee = transfer_split_msol_fee(
             minted_msol,
             self.referral_state.operation_deposit_stake_account_fee,
+            &fee_beneficiaries,
+            remaining_accounts.beneficiary_accounts,
             &self.token_program,
             &self.mint_to,
             &self.msol_token_partner_account,
-            // Note: self.stake_authority is in reality withdraw_auth (Stake account owner)
-            // we're assuming it's the same owner of the destination mSOL token account
-            &self.stake_authority,
+            &fee_authority,
         )?;
 
         // accumulate
-        self.referral_state.deposit_stake_account_amount += delegation.stake;
-        self.referral_state.deposit_stake_account_operations += 1;
-        self.referral_state.accum_deposit_stake_account_fee += operation_fee;
+        self.referral_state
+            .record_deposit_stake_account(stake, operation_fee)?;
+        if kept_stake > 0 {
+            self.referral_state.record_keep_self_stake(kept_stake)?;
+        }
+        if let Some(referee_record) = remaining_accounts.referee_record {
+            let mut referee_record_data = load_referee_record(
+                referee_record,
+                &mut self.referral_state,
+                self.stake_authority.key,
+                Some(&self.rent_payer),
+                &self.system_program,
+            )?;
+            referee_record_data.record_deposit_stake_account(stake, operation_fee)?;
+            store_referee_record(referee_record, &referee_record_data)?;
+        }
+
+        emit!(DepositStakeAccountEvent {
+            referral_state: self.referral_state.key(),
+            user: *self.stake_authority.key,
+            stake_account: *self.stake_account.key,
+            lamports: stake,
+            kept_stake,
+            minted_msol,
+            operation_fee,
+            deposit_stake_account_amount: self.referral_state.deposit_stake_account_amount,
+            deposit_stake_account_operations: self.referral_state.deposit_stake_account_operations,
+            accum_deposit_stake_account_fee: self.referral_state.accum_deposit_stake_account_fee,
+            keep_self_stake_amount: self.referral_state.keep_self_stake_amount,
+        });
         Ok(())
     }
 
@@ -1,18 +1,25 @@
 use anchor_lang::prelude::*;
 
-use super::common::transfer_msol_fee;
+use super::common::{
+    apply_fee, check_delegated_fee, check_marinade_state, check_not_paused,
+    check_partner_msol_account, check_token_fee_source, load_referee_record,
+    split_remaining_accounts, store_referee_record, transfer_split_msol_fee,
+};
 use marinade_onchain_helper::{cpi_context_accounts::MarinadeLiquidUnstake, cpi_util};
 
+use crate::constant::{GLOBAL_STATE_SEED, PAUSE_LIQUID_UNSTAKE};
 use crate::error::ReferralError::*;
-use crate::states::ReferralState;
+use crate::events::LiquidUnstakeEvent;
+use crate::states::{GlobalState, ReferralState};
 
 //-----------------------------------------------------
 #[derive(Accounts)]
 pub struct LiquidUnstake<'info> {
     // this part is equivalent to marinade-finance liquid-unstake instructions
+    // Marinade state, checked against the global state by process
     #[account(mut)]
     pub state: AccountInfo<'info>,
-    #[account(mut)]
+    #[account(mut, address = global_state.msol_mint_account)]
     pub msol_mint: AccountInfo<'info>,
     #[account(mut)]
     pub liq_pool_sol_leg_pda: AccountInfo<'info>,
@@ -29,19 +36,38 @@ pub struct LiquidUnstake<'info> {
     pub system_program: AccountInfo<'info>,
     pub token_program: AccountInfo<'info>,
 
-    // accounts added are: Marinade main program ID & referral_state
+    // accounts added are: Marinade main program ID, global state & referral_state
     #[account(address = marinade_finance::ID)]
     pub marinade_finance_program: AccountInfo<'info>,
-    #[account(mut, constraint = !referral_state.pause)]
+    #[account(seeds = [GLOBAL_STATE_SEED], bump = global_state.bump)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+    #[account(mut)]
     pub referral_state: ProgramAccount<'info, ReferralState>,
     #[account(mut, address = referral_state.msol_token_partner_account)]
     pub msol_token_partner_account: AccountInfo<'info>,
+    // token accounts of the referral fee beneficiaries follow as remaining accounts,
+    // then optionally the referee record of the user (or the program id to skip it)
+    // and the writable signer paying the record rent when it has to be created
 }
 
 impl<'info> LiquidUnstake<'info> {
-    pub fn process(&mut self, msol_amount: u64) -> ProgramResult {
+    // min_lamports_out = 0 means no slippage check
+    pub fn process(
+        &mut self,
+        msol_amount: u64,
+        min_lamports_out: u64,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> ProgramResult {
         // accumulate treasury fees for the liquid-unstake
 
+        check_marinade_state(&self.global_state, &self.state)?;
+        check_not_paused(
+            &self.global_state,
+            &self.referral_state,
+            PAUSE_LIQUID_UNSTAKE,
+        )?;
+        check_partner_msol_account(&self.global_state, &self.msol_token_partner_account)?;
+
         // disallow for stake-as-collateral mode
         if self.referral_state.validator_vote_key.is_some() {
             return Err(NotAllowedForStakeAsCollateralPartner.into());
@@ -56,15 +82,34 @@ impl<'info> LiquidUnstake<'info> {
             .saturating_sub(marinade_state.rent_exempt_for_token_acc);
 
         // fee for liquid unstake operation
-        let operation_fee = transfer_msol_fee(
+        let fee_beneficiaries = self.referral_state.active_fee_beneficiaries();
+        let remaining_accounts = split_remaining_accounts(&fee_beneficiaries, remaining_accounts)?;
+        check_token_fee_source(
+            &self.get_msol_from,
+            &self.global_state.msol_mint_account,
+            &self.get_msol_from_authority,
+        )?;
+        check_delegated_fee(
+            &self.get_msol_from,
+            &self.get_msol_from_authority,
+            apply_fee(
+                self.referral_state.operation_liquid_unstake_fee,
+                msol_amount,
+            )?,
+        )?;
+        let operation_fee = transfer_split_msol_fee(
             msol_amount,
             self.referral_state.operation_liquid_unstake_fee,
+            &fee_beneficiaries,
+            remaining_accounts.beneficiary_accounts,
             &self.token_program,
             &self.get_msol_from,
             &self.msol_token_partner_account,
             &self.get_msol_from_authority,
         )?;
-        let msol_amount_fee_deducted = msol_amount - operation_fee;
+        let msol_amount_fee_deducted = msol_amount
+            .checked_sub(operation_fee)
+            .ok_or(CalculationFailure)?;
 
         // fee is computed based on the liquidity *after* the user takes the sol
         let user_remove_lamports =
@@ -90,6 +135,21 @@ impl<'info> LiquidUnstake<'info> {
         };
         msg!("treasury_msol_cut {}", treasury_msol_cut);
 
+        // lamports the user gets once the liquid-unstake fee is paid
+        let user_receive_lamports = marinade_state.calc_lamports_from_msol_amount(
+            msol_amount_fee_deducted
+                .checked_sub(msol_fee)
+                .ok_or(CalculationFailure)?,
+        )?;
+        if user_receive_lamports < min_lamports_out {
+            msg!(
+                "user receives {} lamports, less than required minimum {}",
+                user_receive_lamports,
+                min_lamports_out
+            );
+            return Err(SlippageExceeded.into());
+        }
+
         // prepare liquid-unstake cpi
         let cpi_ctx = self.into_liquid_unstake_cpi_ctx();
         let instruction_data = marinade_finance::instruction::LiquidUnstake {
@@ -99,11 +159,37 @@ impl<'info> LiquidUnstake<'info> {
         cpi_util::invoke_signed(cpi_ctx, instruction_data)?;
 
         // update accumulators
-        self.referral_state.liq_unstake_msol_fees += treasury_msol_cut;
-        self.referral_state.liq_unstake_msol_amount += msol_amount_fee_deducted;
-        self.referral_state.liq_unstake_sol_amount += user_remove_lamports;
-        self.referral_state.liq_unstake_operations += 1;
-        self.referral_state.accum_liquid_unstake_fee += operation_fee;
+        self.referral_state.record_liquid_unstake(
+            msol_amount_fee_deducted,
+            user_remove_lamports,
+            treasury_msol_cut,
+            operation_fee,
+        )?;
+        if let Some(referee_record) = remaining_accounts.referee_record {
+            let mut referee_record_data = load_referee_record(
+                referee_record,
+                &mut self.referral_state,
+                self.get_msol_from_authority.key,
+                remaining_accounts.signer,
+                &self.system_program,
+            )?;
+            referee_record_data.record_liquid_unstake(user_remove_lamports, operation_fee)?;
+            store_referee_record(referee_record, &referee_record_data)?;
+        }
+
+        emit!(LiquidUnstakeEvent {
+            referral_state: self.referral_state.key(),
+            user: *self.get_msol_from_authority.key,
+            msol_amount: msol_amount_fee_deducted,
+            lamports: user_remove_lamports,
+            operation_fee,
+            treasury_msol_cut,
+            liq_unstake_msol_amount: self.referral_state.liq_unstake_msol_amount,
+            liq_unstake_sol_amount: self.referral_state.liq_unstake_sol_amount,
+            liq_unstake_operations: self.referral_state.liq_unstake_operations,
+            liq_unstake_msol_fees: self.referral_state.liq_unstake_msol_fees,
+            accum_liquid_unstake_fee: self.referral_state.accum_liquid_unstake_fee,
+        });
 
         Ok(())
     }
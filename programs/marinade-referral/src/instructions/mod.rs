@@ -1,5 +1,11 @@
+pub mod add_liquidity;
 pub mod admin;
+pub mod claim;
+pub mod claim_referral_rewards;
 mod common;
 pub mod deposit_sol;
 pub mod deposit_stake_account;
 pub mod liquid_unstake;
+pub mod order_unstake;
+pub mod refresh_collateral;
+pub mod remove_liquidity;
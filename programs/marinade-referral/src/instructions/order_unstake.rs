@@ -0,0 +1,120 @@
+use anchor_lang::prelude::*;
+
+use super::common::{check_marinade_state, check_partner_msol_account, transfer_split_msol_fee};
+use marinade_onchain_helper::cpi_util;
+
+use crate::constant::{GLOBAL_STATE_SEED, PAUSE_DELAYED_UNSTAKE, TICKET_RECORD_SEED};
+use crate::cpi_context_accounts::MarinadeOrderUnstake;
+use crate::error::ReferralError::*;
+use crate::states::{GlobalState, ReferralState, TicketRecord};
+
+//-----------------------------------------------------
+#[derive(Accounts)]
+#[instruction(msol_amount: u64, ticket_record_bump: u8)]
+pub struct OrderUnstake<'info> {
+    // this part is equivalent to marinade-finance order-unstake instructions
+    // Marinade state, checked against the global state by process
+    #[account(mut)]
+    pub state: AccountInfo<'info>,
+    #[account(mut, address = global_state.msol_mint_account)]
+    pub msol_mint: AccountInfo<'info>,
+    #[account(mut)]
+    pub burn_msol_from: AccountInfo<'info>,
+    #[account(mut, signer)]
+    pub burn_msol_authority: AccountInfo<'info>, //burn_msol_from owner or delegate_authority, pays the ticket record
+    #[account(mut)]
+    pub new_ticket_account: AccountInfo<'info>,
+    pub clock: AccountInfo<'info>,
+    pub rent: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+
+    // accounts added are: Marinade main program ID, global state, referral_state, partner token account,
+    // ticket record
+    #[account(address = marinade_finance::ID)]
+    pub marinade_finance_program: AccountInfo<'info>,
+    #[account(seeds = [GLOBAL_STATE_SEED], bump = global_state.bump)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+    #[account(mut, constraint = !referral_state.is_paused(PAUSE_DELAYED_UNSTAKE))]
+    pub referral_state: ProgramAccount<'info, ReferralState>,
+    #[account(mut, address = referral_state.msol_token_partner_account)]
+    pub msol_token_partner_account: AccountInfo<'info>,
+    // binds the ticket to referral_state, Claim records the claim on that referral state only
+    #[account(
+        init,
+        seeds = [TICKET_RECORD_SEED, new_ticket_account.key.as_ref()],
+        bump = ticket_record_bump,
+        payer = burn_msol_authority,
+        space = 8 + TicketRecord::LEN
+    )]
+    pub ticket_record: ProgramAccount<'info, TicketRecord>,
+    pub system_program: AccountInfo<'info>,
+    // token accounts of the referral fee beneficiaries follow as remaining accounts
+}
+
+impl<'info> OrderUnstake<'info> {
+    pub fn process(
+        &mut self,
+        msol_amount: u64,
+        ticket_record_bump: u8,
+        beneficiary_accounts: &[AccountInfo<'info>],
+    ) -> ProgramResult {
+        check_marinade_state(&self.global_state, &self.state)?;
+        check_partner_msol_account(&self.global_state, &self.msol_token_partner_account)?;
+
+        // disallow for stake-as-collateral mode
+        if self.referral_state.validator_vote_key.is_some() {
+            return Err(NotAllowedForStakeAsCollateralPartner.into());
+        };
+
+        // fee for delayed unstake operation
+        let operation_fee = transfer_split_msol_fee(
+            msol_amount,
+            self.referral_state.operation_delayed_unstake_fee,
+            &self.referral_state.active_fee_beneficiaries(),
+            beneficiary_accounts,
+            &self.token_program,
+            &self.burn_msol_from,
+            &self.msol_token_partner_account,
+            &self.burn_msol_authority,
+        )?;
+        let msol_amount_fee_deducted = msol_amount
+            .checked_sub(operation_fee)
+            .ok_or(CalculationFailure)?;
+
+        // prepare order-unstake cpi
+        let cpi_ctx = self.into_order_unstake_cpi_ctx();
+        let instruction_data = marinade_finance::instruction::OrderUnstake {
+            msol_amount: msol_amount_fee_deducted,
+        };
+        // call Marinade
+        cpi_util::invoke_signed(cpi_ctx, instruction_data)?;
+
+        self.ticket_record.referral_state = self.referral_state.key();
+        self.ticket_record.ticket_account = *self.new_ticket_account.key;
+        self.ticket_record.rent_payer = *self.burn_msol_authority.key;
+        self.ticket_record.bump = ticket_record_bump;
+
+        // update accumulators
+        self.referral_state
+            .record_order_unstake(msol_amount_fee_deducted, operation_fee)?;
+
+        Ok(())
+    }
+
+    pub fn into_order_unstake_cpi_ctx(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, MarinadeOrderUnstake<'info>> {
+        let cpi_accounts = MarinadeOrderUnstake {
+            state: self.state.clone(),
+            msol_mint: self.msol_mint.clone(),
+            burn_msol_from: self.burn_msol_from.clone(),
+            burn_msol_authority: self.burn_msol_authority.clone(),
+            new_ticket_account: self.new_ticket_account.clone(),
+            clock: self.clock.clone(),
+            rent: self.rent.clone(),
+            token_program: self.token_program.clone(),
+        };
+
+        CpiContext::new(self.marinade_finance_program.clone(), cpi_accounts)
+    }
+}
@@ -0,0 +1,82 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::TokenAccount;
+use marinade_finance::calc::proportional;
+
+use super::common::{check_marinade_state, check_partner_msol_account, load_marinade_state};
+use crate::constant::GLOBAL_STATE_SEED;
+use crate::error::ReferralError::*;
+use crate::events::RefreshCollateralEvent;
+use crate::states::{GlobalState, ReferralState};
+
+//-----------------------------------------------------
+// permissionless, values the mSOL the stake-as-collateral partner still holds
+#[derive(Accounts)]
+pub struct RefreshCollateral<'info> {
+    // global state
+    #[account(seeds = [GLOBAL_STATE_SEED], bump = global_state.bump)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+
+    // Marinade state, source of the mSOL price
+    // checked against the global state by process
+    pub state: AccountInfo<'info>,
+
+    // referral state of a stake-as-collateral partner
+    #[account(mut)]
+    pub referral_state: ProgramAccount<'info, ReferralState>,
+    #[account(address = referral_state.msol_token_partner_account)]
+    pub msol_token_partner_account: CpiAccount<'info, TokenAccount>,
+}
+
+impl<'info> RefreshCollateral<'info> {
+    pub fn process(&mut self) -> ProgramResult {
+        if self.referral_state.validator_vote_key.is_none() {
+            return Err(OnlyAllowedForStakeAsCollateralPartner.into());
+        }
+
+        check_marinade_state(&self.global_state, &self.state)?;
+        let marinade_state =
+            load_marinade_state(&self.state, &self.global_state.msol_mint_account)?;
+        check_partner_msol_account(
+            &self.global_state,
+            &self.msol_token_partner_account.to_account_info(),
+        )?;
+
+        let msol_amount = self.msol_token_partner_account.amount;
+        let current_collateral_lamports = proportional(
+            msol_amount,
+            marinade_state.msol_price,
+            marinade_finance::State::PRICE_DENOMINATOR,
+        )?;
+        // deposit_stake_account_amount restarts with each settlement, the collateral stands
+        // for all the stake deposited
+        let collateral_shortfall_lamports = self
+            .referral_state
+            .total_deposit_stake_account_amount
+            .saturating_sub(current_collateral_lamports as u128);
+        if collateral_shortfall_lamports > 0 {
+            msg!(
+                "collateral {} is below the deposited stake {}",
+                current_collateral_lamports,
+                self.referral_state.total_deposit_stake_account_amount
+            );
+        }
+
+        let slot = Clock::get()?.slot;
+        self.referral_state.current_collateral_lamports = current_collateral_lamports;
+        self.referral_state.last_refresh_slot = slot;
+
+        emit!(RefreshCollateralEvent {
+            referral_state: self.referral_state.key(),
+            msol_token_partner_account: self.msol_token_partner_account.key(),
+            msol_amount,
+            current_collateral_lamports,
+            slot,
+            total_deposit_stake_account_amount: self
+                .referral_state
+                .total_deposit_stake_account_amount,
+            collateral_shortfall_lamports,
+            keep_self_stake_amount: self.referral_state.keep_self_stake_amount,
+        });
+        Ok(())
+    }
+}
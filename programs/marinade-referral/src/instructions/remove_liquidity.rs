@@ -0,0 +1,149 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::TokenAccount;
+
+use marinade_onchain_helper::cpi_util;
+
+use super::common::{
+    apply_fee, check_delegated_fee, check_marinade_state, check_token_fee_source,
+    load_marinade_state, token_balance, transfer_lp_fee,
+};
+use crate::constant::{GLOBAL_STATE_SEED, PAUSE_ALL_OPERATIONS};
+use crate::cpi_context_accounts::MarinadeRemoveLiquidity;
+use crate::error::ReferralError::*;
+use crate::states::{GlobalState, ReferralState};
+
+//-----------------------------------------------------
+#[derive(Accounts)]
+pub struct RemoveLiquidity<'info> {
+    // this part is equivalent to marinade-finance remove-liquidity instructions
+    // Marinade state, checked against the global state by process
+    #[account(mut)]
+    pub state: AccountInfo<'info>,
+    #[account(mut)]
+    pub lp_mint: AccountInfo<'info>, // liquidity pool mint of the Marinade state, checked in process
+    #[account(mut)]
+    pub burn_from: AccountInfo<'info>,
+    #[account(signer)]
+    pub burn_from_authority: AccountInfo<'info>, //burn_from owner or delegate_authority
+    #[account(mut)]
+    pub transfer_sol_to: AccountInfo<'info>,
+    #[account(mut)]
+    pub transfer_msol_to: AccountInfo<'info>,
+    #[account(mut)]
+    pub liq_pool_sol_leg_pda: AccountInfo<'info>,
+    #[account(mut)]
+    pub liq_pool_msol_leg: AccountInfo<'info>,
+    pub liq_pool_msol_leg_authority: AccountInfo<'info>,
+    pub system_program: AccountInfo<'info>,
+    pub token_program: AccountInfo<'info>,
+
+    // accounts added are: Marinade main program ID, global state, referral_state, partner LP token account
+    #[account(address = marinade_finance::ID)]
+    pub marinade_finance_program: AccountInfo<'info>,
+    #[account(seeds = [GLOBAL_STATE_SEED], bump = global_state.bump)]
+    pub global_state: ProgramAccount<'info, GlobalState>,
+    #[account(mut, constraint = !referral_state.is_paused(PAUSE_ALL_OPERATIONS))]
+    pub referral_state: ProgramAccount<'info, ReferralState>,
+    #[account(
+        mut,
+        constraint = lp_token_partner_account.owner == referral_state.partner_account
+            && lp_token_partner_account.mint == *lp_mint.key
+    )]
+    pub lp_token_partner_account: CpiAccount<'info, TokenAccount>,
+}
+
+impl<'info> RemoveLiquidity<'info> {
+    pub fn process(&mut self, tokens: u64) -> ProgramResult {
+        check_marinade_state(&self.global_state, &self.state)?;
+
+        // disallow for stake-as-collateral mode
+        if self.referral_state.validator_vote_key.is_some() {
+            return Err(NotAllowedForStakeAsCollateralPartner.into());
+        };
+
+        let marinade_state =
+            load_marinade_state(&self.state, &self.global_state.msol_mint_account)?;
+        if *self.lp_mint.key != marinade_state.liq_pool.lp_mint {
+            msg!(
+                "LP mint {} is not the liquidity pool mint {}",
+                self.lp_mint.key,
+                marinade_state.liq_pool.lp_mint
+            );
+            return Err(InvalidLpMint.into());
+        }
+        check_token_fee_source(&self.burn_from, self.lp_mint.key, &self.burn_from_authority)?;
+        check_delegated_fee(
+            &self.burn_from,
+            &self.burn_from_authority,
+            apply_fee(self.referral_state.operation_remove_liquidity_fee, tokens)?,
+        )?;
+
+        // fee for remove liquidity operation, taken in LP tokens before burning
+        let operation_fee = transfer_lp_fee(
+            tokens,
+            self.referral_state.operation_remove_liquidity_fee,
+            &self.token_program,
+            &self.burn_from,
+            &self.lp_token_partner_account.to_account_info(),
+            &self.burn_from_authority,
+        )?;
+        let tokens_fee_deducted = tokens
+            .checked_sub(operation_fee)
+            .ok_or(CalculationFailure)?;
+
+        // balances before remove-liquidity call
+        let sol_before = self.transfer_sol_to.lamports();
+        let msol_before = token_balance(&self.transfer_msol_to)?;
+
+        // call Marinade
+        let cpi_ctx = self.into_remove_liquidity_cpi_ctx();
+        let instruction_data = marinade_finance::instruction::RemoveLiquidity {
+            tokens: tokens_fee_deducted,
+        };
+        cpi_util::invoke_signed(cpi_ctx, instruction_data)?;
+
+        // balances after remove-liquidity call
+        let removed_lamports = self
+            .transfer_sol_to
+            .lamports()
+            .checked_sub(sol_before)
+            .ok_or(CalculationFailure)?;
+        let removed_msol = token_balance(&self.transfer_msol_to)?
+            .checked_sub(msol_before)
+            .ok_or(CalculationFailure)?;
+        msg!(
+            "removed {} lamports and {} msol for {} LP tokens",
+            removed_lamports,
+            removed_msol,
+            tokens_fee_deducted
+        );
+
+        // update accumulators
+        self.referral_state.record_remove_liquidity(
+            removed_lamports,
+            removed_msol,
+            operation_fee,
+        )?;
+        Ok(())
+    }
+
+    pub fn into_remove_liquidity_cpi_ctx(
+        &self,
+    ) -> CpiContext<'_, '_, '_, 'info, MarinadeRemoveLiquidity<'info>> {
+        let cpi_accounts = MarinadeRemoveLiquidity {
+            state: self.state.clone(),
+            lp_mint: self.lp_mint.clone(),
+            burn_from: self.burn_from.clone(),
+            burn_from_authority: self.burn_from_authority.clone(),
+            transfer_sol_to: self.transfer_sol_to.clone(),
+            transfer_msol_to: self.transfer_msol_to.clone(),
+            liq_pool_sol_leg_pda: self.liq_pool_sol_leg_pda.clone(),
+            liq_pool_msol_leg: self.liq_pool_msol_leg.clone(),
+            liq_pool_msol_leg_authority: self.liq_pool_msol_leg_authority.clone(),
+            system_program: self.system_program.clone(),
+            token_program: self.token_program.clone(),
+        };
+
+        CpiContext::new(self.marinade_finance_program.clone(), cpi_accounts)
+    }
+}
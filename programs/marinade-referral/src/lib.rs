@@ -2,12 +2,21 @@ use anchor_lang::prelude::*;
 use anchor_lang::solana_program::declare_id;
 use anchor_lang::solana_program::pubkey::Pubkey;
 
-use instructions::{admin::*, deposit_sol::*, deposit_stake_account::*, liquid_unstake::*};
+use instructions::{
+    add_liquidity::*, admin::*, claim::*, claim_referral_rewards::*, deposit_sol::*,
+    deposit_stake_account::*, liquid_unstake::*, order_unstake::*, refresh_collateral::*,
+    remove_liquidity::*,
+};
+use states::FeeBeneficiary;
 
 ///constant
 pub mod constant;
+///cpi context accounts
+pub mod cpi_context_accounts;
 ///error
 pub mod error;
+///events
+pub mod events;
 ///instructions
 pub mod instructions;
 ///states
@@ -21,7 +30,17 @@ pub mod marinade_referral {
 
     ///deposit SOL
     pub fn deposit(ctx: Context<Deposit>, lamports: u64) -> ProgramResult {
-        ctx.accounts.process(lamports)
+        ctx.accounts.process(lamports, 0, ctx.remaining_accounts)
+    }
+
+    ///deposit SOL, failing if the user would receive less than min_msol_out
+    pub fn deposit_with_min_out(
+        ctx: Context<Deposit>,
+        lamports: u64,
+        min_msol_out: u64,
+    ) -> ProgramResult {
+        ctx.accounts
+            .process(lamports, min_msol_out, ctx.remaining_accounts)
     }
 
     ///deposit stake account
@@ -29,22 +48,83 @@ pub mod marinade_referral {
         ctx: Context<DepositStakeAccount>,
         validator_index: u32,
     ) -> ProgramResult {
-        ctx.accounts.process(validator_index)
+        ctx.accounts
+            .process(validator_index, ctx.remaining_accounts)
+    }
+
+    ///deposit stake account of a stake-as-collateral partner, keeping keep_self_stake_pct
+    pub fn deposit_stake_account_as_collateral(
+        ctx: Context<DepositStakeAccountAsCollateral>,
+        validator_index: u32,
+    ) -> ProgramResult {
+        ctx.accounts
+            .process(validator_index, ctx.remaining_accounts)
     }
 
     ///liquid-unstake mSOL
     pub fn liquid_unstake(ctx: Context<LiquidUnstake>, msol_amount: u64) -> ProgramResult {
-        ctx.accounts.process(msol_amount)
+        ctx.accounts.process(msol_amount, 0, ctx.remaining_accounts)
+    }
+
+    ///liquid-unstake mSOL, failing if the user would receive less than min_lamports_out
+    pub fn liquid_unstake_with_min_out(
+        ctx: Context<LiquidUnstake>,
+        msol_amount: u64,
+        min_lamports_out: u64,
+    ) -> ProgramResult {
+        ctx.accounts
+            .process(msol_amount, min_lamports_out, ctx.remaining_accounts)
+    }
+
+    ///order-unstake mSOL (delayed-unstake)
+    pub fn order_unstake(
+        ctx: Context<OrderUnstake>,
+        msol_amount: u64,
+        ticket_record_bump: u8,
+    ) -> ProgramResult {
+        ctx.accounts
+            .process(msol_amount, ticket_record_bump, ctx.remaining_accounts)
+    }
+
+    ///claim a delayed-unstake ticket ordered through the referral state
+    pub fn claim(ctx: Context<Claim>) -> ProgramResult {
+        ctx.accounts.process()
+    }
+
+    ///close the record of a ticket claimed directly through Marinade, rent back to its payer
+    pub fn close_ticket_record(ctx: Context<CloseTicketRecord>) -> ProgramResult {
+        ctx.accounts.process()
+    }
+
+    ///add liquidity to the mSOL-SOL pool
+    pub fn add_liquidity(ctx: Context<AddLiquidity>, lamports: u64) -> ProgramResult {
+        ctx.accounts.process(lamports)
+    }
+
+    ///remove liquidity from the mSOL-SOL pool
+    pub fn remove_liquidity(ctx: Context<RemoveLiquidity>, tokens: u64) -> ProgramResult {
+        ctx.accounts.process(tokens)
+    }
+
+    ///pay the partner share of liquid-unstake fees from the treasury vault
+    pub fn claim_referral_rewards(ctx: Context<ClaimReferralRewards>) -> ProgramResult {
+        ctx.accounts.process()
+    }
+
+    ///value the mSOL held by a stake-as-collateral partner, permissionless
+    pub fn refresh_collateral(ctx: Context<RefreshCollateral>) -> ProgramResult {
+        ctx.accounts.process()
     }
 
     ///Admin
     ///create global state
     pub fn initialize(
         ctx: Context<Initialize>,
+        bump: u8,
         min_keep_pct: u8,
         max_keep_pct: u8,
     ) -> ProgramResult {
-        ctx.accounts.process(min_keep_pct, max_keep_pct)
+        ctx.accounts.process(bump, min_keep_pct, max_keep_pct)
     }
 
     ///create referral state
@@ -53,34 +133,145 @@ pub mod marinade_referral {
         partner_name: String,
         validator_vote_key: Option<Pubkey>,
         keep_self_stake_pct: u8,
+        bump: u8,
     ) -> ProgramResult {
         ctx.accounts
-            .process(partner_name, validator_vote_key, keep_self_stake_pct)
+            .process(partner_name, validator_vote_key, keep_self_stake_pct, bump)
     }
 
-    ///update referral state
-    pub fn update_referral(ctx: Context<UpdateReferral>, pause: bool) -> ProgramResult {
+    ///close a paused referral state, final snapshot and rent to rent_destination
+    pub fn close_referral_account(
+        ctx: Context<CloseReferralAccount>,
+        require_settled: bool,
+    ) -> ProgramResult {
+        ctx.accounts.process(require_settled)
+    }
+
+    ///move a v0.2 referral state into the partner PDA with the current layout
+    pub fn migrate_referral_state(ctx: Context<MigrateReferralState>, bump: u8) -> ProgramResult {
+        ctx.accounts.process(bump)
+    }
+
+    ///update referral state, pause is the bitmask of the paused operations
+    pub fn update_referral(ctx: Context<UpdateReferral>, pause: u8) -> ProgramResult {
         ctx.accounts.process(pause)
     }
 
     ///update referral operation fees
     pub fn update_operation_fees(
         ctx: Context<UpdateOperationFees>,
-        operation_deposit_sol_fee: Option<u8>,
-        operation_deposit_stake_account_fee: Option<u8>,
-        operation_liquid_unstake_fee: Option<u8>,
-        operation_delayed_unstake_fee: Option<u8>,
+        operation_deposit_sol_fee: Option<u16>,
+        operation_deposit_stake_account_fee: Option<u16>,
+        operation_liquid_unstake_fee: Option<u16>,
+        operation_delayed_unstake_fee: Option<u16>,
+        operation_add_liquidity_fee: Option<u16>,
+        operation_remove_liquidity_fee: Option<u16>,
     ) -> ProgramResult {
         ctx.accounts.process(
             operation_deposit_sol_fee,
             operation_deposit_stake_account_fee,
             operation_liquid_unstake_fee,
             operation_delayed_unstake_fee,
+            operation_add_liquidity_fee,
+            operation_remove_liquidity_fee,
         )
     }
 
-    /// update partner, authority and beneficiary account based on the new partner
-    pub fn change_authority(ctx: Context<ChangeAuthority>) -> ProgramResult {
+    ///update the operation fee caps
+    pub fn update_operation_fee_caps(
+        ctx: Context<UpdateOperationFeeCaps>,
+        max_operation_deposit_sol_fee: Option<u16>,
+        max_operation_deposit_stake_account_fee: Option<u16>,
+        max_operation_liquid_unstake_fee: Option<u16>,
+        max_operation_delayed_unstake_fee: Option<u16>,
+        max_operation_add_liquidity_fee: Option<u16>,
+        max_operation_remove_liquidity_fee: Option<u16>,
+    ) -> ProgramResult {
+        ctx.accounts.process(
+            max_operation_deposit_sol_fee,
+            max_operation_deposit_stake_account_fee,
+            max_operation_liquid_unstake_fee,
+            max_operation_delayed_unstake_fee,
+            max_operation_add_liquidity_fee,
+            max_operation_remove_liquidity_fee,
+        )
+    }
+
+    ///update the validator and kept stake percentage of a stake-as-collateral partner
+    pub fn update_collateral_config(
+        ctx: Context<UpdateCollateralConfig>,
+        validator_vote_key: Pubkey,
+        keep_self_stake_pct: u8,
+    ) -> ProgramResult {
+        ctx.accounts
+            .process(validator_vote_key, keep_self_stake_pct)
+    }
+
+    ///update the accepted keep_self_stake_pct range of the stake-as-collateral partners
+    pub fn update_global_keep_range(
+        ctx: Context<UpdateGlobalKeepRange>,
+        min_keep_pct: u8,
+        max_keep_pct: u8,
+    ) -> ProgramResult {
+        ctx.accounts.process(min_keep_pct, max_keep_pct)
+    }
+
+    ///set the partner deposit caps, 0 = unlimited
+    pub fn update_deposit_caps(
+        ctx: Context<UpdateDepositCaps>,
+        max_total_deposit: u64,
+        max_deposit_per_epoch: u64,
+    ) -> ProgramResult {
+        ctx.accounts
+            .process(max_total_deposit, max_deposit_per_epoch)
+    }
+
+    ///pin the wrapper instructions to the Marinade state minting the global mSOL mint
+    pub fn update_marinade_state(ctx: Context<UpdateMarinadeState>) -> ProgramResult {
+        ctx.accounts.process()
+    }
+
+    ///emergency pause of deposits and liquid-unstakes of all partners
+    pub fn set_global_pause(ctx: Context<SetGlobalPause>, paused: bool) -> ProgramResult {
+        ctx.accounts.process(paused)
+    }
+
+    ///set the sub-partners sharing the referral operation fees
+    pub fn update_fee_beneficiaries(
+        ctx: Context<UpdateFeeBeneficiaries>,
+        fee_beneficiaries: Vec<FeeBeneficiary>,
+    ) -> ProgramResult {
+        ctx.accounts
+            .process(fee_beneficiaries, ctx.remaining_accounts)
+    }
+
+    ///update the partner reward curve
+    pub fn update_reward_curve(
+        ctx: Context<UpdateRewardCurve>,
+        base_fee: u32,
+        max_fee: u32,
+        max_net_stake: u64,
+    ) -> ProgramResult {
+        ctx.accounts.process(base_fee, max_fee, max_net_stake)
+    }
+
+    ///propose a new admin, effective once accepted
+    pub fn propose_authority(ctx: Context<ProposeAuthority>) -> ProgramResult {
+        ctx.accounts.process()
+    }
+
+    ///accept the admin role, signed by the proposed admin
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> ProgramResult {
+        ctx.accounts.process()
+    }
+
+    ///cancel a pending admin proposal
+    pub fn cancel_authority_proposal(ctx: Context<CancelAuthorityProposal>) -> ProgramResult {
+        ctx.accounts.process()
+    }
+
+    ///update foremen accounts
+    pub fn update_foremen(ctx: Context<UpdateForemen>) -> ProgramResult {
         ctx.accounts.process()
     }
 
@@ -92,6 +283,11 @@ pub mod marinade_referral {
         ctx.accounts.process(lamports)
     }
 
+    ///snapshot and reset the referral accumulators
+    pub fn settle_period(ctx: Context<SettlePeriod>) -> ProgramResult {
+        ctx.accounts.process()
+    }
+
     // required for https://docs.rs/solana-program-test/1.7.11/solana_program_test/index.html
     // in order to load two programs with entry points into the simulator
     pub fn test_entry(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
@@ -1,12 +1,23 @@
 use anchor_lang::prelude::*;
-use marinade_finance::{calc::proportional, error::CommonError, Fee};
+use marinade_finance::{calc::proportional, Fee};
+
+use crate::constant::{
+    DEFAULT_OPERATION_FEE_POINTS, GLOBAL_STATE_SEED, MAX_PARTNER_NAME_LENGTH, PAUSE_ALL_OPERATIONS,
+    REFEREE_RECORD_SEED, REFERRAL_STATE_SEED, REFERRAL_STATE_VERSION, TICKET_RECORD_SEED,
+};
+use crate::error::ReferralError::{CalculationFailure, DepositCapExceeded};
 
 //-----------------------------------------------------
 ///marinade-referral-program PDA
 #[account]
 pub struct GlobalState {
+    // layout version, GLOBAL_STATE_VERSION
+    pub version: u8,
+
     // Authority (admin address)
     pub admin_account: Pubkey,
+    // proposed new admin, becomes admin_account once it signs AcceptAuthority
+    pub pending_admin: Option<Pubkey>,
 
     // msol mint account to verify the mint of partner msol account (must be fed externally)
     pub msol_mint_account: Pubkey,
@@ -18,12 +29,66 @@ pub struct GlobalState {
     // max & min accepted keep_pct for stake_as_collateral partner mode
     pub min_keep_pct: u8,
     pub max_keep_pct: u8,
+
+    // bump of the global state PDA (GLOBAL_STATE_SEED)
+    pub bump: u8,
+
+    // caps of the referral operation fees (basis points), set by UpdateOperationFeeCaps
+    pub max_operation_deposit_sol_fee: u16,
+    pub max_operation_deposit_stake_account_fee: u16,
+    pub max_operation_liquid_unstake_fee: u16,
+    pub max_operation_delayed_unstake_fee: u16,
+    pub max_operation_add_liquidity_fee: u16,
+    pub max_operation_remove_liquidity_fee: u16,
+
+    // emergency pause of the deposit and liquid-unstake operations of all partners,
+    // set by SetGlobalPause
+    pub paused: bool,
+
+    // Marinade state the wrapper instructions are pinned to, set by UpdateMarinadeState
+    pub marinade_state: Pubkey,
+
+    // for fields added later, read as zero by accounts of this layout
+    pub reserved: [u8; 64],
+}
+
+impl GlobalState {
+    // Borsh size of the layout, the account space without the discriminator
+    pub const LEN: usize = 1 // version
+        + 32 // admin_account
+        + 1 + 32 // pending_admin
+        + 32 // msol_mint_account
+        + 32 + 32 // foreman_1, foreman_2
+        + 1 + 1 // min_keep_pct, max_keep_pct
+        + 1 // bump
+        + 6 * 2 // max_operation_*_fee
+        + 1 // paused
+        + 32 // marinade_state
+        + 64; // reserved
+
+    // global state address, as initialized by Initialize
+    pub fn find_address() -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[GLOBAL_STATE_SEED], &crate::marinade_referral::ID)
+    }
+}
+
+//-----------------------------------------------------
+///sub-partner receiving a share of the referral operation fees
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct FeeBeneficiary {
+    // mSOL token account of the sub-partner
+    pub msol_token_account: Pubkey,
+    // share of the operation fee (basis points, 10_000 = 100%)
+    pub fee_share: u16,
 }
 
 //-----------------------------------------------------
 ///referral PDA
 #[account]
 pub struct ReferralState {
+    // layout version, REFERRAL_STATE_VERSION (v0.2 accounts have no version)
+    pub version: u8,
+
     // Partner name
     pub partner_name: String, //max-length 20 bytes
 
@@ -36,27 +101,27 @@ pub struct ReferralState {
     // token account where to make payment (mSOL address for partner_account)
     pub msol_token_partner_account: Pubkey,
 
-    // accumulated deposit-sol amount (SOL, u64)
-    pub deposit_sol_amount: u64,
+    // accumulated deposit-sol amount (SOL, u128)
+    pub deposit_sol_amount: u128,
     // accumulated count of deposit-sol operations (u64, for stats/monitoring)
     pub deposit_sol_operations: u64,
 
-    // accumulated deposit-stake-account amount (SOL, u64)
-    pub deposit_stake_account_amount: u64,
+    // accumulated deposit-stake-account amount (SOL, u128)
+    pub deposit_stake_account_amount: u128,
     // accumulated count of deposit-stake-account operations (u64, for stats/monitoring)
     pub deposit_stake_account_operations: u64,
 
     // accumulated liquid-unstake treasury fees (mSOL, u64)
     pub liq_unstake_msol_fees: u64,
-    // accumulated liquid-unstake amount (SOL, u64)
-    pub liq_unstake_sol_amount: u64,
-    // accumulated liquid-unstake amount (mSOL, u64)
-    pub liq_unstake_msol_amount: u64,
+    // accumulated liquid-unstake amount (SOL, u128)
+    pub liq_unstake_sol_amount: u128,
+    // accumulated liquid-unstake amount (mSOL, u128)
+    pub liq_unstake_msol_amount: u128,
     // accumulated count of unstake operations (u64, for stats/monitoring)
     pub liq_unstake_operations: u64,
 
-    // accumulated delayed-unstake amount (mSOL, u64)
-    pub delayed_unstake_amount: u64,
+    // accumulated delayed-unstake amount (mSOL, u128)
+    pub delayed_unstake_amount: u128,
     // accumulated count of delayed-unstake operations (u64, for stats/monitoring)
     pub delayed_unstake_operations: u64,
 
@@ -67,23 +132,141 @@ pub struct ReferralState {
     // Net Stake target for the max % (for example 100K SOL)
     pub max_net_stake: u64,
 
-    // emergency-pause flag (bool)
-    pub pause: bool,
+    // emergency-pause bitmask of the paused operations (PAUSE_* constants),
+    // same layout as the former bool flag which reads as PAUSE_ALL_OPERATIONS
+    pub pause: u8,
 
     // fees that will be assigned to referrals per operation, calculated in basis points
-    pub operation_deposit_sol_fee: u8,
-    pub operation_deposit_stake_account_fee: u8,
-    pub operation_liquid_unstake_fee: u8,
-    pub operation_delayed_unstake_fee: u8,
+    pub operation_deposit_sol_fee: u16,
+    pub operation_deposit_stake_account_fee: u16,
+    pub operation_liquid_unstake_fee: u16,
+    pub operation_delayed_unstake_fee: u16,
 
     // accumulators for operation fees paid
     pub accum_deposit_sol_fee: u64,
     pub accum_deposit_stake_account_fee: u64,
     pub accum_liquid_unstake_fee: u64,
     pub accum_delayed_unstake_fee: u64,
+
+    // accumulated lamports claimed from delayed-unstake tickets (SOL, u128)
+    pub delayed_unstake_claimed_lamports: u128,
+    // accumulated count of delayed-unstake ticket claims (u64, for stats/monitoring)
+    pub delayed_unstake_claim_operations: u64,
+
+    // accumulated add-liquidity amount (SOL, u128)
+    pub add_liquidity_sol_amount: u128,
+    // accumulated count of add-liquidity operations (u64, for stats/monitoring)
+    pub add_liquidity_operations: u64,
+
+    // accumulated remove-liquidity amount received (SOL, u128)
+    pub remove_liquidity_sol_amount: u128,
+    // accumulated remove-liquidity amount received (mSOL, u128)
+    pub remove_liquidity_msol_amount: u128,
+    // accumulated count of remove-liquidity operations (u64, for stats/monitoring)
+    pub remove_liquidity_operations: u64,
+
+    // fees that will be assigned to referrals per liquidity operation, calculated in basis points
+    pub operation_add_liquidity_fee: u16,
+    pub operation_remove_liquidity_fee: u16,
+
+    // accumulators for liquidity operation fees paid (LP tokens, u64)
+    pub accum_add_liquidity_fee: u64,
+    pub accum_remove_liquidity_fee: u64,
+
+    // accumulated liquid-unstake share paid out to the partner from the treasury vault (mSOL, u64)
+    pub claimed_liq_unstake_share: u64,
+
+    // bump of the referral state PDA (REFERRAL_STATE_SEED + partner account at registration)
+    pub bump: u8,
+
+    // sub-partners sharing the mSOL operation fees, unused entries have zero fee_share
+    // (the partner token account receives the rest of the fee)
+    pub fee_beneficiaries: [FeeBeneficiary; 2],
+
+    // count of the referee records created for this referral account (u64, for stats/monitoring)
+    pub unique_referees: u64,
+
+    // accumulated stake split off stake-as-collateral deposits and kept delegated
+    // to the partner validator (SOL, u128), deposit_stake_account_amount holds the deposited part.
+    // Not reset by settlements, it is the stake the partner committed over its lifetime
+    pub keep_self_stake_amount: u128,
+
+    // SOL value of the partner mSOL token account at the last RefreshCollateral,
+    // compared to total_deposit_stake_account_amount by the stake-as-collateral program
+    pub current_collateral_lamports: u64,
+    pub last_refresh_slot: u64,
+    // accumulated deposit-stake-account amount (SOL, u128), not reset by settlements:
+    // the stake the partner mSOL stands for
+    pub total_deposit_stake_account_amount: u128,
+
+    // deposit caps in lamports set by UpdateDepositCaps, 0 = unlimited
+    pub max_total_deposit: u64,
+    pub max_deposit_per_epoch: u64,
+    // deposits counted against the caps by Deposit and DepositStakeAccount (SOL, u128),
+    // not reset by settlements
+    pub total_deposit_amount: u128,
+    // deposits of deposit_epoch, restarted on the first deposit of a new epoch
+    pub epoch_deposit_amount: u64,
+    pub deposit_epoch: u64,
+
+    // for fields added later, read as zero by accounts of this layout
+    pub reserved: [u8; 128],
 }
 
 impl ReferralState {
+    // Borsh size of the layout, the account space without the discriminator
+    pub const LEN: usize = 1 // version
+        + 4 + MAX_PARTNER_NAME_LENGTH // partner_name
+        + 1 + 32 // validator_vote_key
+        + 1 // keep_self_stake_pct
+        + 32 + 32 // partner_account, msol_token_partner_account
+        + 16 + 8 // deposit_sol_*
+        + 16 + 8 // deposit_stake_account_*
+        + 8 + 16 + 16 + 8 // liq_unstake_*
+        + 16 + 8 // delayed_unstake_amount, delayed_unstake_operations
+        + 4 + 4 + 8 // base_fee, max_fee, max_net_stake
+        + 1 // pause
+        + 4 * 2 // operation_*_fee
+        + 4 * 8 // accum_*_fee
+        + 16 + 8 // delayed_unstake_claim_*
+        + 16 + 8 // add_liquidity_*
+        + 16 + 16 + 8 // remove_liquidity_*
+        + 2 * 2 // operation_*_liquidity_fee
+        + 2 * 8 // accum_*_liquidity_fee
+        + 8 // claimed_liq_unstake_share
+        + 1 // bump
+        + 2 * (32 + 2) // fee_beneficiaries
+        + 8 // unique_referees
+        + 16 // keep_self_stake_amount
+        + 8 + 8 // current_collateral_lamports, last_refresh_slot
+        + 16 // total_deposit_stake_account_amount
+        + 8 + 8 // max_total_deposit, max_deposit_per_epoch
+        + 16 + 8 + 8 // total_deposit_amount, epoch_deposit_amount, deposit_epoch
+        + 128; // reserved
+
+    // referral state address of a partner, as registered by InitReferralAccount
+    pub fn find_address(partner_account: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[REFERRAL_STATE_SEED, partner_account.as_ref()],
+            &crate::marinade_referral::ID,
+        )
+    }
+
+    // fee beneficiaries in use, their token accounts are passed in this order
+    // as the remaining accounts of the mSOL operations
+    pub fn active_fee_beneficiaries(&self) -> Vec<FeeBeneficiary> {
+        self.fee_beneficiaries
+            .iter()
+            .filter(|beneficiary| beneficiary.fee_share > 0)
+            .copied()
+            .collect()
+    }
+
+    // operation is one of the PAUSE_* bits, the all-operations bit pauses every operation
+    pub fn is_paused(&self, operation: u8) -> bool {
+        self.pause & (PAUSE_ALL_OPERATIONS | operation) != 0
+    }
+
     pub fn reset_accumulators(&mut self) {
         self.deposit_sol_amount = 0;
         self.deposit_sol_operations = 0;
@@ -96,24 +279,228 @@ impl ReferralState {
         self.liq_unstake_sol_amount = 0;
         self.liq_unstake_operations = 0;
 
+        self.delayed_unstake_amount = 0;
+        self.delayed_unstake_operations = 0;
+        self.delayed_unstake_claimed_lamports = 0;
+        self.delayed_unstake_claim_operations = 0;
+
         self.accum_deposit_sol_fee = 0;
         self.accum_deposit_stake_account_fee = 0;
         self.accum_liquid_unstake_fee = 0;
         self.accum_delayed_unstake_fee = 0;
+
+        self.add_liquidity_sol_amount = 0;
+        self.add_liquidity_operations = 0;
+        self.remove_liquidity_sol_amount = 0;
+        self.remove_liquidity_msol_amount = 0;
+        self.remove_liquidity_operations = 0;
+
+        self.accum_add_liquidity_fee = 0;
+        self.accum_remove_liquidity_fee = 0;
+
+        self.claimed_liq_unstake_share = 0;
     }
 
-    pub fn get_liq_unstake_share_amount(&self) -> Result<u64, CommonError> {
-        let total_deposit = self.deposit_sol_amount + self.deposit_stake_account_amount;
+    // copies a v0.2 referral account, fields added later get their initial values
+    pub fn migrate_from_v0_2(&mut self, old: ReferralStateV0_2, bump: u8) {
+        self.version = REFERRAL_STATE_VERSION;
+
+        self.partner_name = old.partner_name;
+        self.validator_vote_key = old.validator_vote_key;
+        self.keep_self_stake_pct = old.keep_self_stake_pct;
+        self.partner_account = old.partner_account;
+        self.msol_token_partner_account = old.msol_token_partner_account;
+
+        self.deposit_sol_amount = old.deposit_sol_amount as u128;
+        self.deposit_sol_operations = old.deposit_sol_operations;
+        self.deposit_stake_account_amount = old.deposit_stake_account_amount as u128;
+        self.deposit_stake_account_operations = old.deposit_stake_account_operations;
+
+        self.liq_unstake_msol_fees = old.liq_unstake_msol_fees;
+        self.liq_unstake_sol_amount = old.liq_unstake_sol_amount as u128;
+        self.liq_unstake_msol_amount = old.liq_unstake_msol_amount as u128;
+        self.liq_unstake_operations = old.liq_unstake_operations;
+
+        self.delayed_unstake_amount = old.delayed_unstake_amount as u128;
+        self.delayed_unstake_operations = old.delayed_unstake_operations;
+
+        self.base_fee = old.base_fee;
+        self.max_fee = old.max_fee;
+        self.max_net_stake = old.max_net_stake;
+
+        self.pause = pause_from_flag(old.pause);
+
+        self.operation_deposit_sol_fee = old.operation_deposit_sol_fee as u16;
+        self.operation_deposit_stake_account_fee = old.operation_deposit_stake_account_fee as u16;
+        self.operation_liquid_unstake_fee = old.operation_liquid_unstake_fee as u16;
+        self.operation_delayed_unstake_fee = old.operation_delayed_unstake_fee as u16;
+
+        self.accum_deposit_sol_fee = old.accum_deposit_sol_fee;
+        self.accum_deposit_stake_account_fee = old.accum_deposit_stake_account_fee;
+        self.accum_liquid_unstake_fee = old.accum_liquid_unstake_fee;
+        self.accum_delayed_unstake_fee = old.accum_delayed_unstake_fee;
+
+        self.delayed_unstake_claimed_lamports = 0;
+        self.delayed_unstake_claim_operations = 0;
+
+        self.add_liquidity_sol_amount = 0;
+        self.add_liquidity_operations = 0;
+        self.remove_liquidity_sol_amount = 0;
+        self.remove_liquidity_msol_amount = 0;
+        self.remove_liquidity_operations = 0;
+
+        self.operation_add_liquidity_fee = DEFAULT_OPERATION_FEE_POINTS;
+        self.operation_remove_liquidity_fee = DEFAULT_OPERATION_FEE_POINTS;
+
+        self.accum_add_liquidity_fee = 0;
+        self.accum_remove_liquidity_fee = 0;
+
+        self.claimed_liq_unstake_share = 0;
+
+        // the v0.2 totals were never reset, they count toward the lifetime totals and the cap
+        self.total_deposit_stake_account_amount = old.deposit_stake_account_amount as u128;
+        self.total_deposit_amount =
+            old.deposit_sol_amount as u128 + old.deposit_stake_account_amount as u128;
+
+        self.bump = bump;
+    }
+
+    pub fn record_deposit_sol(&mut self, lamports: u64, operation_fee: u64) -> ProgramResult {
+        add_amount(&mut self.deposit_sol_amount, lamports)?;
+        add_operation(&mut self.deposit_sol_operations);
+        add_fee(&mut self.accum_deposit_sol_fee, operation_fee)
+    }
+
+    pub fn record_deposit_stake_account(
+        &mut self,
+        lamports: u64,
+        operation_fee: u64,
+    ) -> ProgramResult {
+        add_amount(&mut self.deposit_stake_account_amount, lamports)?;
+        add_amount(&mut self.total_deposit_stake_account_amount, lamports)?;
+        add_operation(&mut self.deposit_stake_account_operations);
+        add_fee(&mut self.accum_deposit_stake_account_fee, operation_fee)
+    }
+
+    pub fn record_keep_self_stake(&mut self, lamports: u64) -> ProgramResult {
+        add_amount(&mut self.keep_self_stake_amount, lamports)
+    }
+
+    // counts a deposit against the caps, before Marinade is called
+    pub fn record_capped_deposit(&mut self, lamports: u64, epoch: u64) -> ProgramResult {
+        if epoch != self.deposit_epoch {
+            self.deposit_epoch = epoch;
+            self.epoch_deposit_amount = 0;
+        }
+        let total_deposit_amount = self
+            .total_deposit_amount
+            .checked_add(lamports as u128)
+            .ok_or(CalculationFailure)?;
+        let epoch_deposit_amount = self
+            .epoch_deposit_amount
+            .checked_add(lamports)
+            .ok_or(CalculationFailure)?;
+        if self.max_total_deposit > 0 && total_deposit_amount > self.max_total_deposit as u128 {
+            msg!(
+                "deposit of {} lamports over the total cap {}, already deposited {}",
+                lamports,
+                self.max_total_deposit,
+                self.total_deposit_amount
+            );
+            return Err(DepositCapExceeded.into());
+        }
+        if self.max_deposit_per_epoch > 0 && epoch_deposit_amount > self.max_deposit_per_epoch {
+            msg!(
+                "deposit of {} lamports over the epoch {} cap {}, already deposited {}",
+                lamports,
+                epoch,
+                self.max_deposit_per_epoch,
+                self.epoch_deposit_amount
+            );
+            return Err(DepositCapExceeded.into());
+        }
+        self.total_deposit_amount = total_deposit_amount;
+        self.epoch_deposit_amount = epoch_deposit_amount;
+        Ok(())
+    }
+
+    pub fn record_liquid_unstake(
+        &mut self,
+        msol_amount: u64,
+        lamports: u64,
+        treasury_msol_cut: u64,
+        operation_fee: u64,
+    ) -> ProgramResult {
+        add_fee(&mut self.liq_unstake_msol_fees, treasury_msol_cut)?;
+        add_amount(&mut self.liq_unstake_msol_amount, msol_amount)?;
+        add_amount(&mut self.liq_unstake_sol_amount, lamports)?;
+        add_operation(&mut self.liq_unstake_operations);
+        add_fee(&mut self.accum_liquid_unstake_fee, operation_fee)
+    }
+
+    pub fn record_order_unstake(&mut self, msol_amount: u64, operation_fee: u64) -> ProgramResult {
+        add_amount(&mut self.delayed_unstake_amount, msol_amount)?;
+        add_operation(&mut self.delayed_unstake_operations);
+        add_fee(&mut self.accum_delayed_unstake_fee, operation_fee)
+    }
+
+    pub fn record_claim(&mut self, lamports: u64) -> ProgramResult {
+        add_amount(&mut self.delayed_unstake_claimed_lamports, lamports)?;
+        add_operation(&mut self.delayed_unstake_claim_operations);
+        Ok(())
+    }
+
+    pub fn record_add_liquidity(&mut self, lamports: u64, operation_fee: u64) -> ProgramResult {
+        add_amount(&mut self.add_liquidity_sol_amount, lamports)?;
+        add_operation(&mut self.add_liquidity_operations);
+        add_fee(&mut self.accum_add_liquidity_fee, operation_fee)
+    }
+
+    pub fn record_remove_liquidity(
+        &mut self,
+        lamports: u64,
+        msol_amount: u64,
+        operation_fee: u64,
+    ) -> ProgramResult {
+        add_amount(&mut self.remove_liquidity_sol_amount, lamports)?;
+        add_amount(&mut self.remove_liquidity_msol_amount, msol_amount)?;
+        add_operation(&mut self.remove_liquidity_operations);
+        add_fee(&mut self.accum_remove_liquidity_fee, operation_fee)
+    }
+
+    pub fn record_new_referee(&mut self) {
+        add_operation(&mut self.unique_referees);
+    }
+
+    // the partner share was paid, liquid-unstake treasury fees start from zero
+    pub fn record_referral_rewards_claim(&mut self, share_amount: u64) -> ProgramResult {
+        add_fee(&mut self.claimed_liq_unstake_share, share_amount)?;
+        self.liq_unstake_msol_fees = 0;
+        Ok(())
+    }
+
+    pub fn get_liq_unstake_share_amount(&self) -> Result<u64, ProgramError> {
+        let total_deposit = self
+            .deposit_sol_amount
+            .checked_add(self.deposit_stake_account_amount)
+            .ok_or(CalculationFailure)?;
         // zero if more unstaked than deposited
         let net_stake = total_deposit.saturating_sub(self.liq_unstake_sol_amount);
         let share_fee_bp = if net_stake == 0 {
             self.base_fee // minimum
-        } else if net_stake > self.max_net_stake {
+        } else if net_stake > self.max_net_stake as u128 {
             self.max_fee // max
         } else {
-            let delta = self.max_fee - self.base_fee;
+            let delta = self
+                .max_fee
+                .checked_sub(self.base_fee)
+                .ok_or(CalculationFailure)?;
             // base + delta proportional to net_stake/self.max_net_stake
-            self.base_fee + proportional(delta as u64, net_stake, self.max_net_stake)? as u32
+            // net_stake <= max_net_stake here, so it fits into u64
+            let delta_share = proportional(delta as u64, net_stake as u64, self.max_net_stake)?;
+            self.base_fee
+                .checked_add(delta_share as u32)
+                .ok_or(CalculationFailure)?
         };
 
         let share_fee = Fee {
@@ -125,4 +512,271 @@ impl ReferralState {
     }
 }
 
+// lifetime volume totals are u128, amounts of a single operation are u64
+fn add_amount(total: &mut u128, amount: u64) -> ProgramResult {
+    *total = total
+        .checked_add(amount as u128)
+        .ok_or(CalculationFailure)?;
+    Ok(())
+}
+
+fn add_fee(total: &mut u64, fee: u64) -> ProgramResult {
+    *total = total.checked_add(fee).ok_or(CalculationFailure)?;
+    Ok(())
+}
+
+// operation counters are for stats/monitoring only, never fail an operation on them
+fn add_operation(count: &mut u64) {
+    *count = count.saturating_add(1);
+}
+
 //-----------------------------------------------------
+///operations of one user through a referral account, PDA of the referral state and user wallet
+#[account]
+#[derive(Default)]
+pub struct RefereeRecord {
+    // referral state the user operates through
+    pub referral_state: Pubkey,
+    // user wallet (depositor, stake account authority or mSOL owner)
+    pub user: Pubkey,
+
+    // slot of the first operation recorded
+    pub first_seen_slot: u64,
+
+    // accumulated deposit-sol amount (SOL, u128)
+    pub deposit_sol_amount: u128,
+    // accumulated deposit-stake-account amount (SOL, u128)
+    pub deposit_stake_account_amount: u128,
+    // accumulated liquid-unstake amount (SOL, u128)
+    pub liq_unstake_sol_amount: u128,
+
+    // accumulated operation fees paid by the user (mSOL, u64)
+    pub accum_operation_fee: u64,
+
+    // bump of the referee record PDA (REFEREE_RECORD_SEED + referral state + user)
+    pub bump: u8,
+}
+
+impl RefereeRecord {
+    // Borsh size of the layout, the account space without the discriminator
+    pub const LEN: usize = 32 + 32 // referral_state, user
+        + 8 // first_seen_slot
+        + 3 * 16 // deposit and liquid-unstake amounts
+        + 8 // accum_operation_fee
+        + 1; // bump
+
+    pub fn find_address(referral_state: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[REFEREE_RECORD_SEED, referral_state.as_ref(), user.as_ref()],
+            &crate::marinade_referral::ID,
+        )
+    }
+
+    pub fn record_deposit_sol(&mut self, lamports: u64, operation_fee: u64) -> ProgramResult {
+        add_amount(&mut self.deposit_sol_amount, lamports)?;
+        add_fee(&mut self.accum_operation_fee, operation_fee)
+    }
+
+    pub fn record_deposit_stake_account(
+        &mut self,
+        lamports: u64,
+        operation_fee: u64,
+    ) -> ProgramResult {
+        add_amount(&mut self.deposit_stake_account_amount, lamports)?;
+        add_fee(&mut self.accum_operation_fee, operation_fee)
+    }
+
+    pub fn record_liquid_unstake(&mut self, lamports: u64, operation_fee: u64) -> ProgramResult {
+        add_amount(&mut self.liq_unstake_sol_amount, lamports)?;
+        add_fee(&mut self.accum_operation_fee, operation_fee)
+    }
+}
+
+//-----------------------------------------------------
+///referral account layout of v0.2 (keypair account, no version), read by MigrateReferralState
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct ReferralStateV0_2 {
+    pub partner_name: String,
+
+    pub validator_vote_key: Option<Pubkey>,
+    pub keep_self_stake_pct: u8,
+
+    pub partner_account: Pubkey,
+    pub msol_token_partner_account: Pubkey,
+
+    pub deposit_sol_amount: u64,
+    pub deposit_sol_operations: u64,
+
+    pub deposit_stake_account_amount: u64,
+    pub deposit_stake_account_operations: u64,
+
+    pub liq_unstake_msol_fees: u64,
+    pub liq_unstake_sol_amount: u64,
+    pub liq_unstake_msol_amount: u64,
+    pub liq_unstake_operations: u64,
+
+    pub delayed_unstake_amount: u64,
+    pub delayed_unstake_operations: u64,
+
+    pub base_fee: u32,
+    pub max_fee: u32,
+    pub max_net_stake: u64,
+
+    pub pause: bool,
+
+    pub operation_deposit_sol_fee: u8,
+    pub operation_deposit_stake_account_fee: u8,
+    pub operation_liquid_unstake_fee: u8,
+    pub operation_delayed_unstake_fee: u8,
+
+    pub accum_deposit_sol_fee: u64,
+    pub accum_deposit_stake_account_fee: u64,
+    pub accum_liquid_unstake_fee: u64,
+    pub accum_delayed_unstake_fee: u64,
+}
+
+impl ReferralStateV0_2 {
+    // Borsh size of the layout with a full-length partner name, the v0.2 clients
+    // allocated at least this space (without the discriminator)
+    pub const LEN: usize = 4 + MAX_PARTNER_NAME_LENGTH // partner_name
+        + 1 + 32 // validator_vote_key
+        + 1 // keep_self_stake_pct
+        + 32 + 32 // partner_account, msol_token_partner_account
+        + 8 + 8 // deposit_sol_*
+        + 8 + 8 // deposit_stake_account_*
+        + 8 + 8 + 8 + 8 // liq_unstake_*
+        + 8 + 8 // delayed_unstake_*
+        + 4 + 4 + 8 // base_fee, max_fee, max_net_stake
+        + 1 // pause
+        + 4 // operation_*_fee
+        + 4 * 8; // accum_*_fee
+}
+
+//-----------------------------------------------------
+///snapshot of the referral-account accumulators at the end of a settlement period
+#[account]
+pub struct PeriodSnapshot {
+    // referral state the snapshot was taken from
+    pub referral_state: Pubkey,
+    // epoch and unix timestamp of the settlement
+    pub epoch: u64,
+    pub timestamp: i64,
+
+    pub deposit_sol_amount: u128,
+    pub deposit_sol_operations: u64,
+    pub deposit_stake_account_amount: u128,
+    pub deposit_stake_account_operations: u64,
+
+    pub liq_unstake_msol_fees: u64,
+    pub liq_unstake_sol_amount: u128,
+    pub liq_unstake_msol_amount: u128,
+    pub liq_unstake_operations: u64,
+
+    pub delayed_unstake_amount: u128,
+    pub delayed_unstake_operations: u64,
+    pub delayed_unstake_claimed_lamports: u128,
+    pub delayed_unstake_claim_operations: u64,
+
+    pub add_liquidity_sol_amount: u128,
+    pub add_liquidity_operations: u64,
+    pub remove_liquidity_sol_amount: u128,
+    pub remove_liquidity_msol_amount: u128,
+    pub remove_liquidity_operations: u64,
+
+    pub accum_deposit_sol_fee: u64,
+    pub accum_deposit_stake_account_fee: u64,
+    pub accum_liquid_unstake_fee: u64,
+    pub accum_delayed_unstake_fee: u64,
+    pub accum_add_liquidity_fee: u64,
+    pub accum_remove_liquidity_fee: u64,
+
+    pub claimed_liq_unstake_share: u64,
+
+    // partner share of liq_unstake_msol_fees computed at settlement (mSOL, u64)
+    pub liq_unstake_share_amount: u64,
+}
+
+impl PeriodSnapshot {
+    pub fn record(
+        &mut self,
+        referral_state_key: Pubkey,
+        referral_state: &ReferralState,
+        clock: &Clock,
+    ) -> ProgramResult {
+        self.referral_state = referral_state_key;
+        self.epoch = clock.epoch;
+        self.timestamp = clock.unix_timestamp;
+
+        self.deposit_sol_amount = referral_state.deposit_sol_amount;
+        self.deposit_sol_operations = referral_state.deposit_sol_operations;
+        self.deposit_stake_account_amount = referral_state.deposit_stake_account_amount;
+        self.deposit_stake_account_operations = referral_state.deposit_stake_account_operations;
+
+        self.liq_unstake_msol_fees = referral_state.liq_unstake_msol_fees;
+        self.liq_unstake_sol_amount = referral_state.liq_unstake_sol_amount;
+        self.liq_unstake_msol_amount = referral_state.liq_unstake_msol_amount;
+        self.liq_unstake_operations = referral_state.liq_unstake_operations;
+
+        self.delayed_unstake_amount = referral_state.delayed_unstake_amount;
+        self.delayed_unstake_operations = referral_state.delayed_unstake_operations;
+        self.delayed_unstake_claimed_lamports = referral_state.delayed_unstake_claimed_lamports;
+        self.delayed_unstake_claim_operations = referral_state.delayed_unstake_claim_operations;
+
+        self.add_liquidity_sol_amount = referral_state.add_liquidity_sol_amount;
+        self.add_liquidity_operations = referral_state.add_liquidity_operations;
+        self.remove_liquidity_sol_amount = referral_state.remove_liquidity_sol_amount;
+        self.remove_liquidity_msol_amount = referral_state.remove_liquidity_msol_amount;
+        self.remove_liquidity_operations = referral_state.remove_liquidity_operations;
+
+        self.accum_deposit_sol_fee = referral_state.accum_deposit_sol_fee;
+        self.accum_deposit_stake_account_fee = referral_state.accum_deposit_stake_account_fee;
+        self.accum_liquid_unstake_fee = referral_state.accum_liquid_unstake_fee;
+        self.accum_delayed_unstake_fee = referral_state.accum_delayed_unstake_fee;
+        self.accum_add_liquidity_fee = referral_state.accum_add_liquidity_fee;
+        self.accum_remove_liquidity_fee = referral_state.accum_remove_liquidity_fee;
+
+        self.claimed_liq_unstake_share = referral_state.claimed_liq_unstake_share;
+
+        self.liq_unstake_share_amount = referral_state.get_liq_unstake_share_amount()?;
+        Ok(())
+    }
+}
+
+//-----------------------------------------------------
+///delayed-unstake ticket ordered through a referral account, PDA of the ticket account
+#[account]
+pub struct TicketRecord {
+    // referral state the ticket was ordered through, the only one Claim records it on
+    pub referral_state: Pubkey,
+    // Marinade ticket account
+    pub ticket_account: Pubkey,
+    // paid the record rent in OrderUnstake, refunded by CloseTicketRecord
+    pub rent_payer: Pubkey,
+
+    // bump of the ticket record PDA (TICKET_RECORD_SEED + ticket account)
+    pub bump: u8,
+}
+
+impl TicketRecord {
+    // Borsh size of the layout, the account space without the discriminator
+    pub const LEN: usize = 32 + 32 + 32 // referral_state, ticket_account, rent_payer
+        + 1; // bump
+
+    pub fn find_address(ticket_account: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[TICKET_RECORD_SEED, ticket_account.as_ref()],
+            &crate::marinade_referral::ID,
+        )
+    }
+}
+
+//-----------------------------------------------------
+
+// the v0.2 layout stores a bool pause flag
+fn pause_from_flag(pause: bool) -> u8 {
+    if pause {
+        PAUSE_ALL_OPERATIONS
+    } else {
+        0
+    }
+}
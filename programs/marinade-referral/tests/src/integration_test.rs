@@ -1,5 +1,5 @@
 #![allow(dead_code)]
-use marinade_finance_offchain_sdk::anchor_lang::InstructionData;
+use marinade_finance_offchain_sdk::anchor_lang::{Discriminator, InstructionData};
 use marinade_finance_offchain_sdk::marinade_finance;
 use marinade_finance_offchain_sdk::spl_token::solana_program;
 use rand::SeedableRng;
@@ -7,21 +7,27 @@ use rand::{distributions::Uniform, prelude::Distribution, RngCore};
 use rand_chacha::ChaChaRng;
 use std::collections::{HashMap, HashSet};
 use std::io::{self};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, Once};
 
 use crate::initialize::InitializeInputWithSeeds;
 
 use futures::{Future, FutureExt};
+use lazy_static::lazy_static;
 use marinade_finance_offchain_sdk::anchor_lang::solana_program::{
     native_token::{lamports_to_sol, LAMPORTS_PER_SOL},
     program_pack::Pack,
+    program_stubs,
     pubkey::Pubkey,
     stake,
     stake::state::{Authorized, Lockup, StakeState},
     system_instruction, system_program, sysvar,
 };
 use marinade_finance_offchain_sdk::instruction_helpers::InstructionHelpers;
-use solana_sdk::{account::from_account, instruction::Instruction, transaction::Transaction};
+use solana_sdk::{
+    account::{from_account, Account},
+    instruction::Instruction,
+    transaction::Transaction,
+};
 
 use marinade_finance_offchain_sdk::anchor_lang::prelude::*;
 use marinade_finance_offchain_sdk::{
@@ -45,16 +51,17 @@ use solana_vote_program::{
     vote_instruction,
     vote_state::{VoteInit, VoteState},
 };
-extern crate shellexpand;
-use std::fs::File;
 
 use crate::initialize::InitializeInput;
+use marinade_referral::constant::GLOBAL_STATE_SEED;
+use marinade_referral::states::FeeBeneficiary;
 
 pub mod test_add_remove_liquidity;
 pub mod test_delayed_unstake;
 pub mod test_deposit_sol_liquid_unstake;
 pub mod test_deposit_stake_account;
 pub mod test_admin;
+pub mod test_referral_rewards;
 
 const MSOL_SYMBOL: &str = "mSOL";
 const MSOL_SOL_LP_SYMBOL: &str = "mSOL-SOL-LP";
@@ -157,6 +164,15 @@ impl TestUser {
 impl IntegrationTest {
     /// Starts an integration test and initializes the common parameters.
     pub async fn start(input: &impl InitializeInput) -> anyhow::Result<Self> {
+        Self::start_with_accounts(input, vec![]).await
+    }
+
+    /// Starts an integration test with accounts preloaded into the genesis
+    /// (e.g. program-owned accounts of older layouts the program can't create anymore).
+    pub async fn start_with_accounts(
+        input: &impl InitializeInput,
+        accounts: Vec<(Pubkey, Account)>,
+    ) -> anyhow::Result<Self> {
         let mut main_test_program = ProgramTest::new(
             "marinade_finance",
             marinade_finance::ID,
@@ -169,6 +185,9 @@ impl IntegrationTest {
             processor!(marinade_referral::marinade_referral::test_entry),
             //None, //processor!(marinade_referral::test_entry),
         );
+        for (pubkey, account) in accounts {
+            main_test_program.add_account(pubkey, account);
+        }
         // let marinade_referral =
         //     ProgramTest::new(
         //         "marinade_referral",
@@ -177,6 +196,7 @@ impl IntegrationTest {
         //     );
 
         let mut context = main_test_program.start_with_context().await;
+        capture_program_logs();
         //let (mut banks_client, payer, recent_blockhash) = test.start().await;
 
         let rent = context.banks_client.get_rent().await?;
@@ -230,37 +250,6 @@ impl IntegrationTest {
             claim_ticket_accounts: HashSet::new(),
             test_validators: vec![],
         })
-
-        // referral program, initialize global state
-        /*
-        //pub fn initialize_global_state()
-            // referral global state PDA & bump
-            let (global_state_pda, bump) = Pubkey::find_program_address(
-                &[GLOBAL_STATE_SEED],
-                &marinade_referral::marinade_referral::ID
-                );
-
-            // initialize the global state
-            {
-                let accounts = marinade_referral::accounts::Initialize {
-                    admin_account: Pubkey::from_str("AMMK9YLj8PRRG4K9DUsTNPZAZXeVbHiQJxakuVuvSKrn")
-                        .unwrap(),
-                    global_state: global_state_pda,
-                    system_program: system_program::ID,
-                };
-            }
-        }
-        pub fn initialize_referral(partner_main_account:Pubkey){
-                // referral state PDA & bump
-                let (referralStatePda, referralStateBump) = Pubkey::find_program_address(
-                    &[
-                        &partner_main_account.to_bytes()[..32],
-                        &REFERRAL_STATE_SEED,
-                    ],
-                    &marinade_referral::marinade_referral::ID
-                    );
-            }
-        */
     }
 
     // pub async fn start_synthetic(
@@ -1027,6 +1016,77 @@ pub async fn get_account<T: AccountDeserialize>(
     .unwrap()
 }
 
+lazy_static! {
+    // program logs of all the tests running in this process, events are told apart
+    // by the accounts they refer to
+    static ref PROGRAM_LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+}
+
+// syscall stubs of solana-program-test, recording the logs of the programs run in process
+struct LogCaptureStubs {
+    inner: Box<dyn program_stubs::SyscallStubs>,
+}
+
+// placeholder while the stubs of solana-program-test are moved into LogCaptureStubs
+struct DefaultStubs;
+impl program_stubs::SyscallStubs for DefaultStubs {}
+
+impl program_stubs::SyscallStubs for LogCaptureStubs {
+    fn sol_log(&self, message: &str) {
+        PROGRAM_LOGS.lock().unwrap().push(message.to_string());
+        self.inner.sol_log(message);
+    }
+    fn sol_log_compute_units(&self) {
+        self.inner.sol_log_compute_units();
+    }
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        self.inner
+            .sol_invoke_signed(instruction, account_infos, signers_seeds)
+    }
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_clock_sysvar(var_addr)
+    }
+    fn sol_get_epoch_schedule_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_epoch_schedule_sysvar(var_addr)
+    }
+    fn sol_get_fees_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_fees_sysvar(var_addr)
+    }
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.inner.sol_get_rent_sysvar(var_addr)
+    }
+}
+
+// wraps the syscall stubs set by the first ProgramTest start
+fn capture_program_logs() {
+    static ONCE: Once = Once::new();
+    ONCE.call_once(|| {
+        let inner = program_stubs::set_syscall_stubs(Box::new(DefaultStubs));
+        program_stubs::set_syscall_stubs(Box::new(LogCaptureStubs { inner }));
+    });
+}
+
+// events of type T emitted so far by the programs (Anchor logs them base64 encoded)
+pub fn emitted_events<T: AnchorDeserialize + Discriminator>() -> Vec<T> {
+    PROGRAM_LOGS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|message| {
+            let data = base64::decode(message).ok()?;
+            if data.len() < 8 || data[..8] != T::discriminator() {
+                return None;
+            }
+            T::deserialize(&mut &data[8..]).ok()
+        })
+        .collect()
+}
+
 // returns a random lamports amount between from_sol..to_sol
 pub fn random_amount(from_sol: u64, to_sol: u64, rng: &mut impl RngCore) -> u64 {
     Uniform::from((from_sol * LAMPORTS_PER_SOL)..(to_sol * LAMPORTS_PER_SOL)).sample(rng)
@@ -1081,58 +1141,15 @@ pub async fn create_global_state_account(
     foreman_1: Option<Pubkey>,
     foreman_2: Option<Pubkey>,
 ) -> (Pubkey, TestUser) {
-
-    // // admin: AMMK9YLj8PRRG4K9DUsTNPZAZXeVbHiQJxakuVuvSKrn
-    // let admin_key_bytes: [u8; 64] = [
-    //     136, 60, 191, 232, 11, 20, 1, 82, 147, 185, 119, 92, 226, 212, 217, 227, 38, 100, 72, 135,
-    //     189, 121, 32, 38, 93, 10, 41, 104, 38, 158, 171, 38, 138, 239, 196, 48, 200, 45, 19, 235,
-    //     223, 73, 101, 62, 195, 45, 48, 246, 226, 240, 177, 172, 213, 0, 184, 113, 158, 176, 17,
-    //     177, 2, 215, 168, 135,
-    // ];
-    // let admin_key: Keypair = Keypair::from_bytes(&admin_key_bytes).unwrap();
-
-    // global state: MRSh4rUNrpn7mjAq9ENHV4rvwwPKMij113ScZq3twp2
-    // let global_state_key_bytes: [u8; 64] = [
-    //     134, 187, 164, 119, 110, 122, 23, 81, 124, 160, 171, 39, 43, 21, 99, 70, 76, 134, 197, 224,
-    //     143, 215, 219, 77, 113, 249, 46, 150, 129, 186, 236, 4, 11, 97, 116, 100, 244, 31, 228,
-    //     117, 219, 46, 34, 185, 59, 70, 45, 64, 93, 139, 190, 44, 110, 167, 44, 91, 202, 253, 222,
-    //     122, 43, 255, 45, 163,
-    // ];
-    // let global_state_key = Keypair::from_bytes(&global_state_key_bytes).unwrap();
-
-    // Note: global_state_key is now a fixed value to avoid a simple attack:
-    // what if I create a new global state where I am the admin, and then call the admin instructions 
-    // with my new global state and some existing referral-account. Then I can bypass admin check.
-    let global_state_key: Keypair = load_key_pair("~/.config/solana/MRSh4rUNrpn7mjAq9ENHV4rvwwPKMij113ScZq3twp2.json".into());
-
-    let admin_key = Keypair::new(); // = load_key_pair("~/.config/solana/MRSh4rUNrpn7mjAq9ENHV4rvwwPKMij113ScZq3twp2.json".into());
-
     let admin = test
-        .create_test_user_from_keypair(
-            "test_referral_admin_user",
-            200 * LAMPORTS_PER_SOL,
-            admin_key,
-        )
+        .create_test_user("test_referral_admin_user", 200 * LAMPORTS_PER_SOL)
         .await;
 
-    
-    let global_state_pubkey = global_state_key.pubkey();
-    let global_state_space = 8 + std::mem::size_of::<marinade_referral::states::GlobalState>();
-    test.builder.add_signer(Arc::new(global_state_key)); // need to sign with private key to create account
-    test.builder
-        .add_instruction(
-            system_instruction::create_account(
-                &test.builder.fee_payer(),
-                &global_state_pubkey,
-                test.rent.minimum_balance(global_state_space),
-                global_state_space as u64,
-                &marinade_referral::marinade_referral::ID,
-            ),
-            format!("pre-create marinade-referral global state acc because banks-clients do not support creation from program {}", global_state_pubkey),
-        )
-        .unwrap();
-    println!("create global state account {}", global_state_pubkey);
-    test.execute().await;
+    // global state is the PDA derived from the fixed seed, created by the program itself
+    let (global_state_pubkey, global_state_bump) = Pubkey::find_program_address(
+        &[GLOBAL_STATE_SEED],
+        &marinade_referral::marinade_referral::ID,
+    );
 
     let foreman_1 = if foreman_1.is_some() {
         foreman_1.unwrap()
@@ -1148,11 +1165,17 @@ pub async fn create_global_state_account(
         let accounts = marinade_referral::accounts::Initialize {
             admin_account: admin.keypair.pubkey(),
             msol_mint_account: test.state.msol_mint,
+            marinade_state: test.state.key,
             global_state: global_state_pubkey,
             foreman_1,
             foreman_2,
+            system_program: system_program::ID,
+        };
+        let ix_data = marinade_referral::instruction::Initialize {
+            bump: global_state_bump,
+            min_keep_pct: 10,
+            max_keep_pct: 90,
         };
-        let ix_data = marinade_referral::instruction::Initialize {min_keep_pct:10, max_keep_pct:90};
         let instruction = Instruction {
             program_id: marinade_referral::marinade_referral::ID,
             accounts: accounts.to_account_metas(None),
@@ -1175,43 +1198,45 @@ pub async fn create_referral_state_account(
     admin_pk: &Arc<Keypair>,
     referral_msol_account: Pubkey,
 ) -> std::result::Result<Pubkey, u32> {
-    // partner referral state (referral code)
-    let referral_state_key = Keypair::new();
-    let referral_state_pubkey = referral_state_key.pubkey();
-    // 8=Anchor sha-struct-ident, 10 partner-name string
-    let referral_state_size =
-        8 + 10 + std::mem::size_of::<marinade_referral::states::ReferralState>();
-    test.builder.add_signer(Arc::new(referral_state_key)); // need to sign with private key to create account
-    test.builder
-        .add_instruction(
-            system_instruction::create_account(
-                &test.builder.fee_payer(),
-                &referral_state_pubkey,
-                test.rent.minimum_balance(referral_state_size),
-                referral_state_size as u64,
-                &marinade_referral::marinade_referral::ID,
-            ),
-            format!("pre-create referral-state because banks-clients do not support creation from program {}", referral_state_pubkey),
-        )
-        .unwrap();
-    println!(
-        "create referral-state account {}. msol-mint {}",
-        referral_state_pubkey, &test.state.msol_mint
-    );
-    test.execute().await;
+    init_referral_account_execute(
+        test,
+        partner.keypair.pubkey(),
+        global_state,
+        admin_pk,
+        referral_msol_account,
+        None,
+        0,
+    )
+    .await
+}
+
+pub async fn init_referral_account_execute(
+    test: &mut IntegrationTest,
+    partner: Pubkey,
+    global_state: Pubkey,
+    admin_pk: &Arc<Keypair>,
+    referral_msol_account: Pubkey,
+    validator_vote_key: Option<Pubkey>,
+    keep_self_stake_pct: u8,
+) -> std::result::Result<Pubkey, u32> {
+    // partner referral state (referral code), PDA of the partner account
+    let (referral_state_pubkey, referral_state_bump) =
+        marinade_referral::states::ReferralState::find_address(&partner);
 
     {
         let accounts = marinade_referral::accounts::InitReferralAccount {
             global_state,
             signer: admin_pk.pubkey(),
             referral_state: referral_state_pubkey,
-            partner_account: partner.keypair.pubkey(),
+            partner_account: partner,
             msol_token_partner_account: referral_msol_account,
+            system_program: system_program::ID,
         };
         let ix_data = marinade_referral::instruction::InitReferralAccount {
             partner_name: "TEST_PART".into(),
-            validator_vote_key: None,
-            keep_self_stake_pct: 0
+            validator_vote_key,
+            keep_self_stake_pct,
+            bump: referral_state_bump,
         };
         let instruction = Instruction {
             program_id: marinade_referral::marinade_referral::ID,
@@ -1225,30 +1250,90 @@ pub async fn create_referral_state_account(
     Ok(referral_state_pubkey)
 }
 
-pub async fn change_authority_execute(
+pub async fn propose_authority_execute(
     test: &mut IntegrationTest,
     global_state: Pubkey,
-    old_admin: Pubkey,
+    admin: Pubkey,
     new_admin: Pubkey,
+    signer: &Arc<Keypair>, // usually admin but for the failure testing we can differ
+) -> Result<(), u32> {
+    let accounts = marinade_referral::accounts::ProposeAuthority {
+        global_state,
+        admin_account: admin,
+        new_admin_account: new_admin,
+    };
+    let ix_data = marinade_referral::instruction::ProposeAuthority {};
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    println!("Proposing authority change from {} to {}", admin, new_admin);
+    test.try_execute_instruction(instruction, vec![test.fee_payer_signer(), signer.clone()])
+        .await
+}
+
+pub async fn accept_authority_execute(
+    test: &mut IntegrationTest,
+    global_state: Pubkey,
+    new_admin: &Arc<Keypair>,
+) -> Result<(), u32> {
+    let accounts = marinade_referral::accounts::AcceptAuthority {
+        global_state,
+        new_admin_account: new_admin.pubkey(),
+    };
+    let ix_data = marinade_referral::instruction::AcceptAuthority {};
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    println!("Accepting authority by {}", new_admin.pubkey());
+    test.try_execute_instruction(instruction, vec![test.fee_payer_signer(), new_admin.clone()])
+        .await
+}
+
+pub async fn cancel_authority_proposal_execute(
+    test: &mut IntegrationTest,
+    global_state: Pubkey,
+    admin: &Arc<Keypair>,
+) -> Result<(), u32> {
+    let accounts = marinade_referral::accounts::CancelAuthorityProposal {
+        global_state,
+        admin_account: admin.pubkey(),
+    };
+    let ix_data = marinade_referral::instruction::CancelAuthorityProposal {};
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    println!("Cancelling authority proposal");
+    test.try_execute_instruction(instruction, vec![test.fee_payer_signer(), admin.clone()])
+        .await
+}
+
+pub async fn update_foremen_execute(
+    test: &mut IntegrationTest,
+    global_state: Pubkey,
+    admin: &Arc<Keypair>,
     new_foreman_1: Pubkey,
     new_foreman_2: Pubkey,
-    signer: &Arc<Keypair>, // usually old_admin but for the failure testing we can differ
 ) -> Result<(), u32> {
-    let accounts = marinade_referral::accounts::ChangeAuthority {
+    let accounts = marinade_referral::accounts::UpdateForemen {
         global_state,
-        admin_account: old_admin,
-        new_admin_account: new_admin,
+        admin_account: admin.pubkey(),
         new_foreman_1,
         new_foreman_2,
     };
-    let ix_data = marinade_referral::instruction::ChangeAuthority {};
+    let ix_data = marinade_referral::instruction::UpdateForemen {};
     let instruction = Instruction {
         program_id: marinade_referral::marinade_referral::ID,
         accounts: accounts.to_account_metas(None),
         data: ix_data.data(),
     };
-    println!("Changing authority from {} to {}", old_admin, new_admin);
-    test.try_execute_instruction(instruction, vec![test.fee_payer_signer(), signer.clone()])
+    println!("Updating foremen to {} and {}", new_foreman_1, new_foreman_2);
+    test.try_execute_instruction(instruction, vec![test.fee_payer_signer(), admin.clone()])
         .await
 }
 
@@ -1259,7 +1344,7 @@ pub async fn update_referral_execute(
     referral_state: Pubkey,
     new_partner_account: Pubkey,
     new_msol_token_partner_account: Pubkey,
-    pause: bool,
+    pause: u8,
 ) -> Result<(), u32> {
     let accounts = marinade_referral::accounts::UpdateReferral {
         global_state,
@@ -1284,15 +1369,50 @@ pub async fn update_referral_execute(
     .await
 }
 
+// creates an mSOL token account of owner which is not its associated token account
+pub async fn create_msol_token_account(test: &mut IntegrationTest, owner: &Pubkey) -> Pubkey {
+    let token_account_key = Keypair::new();
+    let token_account_pubkey = token_account_key.pubkey();
+    test.builder.add_signer(Arc::new(token_account_key));
+    test.builder
+        .add_instruction(
+            system_instruction::create_account(
+                &test.builder.fee_payer(),
+                &token_account_pubkey,
+                test.rent.minimum_balance(TokenAccount::LEN),
+                TokenAccount::LEN as u64,
+                &marinade_finance_offchain_sdk::spl_token::ID,
+            ),
+            format!("create mSOL token account {}", token_account_pubkey),
+        )
+        .unwrap();
+    test.builder
+        .add_instruction(
+            marinade_finance_offchain_sdk::spl_token::instruction::initialize_account(
+                &marinade_finance_offchain_sdk::spl_token::ID,
+                &token_account_pubkey,
+                &test.state.msol_mint,
+                owner,
+            )
+            .unwrap(),
+            format!("initialize mSOL token account {}", token_account_pubkey),
+        )
+        .unwrap();
+    test.execute().await;
+    token_account_pubkey
+}
+
 pub async fn update_operation_fees(
     test: &mut IntegrationTest,
     global_state: Pubkey,
     signer_keypair: &Arc<Keypair>,
     referral_state: Pubkey,
-    operation_deposit_sol_fee: Option<u8>,
-    operation_deposit_stake_account_fee: Option<u8>,
-    operation_liquid_unstake_fee: Option<u8>,
-    operation_delayed_unstake_fee: Option<u8>,
+    operation_deposit_sol_fee: Option<u16>,
+    operation_deposit_stake_account_fee: Option<u16>,
+    operation_liquid_unstake_fee: Option<u16>,
+    operation_delayed_unstake_fee: Option<u16>,
+    operation_add_liquidity_fee: Option<u16>,
+    operation_remove_liquidity_fee: Option<u16>,
 ) -> Result<(), u32> {
     let accounts = marinade_referral::accounts::UpdateOperationFees {
         global_state,
@@ -1304,6 +1424,8 @@ pub async fn update_operation_fees(
         operation_deposit_stake_account_fee,
         operation_liquid_unstake_fee,
         operation_delayed_unstake_fee,
+        operation_add_liquidity_fee,
+        operation_remove_liquidity_fee,
     };
     let instruction = Instruction {
         program_id: marinade_referral::marinade_referral::ID,
@@ -1318,6 +1440,336 @@ pub async fn update_operation_fees(
     .await
 }
 
+pub async fn update_operation_fee_caps_execute(
+    test: &mut IntegrationTest,
+    global_state: Pubkey,
+    admin_keypair: &Arc<Keypair>,
+    max_operation_deposit_sol_fee: Option<u16>,
+    max_operation_deposit_stake_account_fee: Option<u16>,
+    max_operation_liquid_unstake_fee: Option<u16>,
+    max_operation_delayed_unstake_fee: Option<u16>,
+    max_operation_add_liquidity_fee: Option<u16>,
+    max_operation_remove_liquidity_fee: Option<u16>,
+) -> Result<(), u32> {
+    let accounts = marinade_referral::accounts::UpdateOperationFeeCaps {
+        global_state,
+        admin_account: admin_keypair.pubkey(),
+    };
+    let ix_data = marinade_referral::instruction::UpdateOperationFeeCaps {
+        max_operation_deposit_sol_fee,
+        max_operation_deposit_stake_account_fee,
+        max_operation_liquid_unstake_fee,
+        max_operation_delayed_unstake_fee,
+        max_operation_add_liquidity_fee,
+        max_operation_remove_liquidity_fee,
+    };
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    test.try_execute_instruction(
+        instruction,
+        vec![test.fee_payer_signer(), admin_keypair.clone()],
+    )
+    .await
+}
+
+pub async fn update_collateral_config_execute(
+    test: &mut IntegrationTest,
+    global_state: Pubkey,
+    signer: &Arc<Keypair>,
+    referral_state: Pubkey,
+    validator_vote_key: Pubkey,
+    keep_self_stake_pct: u8,
+) -> Result<(), u32> {
+    let accounts = marinade_referral::accounts::UpdateCollateralConfig {
+        global_state,
+        signer: signer.pubkey(),
+        referral_state,
+    };
+    let ix_data = marinade_referral::instruction::UpdateCollateralConfig {
+        validator_vote_key,
+        keep_self_stake_pct,
+    };
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    test.try_execute_instruction(instruction, vec![test.fee_payer_signer(), signer.clone()])
+        .await
+}
+
+pub async fn update_global_keep_range_execute(
+    test: &mut IntegrationTest,
+    global_state: Pubkey,
+    admin_keypair: &Arc<Keypair>,
+    min_keep_pct: u8,
+    max_keep_pct: u8,
+) -> Result<(), u32> {
+    let accounts = marinade_referral::accounts::UpdateGlobalKeepRange {
+        global_state,
+        admin_account: admin_keypair.pubkey(),
+    };
+    let ix_data = marinade_referral::instruction::UpdateGlobalKeepRange {
+        min_keep_pct,
+        max_keep_pct,
+    };
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    test.try_execute_instruction(
+        instruction,
+        vec![test.fee_payer_signer(), admin_keypair.clone()],
+    )
+    .await
+}
+
+pub async fn update_deposit_caps_execute(
+    test: &mut IntegrationTest,
+    global_state: Pubkey,
+    admin_keypair: &Arc<Keypair>,
+    referral_state: Pubkey,
+    max_total_deposit: u64,
+    max_deposit_per_epoch: u64,
+) -> Result<(), u32> {
+    let accounts = marinade_referral::accounts::UpdateDepositCaps {
+        global_state,
+        admin_account: admin_keypair.pubkey(),
+        referral_state,
+    };
+    let ix_data = marinade_referral::instruction::UpdateDepositCaps {
+        max_total_deposit,
+        max_deposit_per_epoch,
+    };
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    test.try_execute_instruction(
+        instruction,
+        vec![test.fee_payer_signer(), admin_keypair.clone()],
+    )
+    .await
+}
+
+pub async fn update_marinade_state_execute(
+    test: &mut IntegrationTest,
+    global_state: Pubkey,
+    admin_keypair: &Arc<Keypair>,
+    new_marinade_state: Pubkey,
+) -> Result<(), u32> {
+    let accounts = marinade_referral::accounts::UpdateMarinadeState {
+        global_state,
+        admin_account: admin_keypair.pubkey(),
+        new_marinade_state,
+    };
+    let ix_data = marinade_referral::instruction::UpdateMarinadeState {};
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    test.try_execute_instruction(
+        instruction,
+        vec![test.fee_payer_signer(), admin_keypair.clone()],
+    )
+    .await
+}
+
+pub async fn set_global_pause_execute(
+    test: &mut IntegrationTest,
+    global_state: Pubkey,
+    signer: &Arc<Keypair>,
+    paused: bool,
+) -> Result<(), u32> {
+    let accounts = marinade_referral::accounts::SetGlobalPause {
+        global_state,
+        signer: signer.pubkey(),
+    };
+    let ix_data = marinade_referral::instruction::SetGlobalPause { paused };
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    test.try_execute_instruction(instruction, vec![test.fee_payer_signer(), signer.clone()])
+        .await
+}
+
+pub async fn refresh_collateral_execute(
+    test: &mut IntegrationTest,
+    global_state: Pubkey,
+    referral_state: Pubkey,
+    msol_token_partner_account: Pubkey,
+) -> Result<(), u32> {
+    let accounts = marinade_referral::accounts::RefreshCollateral {
+        global_state,
+        state: test.state.key,
+        referral_state,
+        msol_token_partner_account,
+    };
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: marinade_referral::instruction::RefreshCollateral {}.data(),
+    };
+    test.try_execute_instruction(instruction, vec![test.fee_payer_signer()])
+        .await
+}
+
+pub async fn update_fee_beneficiaries_execute(
+    test: &mut IntegrationTest,
+    global_state: Pubkey,
+    admin_keypair: &Arc<Keypair>,
+    referral_state: Pubkey,
+    fee_beneficiaries: Vec<FeeBeneficiary>,
+    beneficiary_accounts: &[Pubkey],
+) -> Result<(), u32> {
+    let accounts = marinade_referral::accounts::UpdateFeeBeneficiaries {
+        global_state,
+        admin_account: admin_keypair.pubkey(),
+        referral_state,
+    };
+    let ix_data = marinade_referral::instruction::UpdateFeeBeneficiaries { fee_beneficiaries };
+    let mut account_metas = accounts.to_account_metas(None);
+    // beneficiary token accounts as remaining accounts
+    account_metas.extend(
+        beneficiary_accounts
+            .iter()
+            .map(|account| AccountMeta::new_readonly(*account, false)),
+    );
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: account_metas,
+        data: ix_data.data(),
+    };
+    test.try_execute_instruction(
+        instruction,
+        vec![test.fee_payer_signer(), admin_keypair.clone()],
+    )
+    .await
+}
+
+pub async fn update_reward_curve_execute(
+    test: &mut IntegrationTest,
+    global_state: Pubkey,
+    admin_keypair: &Arc<Keypair>,
+    referral_state: Pubkey,
+    base_fee: u32,
+    max_fee: u32,
+    max_net_stake: u64,
+) -> Result<(), u32> {
+    let accounts = marinade_referral::accounts::UpdateRewardCurve {
+        global_state,
+        admin_account: admin_keypair.pubkey(),
+        referral_state,
+    };
+    let ix_data = marinade_referral::instruction::UpdateRewardCurve {
+        base_fee,
+        max_fee,
+        max_net_stake,
+    };
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    println!("Calling ix to change reward curve at the referral state {}", referral_state);
+    test.try_execute_instruction(
+        instruction,
+        vec![test.fee_payer_signer(), admin_keypair.clone()],
+    )
+    .await
+}
+
+pub async fn create_period_snapshot_account(test: &mut IntegrationTest) -> Pubkey {
+    let period_snapshot_key = Keypair::new();
+    let period_snapshot_pubkey = period_snapshot_key.pubkey();
+    let period_snapshot_size =
+        8 + std::mem::size_of::<marinade_referral::states::PeriodSnapshot>();
+    test.builder.add_signer(Arc::new(period_snapshot_key)); // need to sign with private key to create account
+    test.builder
+        .add_instruction(
+            system_instruction::create_account(
+                &test.builder.fee_payer(),
+                &period_snapshot_pubkey,
+                test.rent.minimum_balance(period_snapshot_size),
+                period_snapshot_size as u64,
+                &marinade_referral::marinade_referral::ID,
+            ),
+            format!("pre-create period snapshot because banks-clients do not support creation from program {}", period_snapshot_pubkey),
+        )
+        .unwrap();
+    test.execute().await;
+    period_snapshot_pubkey
+}
+
+pub async fn settle_period_execute(
+    test: &mut IntegrationTest,
+    global_state: Pubkey,
+    admin_keypair: &Arc<Keypair>,
+    referral_state: Pubkey,
+) -> Result<Pubkey, u32> {
+    let period_snapshot_pubkey = create_period_snapshot_account(test).await;
+    let accounts = marinade_referral::accounts::SettlePeriod {
+        global_state,
+        admin_account: admin_keypair.pubkey(),
+        referral_state,
+        period_snapshot: period_snapshot_pubkey,
+        clock: sysvar::clock::id(),
+    };
+    let ix_data = marinade_referral::instruction::SettlePeriod {};
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    println!("Calling ix to settle period of the referral state {}", referral_state);
+    test.try_execute_instruction(
+        instruction,
+        vec![test.fee_payer_signer(), admin_keypair.clone()],
+    )
+    .await?;
+    Ok(period_snapshot_pubkey)
+}
+
+pub async fn close_referral_account_execute(
+    test: &mut IntegrationTest,
+    global_state: Pubkey,
+    admin_keypair: &Arc<Keypair>,
+    referral_state: Pubkey,
+    rent_destination: Pubkey,
+    require_settled: bool,
+) -> Result<Pubkey, u32> {
+    let period_snapshot_pubkey = create_period_snapshot_account(test).await;
+    let accounts = marinade_referral::accounts::CloseReferralAccount {
+        global_state,
+        admin_account: admin_keypair.pubkey(),
+        referral_state,
+        period_snapshot: period_snapshot_pubkey,
+        rent_destination,
+        clock: sysvar::clock::id(),
+    };
+    let ix_data = marinade_referral::instruction::CloseReferralAccount { require_settled };
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    println!("Calling ix to close the referral state {}", referral_state);
+    test.try_execute_instruction(
+        instruction,
+        vec![test.fee_payer_signer(), admin_keypair.clone()],
+    )
+    .await?;
+    Ok(period_snapshot_pubkey)
+}
+
 impl MarinadeReferralTestGlobals {
     async fn set_no_operation_fees(&self, test: &mut IntegrationTest) {
         update_operation_fees(
@@ -1329,6 +1781,8 @@ impl MarinadeReferralTestGlobals {
             Some(0),
             Some(0),
             Some(0),
+            Some(0),
+            Some(0),
         )
         .await
         .unwrap();
@@ -1339,6 +1793,8 @@ impl MarinadeReferralTestGlobals {
         assert_eq!(referral_state.accum_deposit_sol_fee, 0);
         assert_eq!(referral_state.accum_deposit_stake_account_fee, 0);
         assert_eq!(referral_state.accum_liquid_unstake_fee, 0);
+        assert_eq!(referral_state.accum_add_liquidity_fee, 0);
+        assert_eq!(referral_state.accum_remove_liquidity_fee, 0);
     }
 
     async fn pause_referral_account(&self, test: &mut IntegrationTest) {
@@ -1349,26 +1805,16 @@ impl MarinadeReferralTestGlobals {
             self.partner_referral_state_pubkey,
             self.partner.keypair.pubkey(),
             self.msol_partner_token_pubkey,
-            true,
+            marinade_referral::constant::PAUSE_ALL_OPERATIONS,
         )
         .await
         .unwrap();
 
         let referral_state: marinade_referral::states::ReferralState =
             get_account(test, self.partner_referral_state_pubkey).await;
-        assert_eq!(referral_state.pause, true);
+        assert_eq!(
+            referral_state.pause,
+            marinade_referral::constant::PAUSE_ALL_OPERATIONS
+        );
     }
 }
-
-/// Reads a `Keypair` from a json file, path can include ~ shortcut
-pub fn load_key_pair(path:String)->Keypair {
-    let full_path = shellexpand::tilde(&path).to_string();
-    let file = File::open(full_path).unwrap();
-    read_keypair(file)
-}
-
-/// Reads a JSON-encoded `Keypair` from a File struct
-fn read_keypair(file: File) -> Keypair {
-    let bytes: Vec<u8> = serde_json::from_reader(file).unwrap();
-    Keypair::from_bytes(&bytes).unwrap()
-}
@@ -7,7 +7,10 @@ use std::sync::Arc;
 
 use marinade_finance_offchain_sdk::spl_associated_token_account::get_associated_token_address;
 use marinade_finance_offchain_sdk::{
-    instruction_helpers::InstructionHelpers, marinade_finance::State,
+    instruction_helpers::InstructionHelpers,
+    marinade_finance,
+    marinade_finance::{liq_pool::LiqPoolHelpers, State},
+    spl_token,
 };
 
 use rand::{distributions::Uniform, prelude::Distribution, CryptoRng, RngCore, SeedableRng};
@@ -15,6 +18,7 @@ use rand_chacha::ChaChaRng;
 
 use solana_program::native_token::LAMPORTS_PER_SOL;
 use solana_sdk::{
+    instruction::Instruction,
     pubkey::Pubkey,
     signature::{Keypair, Signer},
 };
@@ -188,3 +192,256 @@ async fn test_remove_all_liquidity() -> anyhow::Result<()> {
     do_remove_liquidity(&mut user, liquidity_amount, &mut test).await;
     Ok(())
 }
+
+async fn try_referral_add_liquidity(
+    test: &mut IntegrationTest,
+    user: &TestUser,
+    lp_mint: Pubkey,
+    user_lp_token_account: Pubkey,
+    referral_state: Pubkey,
+    lp_token_partner_account: Pubkey,
+    lamports: u64,
+) -> Result<(), u32> {
+    let accounts = marinade_referral::accounts::AddLiquidity {
+        state: test.state.key(),
+        lp_mint,
+        lp_mint_authority: test.state.lp_mint_authority(),
+        liq_pool_msol_leg: test.state.liq_pool.msol_leg,
+        liq_pool_sol_leg_pda: test.state.liq_pool_sol_leg_address(),
+        transfer_from: user.keypair.pubkey(),
+        mint_to: user_lp_token_account,
+        system_program: system_program::ID,
+        token_program: spl_token::ID,
+        //----
+        marinade_finance_program: marinade_finance::ID,
+        global_state: marinade_referral::states::GlobalState::find_address().0,
+        referral_state,
+        lp_token_partner_account,
+    };
+    let ix_data = marinade_referral::instruction::AddLiquidity { lamports };
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    println!("marinade-referral add_liquidity");
+    test.try_execute_instruction(
+        instruction,
+        vec![test.fee_payer_signer(), user.keypair.clone()],
+    )
+    .await
+}
+
+async fn try_referral_remove_liquidity(
+    test: &mut IntegrationTest,
+    user: &TestUser,
+    user_lp_token_account: Pubkey,
+    user_msol_account: Pubkey,
+    referral_state: Pubkey,
+    lp_token_partner_account: Pubkey,
+    tokens: u64,
+) -> Result<(), u32> {
+    let accounts = marinade_referral::accounts::RemoveLiquidity {
+        state: test.state.key(),
+        lp_mint: test.state.liq_pool.lp_mint,
+        burn_from: user_lp_token_account,
+        burn_from_authority: user.keypair.pubkey(),
+        transfer_sol_to: user.keypair.pubkey(),
+        transfer_msol_to: user_msol_account,
+        liq_pool_sol_leg_pda: test.state.liq_pool_sol_leg_address(),
+        liq_pool_msol_leg: test.state.liq_pool.msol_leg,
+        liq_pool_msol_leg_authority: test.state.liq_pool_msol_leg_authority(),
+        system_program: system_program::ID,
+        token_program: spl_token::ID,
+        //----
+        marinade_finance_program: marinade_finance::ID,
+        global_state: marinade_referral::states::GlobalState::find_address().0,
+        referral_state,
+        lp_token_partner_account,
+    };
+    let ix_data = marinade_referral::instruction::RemoveLiquidity { tokens };
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    println!("marinade-referral remove_liquidity");
+    test.try_execute_instruction(
+        instruction,
+        vec![test.fee_payer_signer(), user.keypair.clone()],
+    )
+    .await
+}
+
+#[test(tokio::test)]
+async fn test_referral_add_remove_liquidity_with_fees() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, mut rng) = IntegrationTest::init_test().await?;
+
+    let add_liquidity_fee: u16 = 10;
+    let remove_liquidity_fee: u16 = 20;
+    update_operation_fees(
+        &mut test,
+        marinade_referral_test_globals.global_state_pubkey,
+        &marinade_referral_test_globals.admin_key,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        None,
+        None,
+        None,
+        None,
+        Some(add_liquidity_fee),
+        Some(remove_liquidity_fee),
+    )
+    .await
+    .unwrap();
+
+    let partner_lp_token_account = marinade_referral_test_globals
+        .partner
+        .get_or_create_lp_token_account(&mut test)
+        .await;
+    let user = test
+        .create_test_user("alice", 1001 * LAMPORTS_PER_SOL)
+        .await;
+    let user_msol_account = user.get_or_create_msol_account_instruction(&mut test).await;
+    let user_lp_token_account = user.get_or_create_lp_token_account(&mut test).await;
+    test.execute().await;
+
+    // ADD LIQUIDITY
+    let lamports = random_amount(1, 1000, &mut rng);
+    let lp_mint = test.state.liq_pool.lp_mint;
+    try_referral_add_liquidity(
+        &mut test,
+        &user,
+        lp_mint,
+        user_lp_token_account.pubkey,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        partner_lp_token_account.pubkey,
+        lamports,
+    )
+    .await
+    .unwrap();
+
+    // empty pool, LP tokens are minted 1:1
+    let add_fee_tokens = lamports * add_liquidity_fee as u64 / 10_000;
+    let user_lp_tokens = lamports - add_fee_tokens;
+    assert_eq!(
+        test.get_token_balance(&user_lp_token_account.pubkey).await,
+        user_lp_tokens
+    );
+    assert_eq!(
+        test.get_token_balance(&partner_lp_token_account.pubkey)
+            .await,
+        add_fee_tokens,
+        "Partner is expected to receive LP tokens in the amount of the operation fee"
+    );
+    let referral_state: marinade_referral::states::ReferralState = get_account(
+        &mut test,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+    )
+    .await;
+    assert_eq!(referral_state.add_liquidity_sol_amount, lamports as u128);
+    assert_eq!(referral_state.add_liquidity_operations, 1);
+    assert_eq!(referral_state.accum_add_liquidity_fee, add_fee_tokens);
+
+    // REMOVE LIQUIDITY
+    let user_sol_balance_before = user.sol_balance(&mut test).await;
+    try_referral_remove_liquidity(
+        &mut test,
+        &user,
+        user_lp_token_account.pubkey,
+        user_msol_account.pubkey,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        partner_lp_token_account.pubkey,
+        user_lp_tokens,
+    )
+    .await
+    .unwrap();
+
+    let remove_fee_tokens = user_lp_tokens * remove_liquidity_fee as u64 / 10_000;
+    // no mSOL in the pool, LP tokens are burned 1:1 for SOL
+    let removed_lamports = user_lp_tokens - remove_fee_tokens;
+    assert_eq!(
+        user.sol_balance(&mut test).await,
+        user_sol_balance_before + removed_lamports
+    );
+    assert_eq!(
+        test.get_token_balance(&user_lp_token_account.pubkey).await,
+        0
+    );
+    assert_eq!(
+        test.get_token_balance(&partner_lp_token_account.pubkey)
+            .await,
+        add_fee_tokens + remove_fee_tokens
+    );
+    let referral_state: marinade_referral::states::ReferralState = get_account(
+        &mut test,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+    )
+    .await;
+    assert_eq!(
+        referral_state.remove_liquidity_sol_amount,
+        removed_lamports as u128
+    );
+    assert_eq!(referral_state.remove_liquidity_msol_amount, 0);
+    assert_eq!(referral_state.remove_liquidity_operations, 1);
+    assert_eq!(referral_state.accum_remove_liquidity_fee, remove_fee_tokens);
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_referral_add_liquidity_wrong_partner_account() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    let user = test
+        .create_test_user("alice", 1001 * LAMPORTS_PER_SOL)
+        .await;
+    let user_lp_token_account = user.get_or_create_lp_token_account(&mut test).await;
+    test.execute().await;
+
+    // the LP fee account is not owned by the partner
+    let lp_mint = test.state.liq_pool.lp_mint;
+    let result = try_referral_add_liquidity(
+        &mut test,
+        &user,
+        lp_mint,
+        user_lp_token_account.pubkey,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        user_lp_token_account.pubkey,
+        LAMPORTS_PER_SOL,
+    )
+    .await;
+    match result {
+        Ok(_) => panic!("Expected error happens when LP token account is not owned by the partner"),
+        Err(number) => {
+            // https://github.com/coral-xyz/anchor/blob/v0.14.0/lang/src/error.rs
+            assert_eq!(
+                143, number,
+                "Expected anchor error 'A raw constraint was violated'"
+            );
+        }
+    }
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_referral_add_liquidity_wrong_lp_mint() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    let user = test
+        .create_test_user("alice", 1001 * LAMPORTS_PER_SOL)
+        .await;
+    let user_msol_account = user.get_or_create_msol_account_instruction(&mut test).await;
+    test.execute().await;
+
+    // mSOL mint and accounts passed as the LP ones, the fee would be taken in mSOL
+    let msol_mint = test.state.msol_mint;
+    let result = try_referral_add_liquidity(
+        &mut test,
+        &user,
+        msol_mint,
+        user_msol_account.pubkey,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        marinade_referral_test_globals.msol_partner_token_pubkey,
+        LAMPORTS_PER_SOL,
+    )
+    .await;
+    assert_eq!(result, Err(317), "Expected the LP mint error");
+    Ok(())
+}
@@ -3,6 +3,10 @@
 // global state and referral state initialization
 // RUSTFLAGS=-Awarnings cargo test test_state_initialization --manifest-path programs/marinade-referral/tests/Cargo.toml
 //
+use crate::integration_test::test_deposit_sol_liquid_unstake::{
+    do_deposit_sol, try_deposit_execute,
+};
+use crate::integration_test::test_referral_rewards::generate_liquid_unstake_fees;
 use crate::{initialize::InitializeInputWithSeeds, integration_test::*};
 use std::sync::Arc;
 
@@ -10,12 +14,18 @@ use rand::SeedableRng;
 use rand_chacha::ChaChaRng;
 
 use marinade_finance_offchain_sdk::spl_token::solana_program;
+use marinade_finance_offchain_sdk::anchor_lang::Discriminator;
 use marinade_referral::constant::{
     DEFAULT_BASE_FEE_POINTS, DEFAULT_MAX_FEE_POINTS, DEFAULT_MAX_NET_STAKE,
-    DEFAULT_OPERATION_FEE_POINTS, MAX_OPERATION_FEE_POINTS,
+    DEFAULT_MAX_OPERATION_FEE_POINTS, DEFAULT_OPERATION_FEE_POINTS, GLOBAL_STATE_SEED,
+    GLOBAL_STATE_VERSION, MAX_PARTNER_NAME_LENGTH, PAUSE_ALL_OPERATIONS, PAUSE_LIQUID_UNSTAKE,
+    REFERRAL_STATE_VERSION,
 };
+use marinade_referral::states::{FeeBeneficiary, ReferralStateV0_2};
 use solana_sdk::{
+    account::Account,
     native_token::LAMPORTS_PER_SOL,
+    rent::Rent,
     signature::{Keypair, Signer},
 };
 use test_env_log::test;
@@ -49,7 +59,51 @@ async fn test_init_global_state() -> anyhow::Result<()> {
         test.state.msol_mint, global_state.msol_mint_account,
         "Global state 'treasury token account' key does not match"
     );
+    assert_eq!(
+        DEFAULT_MAX_OPERATION_FEE_POINTS, global_state.max_operation_deposit_sol_fee,
+        "Global state 'max deposit sol fee' should be init at default value"
+    );
+    assert_eq!(
+        DEFAULT_MAX_OPERATION_FEE_POINTS, global_state.max_operation_liquid_unstake_fee,
+        "Global state 'max liquid unstake fee' should be init at default value"
+    );
+    let global_state_account = test
+        .context
+        .banks_client
+        .get_account(marinade_referrals.global_state_pubkey)
+        .await?
+        .unwrap();
+    assert_eq!(
+        8 + marinade_referral::states::GlobalState::LEN,
+        global_state_account.data.len(),
+        "Global state account should be sized by the layout length"
+    );
+    assert_eq!(
+        GLOBAL_STATE_VERSION, global_state.version,
+        "Global state should have the current layout version"
+    );
     // REFERRAL STATE
+    let referral_state_account = test
+        .context
+        .banks_client
+        .get_account(marinade_referrals.partner_referral_state_pubkey)
+        .await?
+        .unwrap();
+    assert_eq!(
+        8 + marinade_referral::states::ReferralState::LEN,
+        referral_state_account.data.len(),
+        "Referral state account should be sized by the layout length"
+    );
+    assert_eq!(
+        marinade_referral::states::ReferralState::LEN,
+        referral_state.try_to_vec()?.len() + MAX_PARTNER_NAME_LENGTH
+            - referral_state.partner_name.len(),
+        "Referral state layout length should be its Borsh size with the longest partner name"
+    );
+    assert_eq!(
+        REFERRAL_STATE_VERSION, referral_state.version,
+        "Referral state should have the current layout version"
+    );
     assert_eq!(
         "TEST_PART", referral_state.partner_name,
         "Refferal state 'partner name' does not match"
@@ -63,9 +117,9 @@ async fn test_init_global_state() -> anyhow::Result<()> {
         marinade_referrals.msol_partner_token_pubkey, referral_state.msol_token_partner_account,
         "Referral state 'partner token account' does not match",
     );
-    assert!(
-        !referral_state.pause,
-        "Account init value of the 'pause' should be false",
+    assert_eq!(
+        0, referral_state.pause,
+        "Account init value of the 'pause' should be 0",
     );
     assert_eq!(
         DEFAULT_MAX_NET_STAKE, referral_state.max_net_stake,
@@ -155,7 +209,7 @@ async fn test_double_referral_state_initialization() -> anyhow::Result<()> {
     // referral_state exists
     let referral_state: marinade_referral::states::ReferralState =
         get_account(&mut test, marinade_referrals.partner_referral_state_pubkey).await;
-    assert!(!referral_state.pause, "Referral state account should exist and not paused");
+    assert_eq!(0, referral_state.pause, "Referral state account should exist and not paused");
 
 
     let accounts = marinade_referral::accounts::InitReferralAccount {
@@ -164,11 +218,21 @@ async fn test_double_referral_state_initialization() -> anyhow::Result<()> {
         referral_state: marinade_referrals.partner_referral_state_pubkey,
         partner_account: marinade_referrals.partner.keypair.pubkey(),
         msol_token_partner_account: marinade_referrals.msol_partner_token_pubkey,
+        system_program: solana_program::system_program::ID,
     };
+    let (referral_state_pda, referral_state_bump) = marinade_referral::states::ReferralState::find_address(
+        &marinade_referrals.partner.keypair.pubkey(),
+    );
+    assert_eq!(
+        referral_state_pda, marinade_referrals.partner_referral_state_pubkey,
+        "Referral state should be derived from the partner account"
+    );
+    assert_eq!(referral_state_bump, referral_state.bump);
     let ix_data = marinade_referral::instruction::InitReferralAccount {
         partner_name: "FAILING".into(),
         validator_vote_key: None,
         keep_self_stake_pct: 0,
+        bump: referral_state_bump,
     };
     let instruction = Instruction {
         program_id: marinade_referral::marinade_referral::ID,
@@ -177,13 +241,65 @@ async fn test_double_referral_state_initialization() -> anyhow::Result<()> {
     };
     let txn_result = test.try_execute_instruction(instruction, vec![test.fee_payer_signer(), marinade_referrals.admin_key.clone()])
         .await;
-    match txn_result {
-        // https://github.com/coral-xyz/anchor/blob/v0.14.0/lang/src/error.rs
-        Err(error_number) => assert_eq!(
-            153, error_number, "Expected zero account discriminant"
-        ),
-        _ => panic!("Expected the transaction fails with the constraint violation."),
-    }
+    // the partner PDA already exists, a second registration can't create it
+    assert!(
+        txn_result.is_err(),
+        "Expected the partner can't be registered twice"
+    );
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_double_global_state_initialization() -> anyhow::Result<()> {
+    let (mut test, marinade_referrals, _) = IntegrationTest::init_test().await?;
+
+    // global state lives at the PDA derived from the fixed seed
+    let (global_state_pda, global_state_bump) = Pubkey::find_program_address(
+        &[GLOBAL_STATE_SEED],
+        &marinade_referral::marinade_referral::ID,
+    );
+    assert_eq!(global_state_pda, marinade_referrals.global_state_pubkey);
+    let global_state: marinade_referral::states::GlobalState =
+        get_account(&mut test, marinade_referrals.global_state_pubkey).await;
+    assert_eq!(global_state_bump, global_state.bump);
+
+    // the PDA can be created only once, a second admin can't take it over
+    let saboteur = test
+        .create_test_user("saboteur", 200 * LAMPORTS_PER_SOL)
+        .await;
+    let accounts = marinade_referral::accounts::Initialize {
+        admin_account: saboteur.keypair.pubkey(),
+        msol_mint_account: test.state.msol_mint,
+        marinade_state: test.state.key,
+        global_state: global_state_pda,
+        foreman_1: saboteur.keypair.pubkey(),
+        foreman_2: saboteur.keypair.pubkey(),
+        system_program: solana_program::system_program::ID,
+    };
+    let ix_data = marinade_referral::instruction::Initialize {
+        bump: global_state_bump,
+        min_keep_pct: 0,
+        max_keep_pct: 100,
+    };
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    let txn_result = test
+        .try_execute_instruction(
+            instruction,
+            vec![test.fee_payer_signer(), saboteur.keypair.clone()],
+        )
+        .await;
+    assert!(
+        txn_result.is_err(),
+        "Expected the global state can't be initialized twice"
+    );
+
+    let global_state: marinade_referral::states::GlobalState =
+        get_account(&mut test, marinade_referrals.global_state_pubkey).await;
+    assert_eq!(marinade_referrals.admin_key.pubkey(), global_state.admin_account);
     Ok(())
 }
 
@@ -192,8 +308,9 @@ async fn init_test_with_foreman() -> anyhow::Result<(IntegrationTest, Pubkey, Te
     let input = InitializeInputWithSeeds::random(&mut rng);
     let mut test = IntegrationTest::start(&input).await?;
 
-    let foreman_1 = test.create_test_user("foreman1", 1_000).await;
-    let foreman_2 = test.create_test_user("foreman2", 2_000).await;
+    // foremen pay for the referral state accounts they create
+    let foreman_1 = test.create_test_user("foreman1", LAMPORTS_PER_SOL).await;
+    let foreman_2 = test.create_test_user("foreman2", 2 * LAMPORTS_PER_SOL).await;
     let (global_state_pubkey, admin) = create_global_state_account(
         &mut test,
         Some(foreman_1.keypair.pubkey()),
@@ -288,14 +405,13 @@ async fn test_init_referral_state_with_foreman() -> anyhow::Result<()> {
 async fn test_change_authority() -> anyhow::Result<()> {
     let (mut test, marinade_referrals, _) = IntegrationTest::init_test().await?;
 
-    // changing authority to the same as it was before
-    change_authority_execute(
+    // proposing a new admin does not change the admin yet
+    let new_admin = Arc::new(Keypair::new());
+    propose_authority_execute(
         &mut test,
         marinade_referrals.global_state_pubkey,
         marinade_referrals.admin_key.pubkey(),
-        marinade_referrals.admin_key.pubkey(),
-        solana_program::system_program::ID,
-        solana_program::sysvar::clock::ID,
+        new_admin.pubkey(),
         &marinade_referrals.admin_key,
     )
     .await
@@ -305,31 +421,31 @@ async fn test_change_authority() -> anyhow::Result<()> {
     assert_eq!(
         marinade_referrals.admin_key.pubkey(),
         global_state.admin_account,
-        "Global state admin key does not match after change authority"
+        "Global state admin key should not change before the proposal is accepted"
     );
     assert_eq!(
-        solana_program::system_program::ID,
-        global_state.foreman_1,
-        "Global state foreman_1 does not match after authority 'no change'"
-    );
-    assert_eq!(
-        solana_program::sysvar::clock::ID,
-        global_state.foreman_2,
-        "Global state foreman does not match after authority 'no change'"
+        Some(new_admin.pubkey()),
+        global_state.pending_admin,
+        "Global state pending admin does not match the proposed one"
     );
 
-    // changing authority to a new admin account
-    let new_admin = Arc::new(Keypair::new());
-    let new_foreman_1 = Pubkey::new_unique();
-    let new_foreman_2 = Pubkey::new_unique();
-    change_authority_execute(
+    // only the proposed admin may accept
+    let another_new_admin = Arc::new(Keypair::new());
+    let txn_result = accept_authority_execute(
         &mut test,
         marinade_referrals.global_state_pubkey,
-        marinade_referrals.admin_key.pubkey(),
-        new_admin.pubkey(),
-        new_foreman_1,
-        new_foreman_2,
-        &marinade_referrals.admin_key,
+        &another_new_admin,
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(323, error_number, "Expected not pending admin error"),
+        _ => panic!("Expected the transaction fails with the not pending admin error."),
+    }
+
+    accept_authority_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &new_admin,
     )
     .await
     .unwrap();
@@ -338,30 +454,23 @@ async fn test_change_authority() -> anyhow::Result<()> {
     assert_eq!(
         new_admin.pubkey(),
         global_state.admin_account,
-        "Global state admin key does not match to new admin after changing authority"
+        "Global state admin key does not match to new admin after accepting authority"
     );
+    assert_eq!(None, global_state.pending_admin);
     assert_eq!(
-        new_foreman_1,
+        solana_program::system_program::ID,
         global_state.foreman_1,
-        "Global state foreman_1 does not match after changing new admin'"
-    );
-    assert_eq!(
-        new_foreman_2,
-        global_state.foreman_2,
-        "Global state foreman_2 does not match after changing new admin'"
+        "Global state foreman_1 should not change with the admin"
     );
 
-    // changing authority to admin account that's not referred in the global state
+    // the old admin is not referred in the global state anymore
     // (correctly signed but admin account does not match the saved value)
-    let another_new_admin = Arc::new(Keypair::new());
-    let txn_result = change_authority_execute(
+    let txn_result = propose_authority_execute(
         &mut test,
         marinade_referrals.global_state_pubkey,
-        another_new_admin.pubkey(),
         marinade_referrals.admin_key.pubkey(),
-        solana_program::system_program::ID,
-        solana_program::system_program::ID,
-        &another_new_admin,
+        marinade_referrals.admin_key.pubkey(),
+        &marinade_referrals.admin_key,
     )
     .await;
     match txn_result {
@@ -370,14 +479,81 @@ async fn test_change_authority() -> anyhow::Result<()> {
         _ => panic!("Expected the transaction fails with the constraint violation."),
     }
 
+    // a cancelled proposal can not be accepted
+    propose_authority_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        new_admin.pubkey(),
+        another_new_admin.pubkey(),
+        &new_admin,
+    )
+    .await
+    .unwrap();
+    cancel_authority_proposal_execute(&mut test, marinade_referrals.global_state_pubkey, &new_admin)
+        .await
+        .unwrap();
+    let txn_result = accept_authority_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &another_new_admin,
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(323, error_number, "Expected not pending admin error"),
+        _ => panic!("Expected the transaction fails with the not pending admin error."),
+    }
+
     let global_state: marinade_referral::states::GlobalState =
         get_account(&mut test, marinade_referrals.global_state_pubkey).await;
     assert_eq!(
         new_admin.pubkey(),
         global_state.admin_account,
-        "Global state admin key does not match after the changing authority failed"
+        "Global state admin key does not match after the cancelled proposal"
+    );
+    assert_eq!(None, global_state.pending_admin);
+
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_update_foremen() -> anyhow::Result<()> {
+    let (mut test, marinade_referrals, _) = IntegrationTest::init_test().await?;
+
+    let new_foreman_1 = Pubkey::new_unique();
+    let new_foreman_2 = Pubkey::new_unique();
+    update_foremen_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        new_foreman_1,
+        new_foreman_2,
+    )
+    .await
+    .unwrap();
+    let global_state: marinade_referral::states::GlobalState =
+        get_account(&mut test, marinade_referrals.global_state_pubkey).await;
+    assert_eq!(
+        marinade_referrals.admin_key.pubkey(),
+        global_state.admin_account,
+        "Global state admin key should not change with the foremen"
     );
+    assert_eq!(new_foreman_1, global_state.foreman_1);
+    assert_eq!(new_foreman_2, global_state.foreman_2);
 
+    let saboteur = Arc::new(Keypair::new());
+    let txn_result = update_foremen_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &saboteur,
+        saboteur.pubkey(),
+        saboteur.pubkey(),
+    )
+    .await;
+    match txn_result {
+        // https://github.com/coral-xyz/anchor/blob/v0.14.0/lang/src/error.rs
+        Err(error_number) => assert_eq!(141, error_number, "A constraint should be violated"),
+        _ => panic!("Expected the transaction fails with the constraint violation."),
+    }
     Ok(())
 }
 
@@ -393,18 +569,35 @@ async fn test_update_referral() -> anyhow::Result<()> {
         marinade_referrals.partner_referral_state_pubkey,
         marinade_referrals.partner.keypair.pubkey(),
         marinade_referrals.msol_partner_token_pubkey,
-        true,
+        PAUSE_ALL_OPERATIONS | PAUSE_LIQUID_UNSTAKE,
     )
     .await
     .unwrap();
     let referral_state: marinade_referral::states::ReferralState =
         get_account(&mut test, marinade_referrals.partner_referral_state_pubkey).await;
-    assert!(
+    assert_eq!(
+        PAUSE_ALL_OPERATIONS | PAUSE_LIQUID_UNSTAKE,
         referral_state.pause,
-        "Referral state update 'pause' value should be true",
+        "Referral state update 'pause' value should be set",
     );
 
-    // updating with optional values
+    // only the known operation bits
+    let txn_result = update_referral_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_referrals.partner_referral_state_pubkey,
+        marinade_referrals.partner.keypair.pubkey(),
+        marinade_referrals.msol_partner_token_pubkey,
+        1 << 7,
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(336, error_number, "Expected unknown pause bits error"),
+        _ => panic!("Expected the transaction fails with the unknown pause bits error."),
+    }
+
+    // the referral state is the PDA of its partner, the partner can't be changed
     let new_partner = test
         .create_test_user("test_referral_partner", LAMPORTS_PER_SOL)
         .await;
@@ -413,31 +606,50 @@ async fn test_update_referral() -> anyhow::Result<()> {
         .get_or_create_msol_account_instruction(&mut test)
         .await;
     test.execute().await; // execute if the ATA needed to be created
-    update_referral_execute(
+    let txn_result = update_referral_execute(
         &mut test,
         marinade_referrals.global_state_pubkey,
         &marinade_referrals.admin_key,
         marinade_referrals.partner_referral_state_pubkey,
         new_partner.keypair.pubkey(),
         new_token_partner_account.pubkey,
-        false,
+        0,
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => {
+            assert_eq!(324, error_number, "Expected partner account change error")
+        }
+        _ => panic!("Expected the transaction fails with the partner account change error."),
+    }
 
+    // another mSOL token account of the same partner
+    let new_msol_token_partner_account =
+        create_msol_token_account(&mut test, &marinade_referrals.partner.keypair.pubkey()).await;
+    update_referral_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_referrals.partner_referral_state_pubkey,
+        marinade_referrals.partner.keypair.pubkey(),
+        new_msol_token_partner_account,
+        0,
     )
     .await
     .unwrap();
     let referral_state: marinade_referral::states::ReferralState =
         get_account(&mut test, marinade_referrals.partner_referral_state_pubkey).await;
-    assert!(
-        !referral_state.pause,
-        "Referral state update 'pause' value should be false",
+    assert_eq!(
+        0, referral_state.pause,
+        "Referral state update 'pause' value should be 0",
     );
     assert_eq!(
-        new_partner.keypair.pubkey(),
+        marinade_referrals.partner.keypair.pubkey(),
         referral_state.partner_account,
-        "Referral state update 'partner account' should be changed",
+        "Referral state 'partner account' should not be changed",
     );
     assert_eq!(
-        new_token_partner_account.pubkey, referral_state.msol_token_partner_account,
+        new_msol_token_partner_account, referral_state.msol_token_partner_account,
         "Referral state update 'msol token partner account' should be changed",
     );
 
@@ -456,7 +668,9 @@ async fn test_update_operation_fees() -> anyhow::Result<()> {
         Some(31),
         Some(32),
         Some(33),
-        Some(MAX_OPERATION_FEE_POINTS as u8),
+        Some(DEFAULT_MAX_OPERATION_FEE_POINTS),
+        None,
+        None,
     )
     .await
     .unwrap();
@@ -476,7 +690,7 @@ async fn test_update_operation_fees() -> anyhow::Result<()> {
         "Referral state update 'partner account' should be changed",
     );
     assert_eq!(
-        MAX_OPERATION_FEE_POINTS, referral_state.operation_delayed_unstake_fee,
+        DEFAULT_MAX_OPERATION_FEE_POINTS, referral_state.operation_delayed_unstake_fee,
         "Referral state update 'partner account' should be changed",
     );
 
@@ -486,7 +700,9 @@ async fn test_update_operation_fees() -> anyhow::Result<()> {
         &marinade_referrals.admin_key,
         marinade_referrals.partner_referral_state_pubkey,
         None,
-        Some(MAX_OPERATION_FEE_POINTS as u8 + 1),
+        Some(DEFAULT_MAX_OPERATION_FEE_POINTS + 1),
+        None,
+        None,
         None,
         None,
     )
@@ -540,6 +756,8 @@ async fn test_update_operation_fee_with_foreman() -> anyhow::Result<()> {
         Some(2),
         Some(3),
         Some(4),
+        None,
+        None,
     )
     .await.unwrap();
     let referral_state: marinade_referral::states::ReferralState =
@@ -561,6 +779,8 @@ async fn test_update_operation_fee_with_foreman() -> anyhow::Result<()> {
         Some(12),
         Some(13),
         Some(14),
+        None,
+        None,
     )
     .await.unwrap();
     let referral_state: marinade_referral::states::ReferralState =
@@ -584,6 +804,8 @@ async fn test_update_operation_fee_with_foreman() -> anyhow::Result<()> {
         Some(0),
         Some(0),
         Some(0),
+        None,
+        None,
     )
     .await;
     match txn_result {
@@ -596,3 +818,1040 @@ async fn test_update_operation_fee_with_foreman() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test(tokio::test)]
+async fn test_update_operation_fee_caps() -> anyhow::Result<()> {
+    let (mut test, marinade_referrals, _) = IntegrationTest::init_test().await?;
+
+    // only admin can change the caps
+    let txn_result = update_operation_fee_caps_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.partner.keypair,
+        Some(100),
+        None,
+        None,
+        None,
+        None,
+        None,
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(141, error_number, "Expected has_one constraint error"),
+        _ => panic!("Expected the transaction fails with the has_one constraint violation."),
+    }
+
+    // cap cannot be over 100%
+    let txn_result = update_operation_fee_caps_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        Some(10_001),
+        None,
+        None,
+        None,
+        None,
+        None,
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(329, error_number, "Expected fee cap over max error"),
+        _ => panic!("Expected the transaction fails with the fee cap over max error."),
+    }
+
+    update_operation_fee_caps_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        Some(100),
+        None,
+        None,
+        None,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    let global_state: marinade_referral::states::GlobalState =
+        get_account(&mut test, marinade_referrals.global_state_pubkey).await;
+    assert_eq!(100, global_state.max_operation_deposit_sol_fee);
+    assert_eq!(
+        DEFAULT_MAX_OPERATION_FEE_POINTS,
+        global_state.max_operation_deposit_stake_account_fee
+    );
+
+    // fee over the default cap is accepted with the raised cap
+    update_operation_fees(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_referrals.partner_referral_state_pubkey,
+        Some(100),
+        None,
+        None,
+        None,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, marinade_referrals.partner_referral_state_pubkey).await;
+    assert_eq!(100, referral_state.operation_deposit_sol_fee);
+
+    let txn_result = update_operation_fees(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_referrals.partner_referral_state_pubkey,
+        Some(101),
+        None,
+        None,
+        None,
+        None,
+        None,
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(
+            307, error_number,
+            "Constraint fee over max should be violated"
+        ),
+        _ => panic!("Expected the transaction fails with the constraint violation."),
+    }
+
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_update_collateral_config() -> anyhow::Result<()> {
+    let (mut test, marinade_referrals, _) = IntegrationTest::init_test().await?;
+
+    // stake-as-collateral partner
+    let collateral_partner = test
+        .create_test_user("collateral_partner", LAMPORTS_PER_SOL)
+        .await;
+    let collateral_partner_msol = collateral_partner
+        .get_or_create_msol_account_instruction(&mut test)
+        .await;
+    test.execute().await;
+    let validator_vote = Pubkey::new_unique();
+    let collateral_referral_state = init_referral_account_execute(
+        &mut test,
+        collateral_partner.keypair.pubkey(),
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        collateral_partner_msol.pubkey,
+        Some(validator_vote),
+        20,
+    )
+    .await
+    .unwrap();
+
+    // only admin or foremen
+    let new_validator_vote = Pubkey::new_unique();
+    let txn_result = update_collateral_config_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &collateral_partner.keypair,
+        collateral_referral_state,
+        new_validator_vote,
+        50,
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(143, error_number, "Expected raw constraint error"),
+        _ => panic!("Expected the transaction fails with the raw constraint violation."),
+    }
+
+    // only for stake-as-collateral partners
+    let txn_result = update_collateral_config_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_referrals.partner_referral_state_pubkey,
+        new_validator_vote,
+        50,
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(314, error_number, "Expected collateral partner error"),
+        _ => panic!("Expected the transaction fails with the collateral partner error."),
+    }
+
+    // keep_pct within the global range 10-90
+    let txn_result = update_collateral_config_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        collateral_referral_state,
+        new_validator_vote,
+        95,
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(309, error_number, "Expected keep_pct out of range error"),
+        _ => panic!("Expected the transaction fails with the keep_pct out of range error."),
+    }
+
+    update_collateral_config_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        collateral_referral_state,
+        new_validator_vote,
+        50,
+    )
+    .await
+    .unwrap();
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, collateral_referral_state).await;
+    assert_eq!(Some(new_validator_vote), referral_state.validator_vote_key);
+    assert_eq!(50, referral_state.keep_self_stake_pct);
+
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_update_marinade_state() -> anyhow::Result<()> {
+    let (mut test, marinade_referrals, _) = IntegrationTest::init_test().await?;
+    let global_state: marinade_referral::states::GlobalState =
+        get_account(&mut test, marinade_referrals.global_state_pubkey).await;
+    assert_eq!(test.state.key, global_state.marinade_state);
+
+    // only admin
+    let marinade_state = test.state.key;
+    let txn_result = update_marinade_state_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.partner.keypair,
+        marinade_state,
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(141, error_number, "Expected has_one constraint error"),
+        _ => panic!("Expected the transaction fails with the has_one constraint violation."),
+    }
+
+    // only a Marinade state
+    let txn_result = update_marinade_state_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_referrals.msol_partner_token_pubkey,
+    )
+    .await;
+    assert!(
+        txn_result.is_err(),
+        "Expected a token account is rejected as Marinade state"
+    );
+
+    update_marinade_state_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_state,
+    )
+    .await
+    .unwrap();
+    let global_state: marinade_referral::states::GlobalState =
+        get_account(&mut test, marinade_referrals.global_state_pubkey).await;
+    assert_eq!(marinade_state, global_state.marinade_state);
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_update_global_keep_range() -> anyhow::Result<()> {
+    let (mut test, marinade_referrals, _) = IntegrationTest::init_test().await?;
+
+    // only admin
+    let txn_result = update_global_keep_range_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.partner.keypair,
+        5,
+        95,
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(141, error_number, "Expected has_one constraint error"),
+        _ => panic!("Expected the transaction fails with the has_one constraint violation."),
+    }
+
+    // same checks as at initialization
+    let txn_result = update_global_keep_range_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        60,
+        50,
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(311, error_number, "Expected min/max keep_pct error"),
+        _ => panic!("Expected the transaction fails with the min/max keep_pct error."),
+    }
+    let txn_result = update_global_keep_range_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        5,
+        101,
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(310, error_number, "Expected max keep_pct error"),
+        _ => panic!("Expected the transaction fails with the max keep_pct error."),
+    }
+
+    update_global_keep_range_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        5,
+        95,
+    )
+    .await
+    .unwrap();
+    let global_state: marinade_referral::states::GlobalState =
+        get_account(&mut test, marinade_referrals.global_state_pubkey).await;
+    assert_eq!(5, global_state.min_keep_pct);
+    assert_eq!(95, global_state.max_keep_pct);
+
+    // new partners are registered within the new range
+    let collateral_partner = test
+        .create_test_user("collateral_partner", LAMPORTS_PER_SOL)
+        .await;
+    let collateral_partner_msol = collateral_partner
+        .get_or_create_msol_account_instruction(&mut test)
+        .await;
+    test.execute().await;
+    let collateral_referral_state = init_referral_account_execute(
+        &mut test,
+        collateral_partner.keypair.pubkey(),
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        collateral_partner_msol.pubkey,
+        Some(Pubkey::new_unique()),
+        95,
+    )
+    .await
+    .unwrap();
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, collateral_referral_state).await;
+    assert_eq!(95, referral_state.keep_self_stake_pct);
+
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_update_fee_beneficiaries() -> anyhow::Result<()> {
+    let (mut test, marinade_referrals, _) = IntegrationTest::init_test().await?;
+    let sub_partner = test.create_test_user("sub_partner", LAMPORTS_PER_SOL).await;
+    let sub_partner_msol_account = sub_partner
+        .get_or_create_msol_account_instruction(&mut test)
+        .await;
+    test.execute().await;
+    let sub_partner_lp_account = sub_partner.get_or_create_lp_token_account(&mut test).await;
+    test.execute().await;
+    let beneficiary = |msol_token_account: Pubkey, fee_share: u16| FeeBeneficiary {
+        msol_token_account,
+        fee_share,
+    };
+
+    // only admin can set the beneficiaries
+    let txn_result = update_fee_beneficiaries_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.partner.keypair,
+        marinade_referrals.partner_referral_state_pubkey,
+        vec![beneficiary(sub_partner_msol_account.pubkey, 1_000)],
+        &[sub_partner_msol_account.pubkey],
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(141, error_number, "Expected has_one constraint error"),
+        _ => panic!("Expected the transaction fails with the has_one constraint violation."),
+    }
+
+    // shares cannot go over 100%
+    let txn_result = update_fee_beneficiaries_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_referrals.partner_referral_state_pubkey,
+        vec![beneficiary(sub_partner_msol_account.pubkey, 10_001)],
+        &[sub_partner_msol_account.pubkey],
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(330, error_number, "Expected invalid beneficiaries error"),
+        _ => panic!("Expected the transaction fails with the invalid beneficiaries error."),
+    }
+
+    // token accounts have to be passed as remaining accounts
+    let txn_result = update_fee_beneficiaries_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_referrals.partner_referral_state_pubkey,
+        vec![beneficiary(sub_partner_msol_account.pubkey, 1_000)],
+        &[],
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(331, error_number, "Expected beneficiary accounts error"),
+        _ => panic!("Expected the transaction fails with the beneficiary accounts error."),
+    }
+
+    // only mSOL token accounts
+    let txn_result = update_fee_beneficiaries_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_referrals.partner_referral_state_pubkey,
+        vec![beneficiary(sub_partner_lp_account.pubkey, 1_000)],
+        &[sub_partner_lp_account.pubkey],
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(304, error_number, "Expected invalid mint error"),
+        _ => panic!("Expected the transaction fails with the invalid mint error."),
+    }
+
+    update_fee_beneficiaries_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_referrals.partner_referral_state_pubkey,
+        vec![beneficiary(sub_partner_msol_account.pubkey, 1_000)],
+        &[sub_partner_msol_account.pubkey],
+    )
+    .await
+    .unwrap();
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, marinade_referrals.partner_referral_state_pubkey).await;
+    assert_eq!(
+        vec![beneficiary(sub_partner_msol_account.pubkey, 1_000)],
+        referral_state.active_fee_beneficiaries()
+    );
+
+    // empty list removes the beneficiaries
+    update_fee_beneficiaries_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_referrals.partner_referral_state_pubkey,
+        vec![],
+        &[],
+    )
+    .await
+    .unwrap();
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, marinade_referrals.partner_referral_state_pubkey).await;
+    assert!(referral_state.active_fee_beneficiaries().is_empty());
+
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_settle_period() -> anyhow::Result<()> {
+    let (mut test, marinade_referrals, mut rng) = IntegrationTest::init_test().await?;
+    let mut user = test
+        .create_test_user("test_dep_sol_user", 200 * LAMPORTS_PER_SOL)
+        .await;
+    let lamports = random_amount(1, 100, &mut rng);
+    do_deposit_sol(&mut user, lamports, &mut test, &marinade_referrals, 10)
+        .await
+        .unwrap();
+    let referral_state_before: marinade_referral::states::ReferralState =
+        get_account(&mut test, marinade_referrals.partner_referral_state_pubkey).await;
+
+    let period_snapshot_pubkey = settle_period_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_referrals.partner_referral_state_pubkey,
+    )
+    .await
+    .unwrap();
+
+    let period_snapshot: marinade_referral::states::PeriodSnapshot =
+        get_account(&mut test, period_snapshot_pubkey).await;
+    assert_eq!(
+        marinade_referrals.partner_referral_state_pubkey,
+        period_snapshot.referral_state
+    );
+    assert_eq!(test.get_clock().await.epoch, period_snapshot.epoch);
+    assert_eq!(lamports as u128, period_snapshot.deposit_sol_amount);
+    assert_eq!(1, period_snapshot.deposit_sol_operations);
+    assert_eq!(
+        referral_state_before.accum_deposit_sol_fee,
+        period_snapshot.accum_deposit_sol_fee
+    );
+    assert_eq!(
+        referral_state_before.get_liq_unstake_share_amount().unwrap(),
+        period_snapshot.liq_unstake_share_amount
+    );
+
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, marinade_referrals.partner_referral_state_pubkey).await;
+    assert_eq!(0, referral_state.deposit_sol_amount);
+    assert_eq!(0, referral_state.deposit_sol_operations);
+    assert_eq!(0, referral_state.accum_deposit_sol_fee);
+    assert_eq!(0, referral_state.delayed_unstake_amount);
+    assert_eq!(0, referral_state.delayed_unstake_operations);
+    // configuration is kept
+    assert_eq!(10, referral_state.operation_deposit_sol_fee);
+
+    // only the admin can settle a period
+    let saboteur = test.create_test_user("saboteur", LAMPORTS_PER_SOL).await;
+    let txn_result = settle_period_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &saboteur.keypair,
+        marinade_referrals.partner_referral_state_pubkey,
+    )
+    .await;
+    match txn_result {
+        // https://github.com/coral-xyz/anchor/blob/v0.14.0/lang/src/error.rs
+        Err(error_number) => assert_eq!(141, error_number, "A has_one constraint should be violated"),
+        _ => panic!("Expected the transaction fails with the constraint violation."),
+    }
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_update_reward_curve() -> anyhow::Result<()> {
+    let (mut test, marinade_referrals, _) = IntegrationTest::init_test().await?;
+
+    update_reward_curve_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_referrals.partner_referral_state_pubkey,
+        500,
+        5_000,
+        50_000 * LAMPORTS_PER_SOL,
+    )
+    .await
+    .unwrap();
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, marinade_referrals.partner_referral_state_pubkey).await;
+    assert_eq!(500, referral_state.base_fee);
+    assert_eq!(5_000, referral_state.max_fee);
+    assert_eq!(50_000 * LAMPORTS_PER_SOL, referral_state.max_net_stake);
+
+    // base fee over max fee
+    let txn_result = update_reward_curve_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_referrals.partner_referral_state_pubkey,
+        5_001,
+        5_000,
+        50_000 * LAMPORTS_PER_SOL,
+    )
+    .await;
+    assert_eq!(Err(321), txn_result, "Expected reward curve fee out of range");
+    // max fee over 100%
+    let txn_result = update_reward_curve_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_referrals.partner_referral_state_pubkey,
+        0,
+        10_001,
+        50_000 * LAMPORTS_PER_SOL,
+    )
+    .await;
+    assert_eq!(Err(321), txn_result, "Expected reward curve fee out of range");
+    // zero net stake target
+    let txn_result = update_reward_curve_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_referrals.partner_referral_state_pubkey,
+        0,
+        10_000,
+        0,
+    )
+    .await;
+    assert_eq!(Err(322), txn_result, "Expected reward curve zero net stake");
+
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, marinade_referrals.partner_referral_state_pubkey).await;
+    assert_eq!(
+        (500, 5_000, 50_000 * LAMPORTS_PER_SOL),
+        (
+            referral_state.base_fee,
+            referral_state.max_fee,
+            referral_state.max_net_stake
+        ),
+        "Check that reward curve has not changed after wrong update failed",
+    );
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_close_referral_account() -> anyhow::Result<()> {
+    let (mut test, marinade_referrals, _) = IntegrationTest::init_test().await?;
+    generate_liquid_unstake_fees(&mut test, &marinade_referrals).await;
+    let rent_destination = Pubkey::new_unique();
+
+    // an active partner can't be closed
+    let txn_result = close_referral_account_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_referrals.partner_referral_state_pubkey,
+        rent_destination,
+        false,
+    )
+    .await;
+    match txn_result {
+        // https://github.com/coral-xyz/anchor/blob/v0.14.0/lang/src/error.rs
+        Err(error_number) => assert_eq!(143, error_number, "Expected not paused constraint violated"),
+        _ => panic!("Expected the transaction fails with the constraint violation."),
+    }
+
+    marinade_referrals.pause_referral_account(&mut test).await;
+    let referral_state_before: marinade_referral::states::ReferralState =
+        get_account(&mut test, marinade_referrals.partner_referral_state_pubkey).await;
+    assert!(referral_state_before.liq_unstake_msol_fees > 0);
+
+    // liquid-unstake fees were neither settled nor claimed
+    let txn_result = close_referral_account_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_referrals.partner_referral_state_pubkey,
+        rent_destination,
+        true,
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(328, error_number, "Expected referral not settled error"),
+        _ => panic!("Expected the transaction fails with the referral not settled error."),
+    }
+
+    let referral_state_lamports = test
+        .get_sol_balance(&marinade_referrals.partner_referral_state_pubkey)
+        .await;
+    let period_snapshot_pubkey = close_referral_account_execute(
+        &mut test,
+        marinade_referrals.global_state_pubkey,
+        &marinade_referrals.admin_key,
+        marinade_referrals.partner_referral_state_pubkey,
+        rent_destination,
+        false,
+    )
+    .await
+    .unwrap();
+
+    // final snapshot keeps the accumulators
+    let period_snapshot: marinade_referral::states::PeriodSnapshot =
+        get_account(&mut test, period_snapshot_pubkey).await;
+    assert_eq!(
+        marinade_referrals.partner_referral_state_pubkey,
+        period_snapshot.referral_state
+    );
+    assert_eq!(
+        referral_state_before.liq_unstake_msol_fees,
+        period_snapshot.liq_unstake_msol_fees
+    );
+    assert_eq!(
+        referral_state_before.deposit_sol_amount,
+        period_snapshot.deposit_sol_amount
+    );
+
+    // referral state is gone, its rent went to the destination
+    let referral_account = test
+        .context
+        .banks_client
+        .get_account(marinade_referrals.partner_referral_state_pubkey)
+        .await?;
+    assert!(referral_account.is_none(), "Referral state should be closed");
+    assert_eq!(
+        referral_state_lamports,
+        test.get_sol_balance(&rent_destination).await
+    );
+    Ok(())
+}
+
+fn v0_2_referral_state(partner: Pubkey, msol_token_partner_account: Pubkey) -> ReferralStateV0_2 {
+    ReferralStateV0_2 {
+        partner_name: "OLD_PART".into(),
+        validator_vote_key: None,
+        keep_self_stake_pct: 0,
+        partner_account: partner,
+        msol_token_partner_account,
+        deposit_sol_amount: 10 * LAMPORTS_PER_SOL,
+        deposit_sol_operations: 3,
+        deposit_stake_account_amount: 5 * LAMPORTS_PER_SOL,
+        deposit_stake_account_operations: 1,
+        liq_unstake_msol_fees: 1_000_000,
+        liq_unstake_sol_amount: 2 * LAMPORTS_PER_SOL,
+        liq_unstake_msol_amount: 2 * LAMPORTS_PER_SOL - 1_000,
+        liq_unstake_operations: 2,
+        delayed_unstake_amount: LAMPORTS_PER_SOL,
+        delayed_unstake_operations: 1,
+        base_fee: DEFAULT_BASE_FEE_POINTS,
+        max_fee: DEFAULT_MAX_FEE_POINTS,
+        max_net_stake: DEFAULT_MAX_NET_STAKE,
+        pause: true,
+        operation_deposit_sol_fee: 1,
+        operation_deposit_stake_account_fee: 2,
+        operation_liquid_unstake_fee: 3,
+        operation_delayed_unstake_fee: 4,
+        accum_deposit_sol_fee: 100,
+        accum_deposit_stake_account_fee: 200,
+        accum_liquid_unstake_fee: 300,
+        accum_delayed_unstake_fee: 400,
+    }
+}
+
+fn migrate_referral_state_instruction(
+    global_state: Pubkey,
+    admin: Pubkey,
+    old_referral_state: Pubkey,
+    partner: Pubkey,
+) -> Instruction {
+    let (referral_state, bump) = marinade_referral::states::ReferralState::find_address(&partner);
+    let accounts = marinade_referral::accounts::MigrateReferralState {
+        global_state,
+        admin_account: admin,
+        old_referral_state,
+        referral_state,
+        partner_account: partner,
+        system_program: solana_program::system_program::ID,
+    };
+    let ix_data = marinade_referral::instruction::MigrateReferralState { bump };
+    Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    }
+}
+
+#[test(tokio::test)]
+async fn test_migrate_v0_2_referral_state() -> anyhow::Result<()> {
+    // v0.2 referral account as created by the previous program version (keypair account)
+    let partner_key = Keypair::new();
+    let partner_msol_account = Pubkey::new_unique();
+    let old_referral_state_pubkey = Pubkey::new_unique();
+    let old_referral_state = v0_2_referral_state(partner_key.pubkey(), partner_msol_account);
+    let old_account = v0_2_referral_state_account(&old_referral_state);
+    let old_lamports = old_account.lamports;
+
+    let mut rng = ChaChaRng::from_seed(rand::random());
+    let input = InitializeInputWithSeeds::random(&mut rng);
+    let mut test = IntegrationTest::start_with_accounts(
+        &input,
+        vec![(old_referral_state_pubkey, old_account)],
+    )
+    .await?;
+    let (global_state_pubkey, admin) = create_global_state_account(&mut test, None, None).await;
+    let partner = test
+        .create_test_user_from_keypair("partner", LAMPORTS_PER_SOL, partner_key)
+        .await;
+
+    // the new referral state has to be derived from the partner of the old one
+    let saboteur = Pubkey::new_unique();
+    let txn_result = test
+        .try_execute_instruction(
+            migrate_referral_state_instruction(
+                global_state_pubkey,
+                admin.keypair.pubkey(),
+                old_referral_state_pubkey,
+                saboteur,
+            ),
+            vec![test.fee_payer_signer(), admin.keypair.clone()],
+        )
+        .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(327, error_number, "Expected partner mismatch error"),
+        _ => panic!("Expected the transaction fails with the partner mismatch error."),
+    }
+
+    let admin_lamports_before = admin.sol_balance(&mut test).await;
+    test.try_execute_instruction(
+        migrate_referral_state_instruction(
+            global_state_pubkey,
+            admin.keypair.pubkey(),
+            old_referral_state_pubkey,
+            partner.keypair.pubkey(),
+        ),
+        vec![test.fee_payer_signer(), admin.keypair.clone()],
+    )
+    .await
+    .unwrap();
+
+    // accumulated data survives the migration
+    let (referral_state_pubkey, referral_state_bump) =
+        marinade_referral::states::ReferralState::find_address(&partner.keypair.pubkey());
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, referral_state_pubkey).await;
+    assert_eq!(REFERRAL_STATE_VERSION, referral_state.version);
+    assert_eq!(referral_state_bump, referral_state.bump);
+    assert_eq!(old_referral_state.partner_name, referral_state.partner_name);
+    assert_eq!(partner.keypair.pubkey(), referral_state.partner_account);
+    assert_eq!(partner_msol_account, referral_state.msol_token_partner_account);
+    assert_eq!(
+        old_referral_state.deposit_sol_amount as u128,
+        referral_state.deposit_sol_amount
+    );
+    assert_eq!(
+        old_referral_state.deposit_sol_operations,
+        referral_state.deposit_sol_operations
+    );
+    assert_eq!(
+        old_referral_state.deposit_stake_account_amount as u128,
+        referral_state.deposit_stake_account_amount
+    );
+    assert_eq!(
+        old_referral_state.liq_unstake_msol_fees,
+        referral_state.liq_unstake_msol_fees
+    );
+    assert_eq!(
+        old_referral_state.liq_unstake_sol_amount as u128,
+        referral_state.liq_unstake_sol_amount
+    );
+    assert_eq!(
+        old_referral_state.liq_unstake_msol_amount as u128,
+        referral_state.liq_unstake_msol_amount
+    );
+    assert_eq!(
+        old_referral_state.delayed_unstake_amount as u128,
+        referral_state.delayed_unstake_amount
+    );
+    // the former pause flag pauses all operations
+    assert!(old_referral_state.pause);
+    assert_eq!(PAUSE_ALL_OPERATIONS, referral_state.pause);
+    assert_eq!(
+        old_referral_state.operation_delayed_unstake_fee as u16,
+        referral_state.operation_delayed_unstake_fee
+    );
+    assert_eq!(
+        old_referral_state.accum_liquid_unstake_fee,
+        referral_state.accum_liquid_unstake_fee
+    );
+    assert_eq!(
+        DEFAULT_OPERATION_FEE_POINTS,
+        referral_state.operation_add_liquidity_fee
+    );
+    assert_eq!(0, referral_state.claimed_liq_unstake_share);
+    // the v0.2 deposits were never reset, they count toward the lifetime totals
+    assert_eq!(
+        old_referral_state.deposit_stake_account_amount as u128,
+        referral_state.total_deposit_stake_account_amount
+    );
+    assert_eq!(
+        old_referral_state.deposit_sol_amount as u128
+            + old_referral_state.deposit_stake_account_amount as u128,
+        referral_state.total_deposit_amount
+    );
+
+    // the old account is closed and its rent returned to the admin
+    let old_account = test
+        .context
+        .banks_client
+        .get_account(old_referral_state_pubkey)
+        .await?;
+    assert!(old_account.is_none(), "Old referral state should be closed");
+    let referral_state_lamports = test.get_sol_balance(&referral_state_pubkey).await;
+    assert_eq!(
+        admin_lamports_before + old_lamports - referral_state_lamports,
+        admin.sol_balance(&mut test).await,
+        "Admin pays the new referral state and receives the old one's rent"
+    );
+
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_migrated_referral_state_deposit_cap() -> anyhow::Result<()> {
+    let mut rng = ChaChaRng::from_seed(rand::random());
+    let input = InitializeInputWithSeeds::random(&mut rng);
+    let partner_key = Keypair::new();
+    let partner_msol_account =
+        get_associated_token_address(&partner_key.pubkey(), &input.msol_mint.pubkey());
+    let old_referral_state_pubkey = Pubkey::new_unique();
+    let mut old_referral_state = v0_2_referral_state(partner_key.pubkey(), partner_msol_account);
+    old_referral_state.pause = false;
+    let mut test = IntegrationTest::start_with_accounts(
+        &input,
+        vec![(
+            old_referral_state_pubkey,
+            v0_2_referral_state_account(&old_referral_state),
+        )],
+    )
+    .await?;
+    let (global_state_pubkey, admin) = create_global_state_account(&mut test, None, None).await;
+    let partner = test
+        .create_test_user_from_keypair("partner", LAMPORTS_PER_SOL, partner_key)
+        .await;
+    partner.get_or_create_msol_account_instruction(&mut test).await;
+    test.execute().await;
+    test.try_execute_instruction(
+        migrate_referral_state_instruction(
+            global_state_pubkey,
+            admin.keypair.pubkey(),
+            old_referral_state_pubkey,
+            partner.keypair.pubkey(),
+        ),
+        vec![test.fee_payer_signer(), admin.keypair.clone()],
+    )
+    .await
+    .unwrap();
+    let (referral_state_pubkey, _) =
+        marinade_referral::states::ReferralState::find_address(&partner.keypair.pubkey());
+
+    // the deposits made before the migration count toward the total cap
+    let old_deposits =
+        old_referral_state.deposit_sol_amount + old_referral_state.deposit_stake_account_amount;
+    update_deposit_caps_execute(
+        &mut test,
+        global_state_pubkey,
+        &admin.keypair,
+        referral_state_pubkey,
+        old_deposits + LAMPORTS_PER_SOL,
+        0,
+    )
+    .await
+    .unwrap();
+    let mut user = test
+        .create_test_user("test_dep_sol_user", 200 * LAMPORTS_PER_SOL)
+        .await;
+    let user_msol_account = user.get_or_create_msol_account_instruction(&mut test).await;
+    test.execute().await;
+    let marinade_instance_state = test.state.key;
+    let depositor = user.keypair.pubkey();
+    for &(lamports, expected_error) in [(2 * LAMPORTS_PER_SOL, Some(341)), (LAMPORTS_PER_SOL, None)]
+        .iter()
+    {
+        let deposit_result = try_deposit_execute(
+            &mut test,
+            &mut user,
+            marinade_instance_state,
+            depositor,
+            user_msol_account.pubkey,
+            referral_state_pubkey,
+            partner_msol_account,
+            lamports,
+            &[],
+        )
+        .await;
+        match (deposit_result, expected_error) {
+            (Err(number), Some(error)) => {
+                assert_eq!(error, number, "Expected the deposit cap error")
+            }
+            (Ok(()), None) => {}
+            (result, _) => panic!("Unexpected deposit result {:?}", result),
+        }
+    }
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, referral_state_pubkey).await;
+    assert_eq!(
+        (old_deposits + LAMPORTS_PER_SOL) as u128,
+        referral_state.total_deposit_amount
+    );
+    Ok(())
+}
+
+// v0.2 referral account as created by the previous program version (keypair account),
+// the v0.2 clients allocated the space from the in-memory size of the layout
+fn v0_2_referral_state_account(old_referral_state: &ReferralStateV0_2) -> Account {
+    let mut data = marinade_referral::states::ReferralState::discriminator().to_vec();
+    data.extend(old_referral_state.try_to_vec().unwrap());
+    data.resize(8 + 10 + std::mem::size_of::<ReferralStateV0_2>(), 0);
+    Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: marinade_referral::marinade_referral::ID,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+#[test(tokio::test)]
+async fn test_unknown_referral_state_layouts() -> anyhow::Result<()> {
+    let partner = Pubkey::new_unique();
+    // shorter than the v0.2 layout
+    let mut short_data = marinade_referral::states::ReferralState::discriminator().to_vec();
+    short_data.extend(v0_2_referral_state(partner, Pubkey::new_unique()).try_to_vec()?);
+    short_data.truncate(8 + ReferralStateV0_2::LEN - 1);
+    let short_referral_state_pubkey = Pubkey::new_unique();
+    // v0.2 size, not a referral state
+    let mut other_account = v0_2_referral_state_account(&v0_2_referral_state(
+        partner,
+        Pubkey::new_unique(),
+    ));
+    other_account.data[..8]
+        .copy_from_slice(&marinade_referral::states::GlobalState::discriminator());
+    let other_account_pubkey = Pubkey::new_unique();
+
+    let mut rng = ChaChaRng::from_seed(rand::random());
+    let input = InitializeInputWithSeeds::random(&mut rng);
+    let mut test = IntegrationTest::start_with_accounts(
+        &input,
+        vec![
+            (
+                short_referral_state_pubkey,
+                Account {
+                    lamports: Rent::default().minimum_balance(short_data.len()),
+                    data: short_data,
+                    owner: marinade_referral::marinade_referral::ID,
+                    executable: false,
+                    rent_epoch: 0,
+                },
+            ),
+            (other_account_pubkey, other_account),
+        ],
+    )
+    .await?;
+    let (global_state_pubkey, admin) = create_global_state_account(&mut test, None, None).await;
+
+    // a referral state of the current layout is already migrated
+    let current_partner = test
+        .create_test_user("current_partner", LAMPORTS_PER_SOL)
+        .await;
+    let current_partner_msol = current_partner
+        .get_or_create_msol_account_instruction(&mut test)
+        .await;
+    test.execute().await;
+    let current_referral_state_pubkey = create_referral_state_account(
+        &mut test,
+        &current_partner,
+        global_state_pubkey,
+        &admin.keypair,
+        current_partner_msol.pubkey,
+    )
+    .await
+    .unwrap();
+
+    for old_referral_state in [
+        short_referral_state_pubkey,
+        other_account_pubkey,
+        current_referral_state_pubkey,
+    ]
+    .iter()
+    {
+        let txn_result = test
+            .try_execute_instruction(
+                migrate_referral_state_instruction(
+                    global_state_pubkey,
+                    admin.keypair.pubkey(),
+                    *old_referral_state,
+                    partner,
+                ),
+                vec![test.fee_payer_signer(), admin.keypair.clone()],
+            )
+            .await;
+        match txn_result {
+            Err(error_number) => assert_eq!(326, error_number, "Expected not migratable error"),
+            _ => panic!("Expected the transaction fails with the not migratable error."),
+        }
+    }
+    Ok(())
+}
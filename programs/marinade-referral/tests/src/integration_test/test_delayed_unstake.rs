@@ -1,7 +1,14 @@
 #![allow(unused_imports)]
-use crate::{initialize::InitializeInputWithSeeds, integration_test::IntegrationTest};
+use crate::{
+    initialize::InitializeInputWithSeeds,
+    integration_test::{
+        create_referral_state_account, get_account, update_operation_fees, IntegrationTest,
+        MarinadeReferralTestGlobals,
+    },
+};
 
 use marinade_finance_offchain_sdk::anchor_lang::solana_program::native_token::LAMPORTS_PER_SOL;
+use marinade_finance_offchain_sdk::anchor_lang::{InstructionData, ToAccountMetas};
 use marinade_finance_offchain_sdk::marinade_finance;
 use marinade_finance_offchain_sdk::spl_associated_token_account::get_associated_token_address;
 use marinade_finance_offchain_sdk::{
@@ -14,7 +21,9 @@ use solana_sdk::{
     native_token::sol_to_lamports,
     pubkey::Pubkey,
     signature::{Keypair, Signer},
-    system_instruction,
+    instruction::Instruction,
+    system_instruction, system_program,
+    sysvar::{clock, rent},
     transaction::Transaction,
 };
 use std::sync::Arc;
@@ -320,3 +329,460 @@ async fn test_claim_ticket() -> anyhow::Result<()> {
     do_claim(&mut params, &mut test).await;
     Ok(())
 }
+
+pub async fn try_referral_order_unstake(
+    params: &DelayedUnstakeParams,
+    test: &mut IntegrationTest,
+    referral_state: Pubkey,
+    msol_token_partner_account: Pubkey,
+) -> Result<(), u32> {
+    let (ticket_record, ticket_record_bump) =
+        marinade_referral::states::TicketRecord::find_address(&params.ticket_account.pubkey());
+    let accounts = marinade_referral::accounts::OrderUnstake {
+        state: test.state.key,
+        msol_mint: test.state.msol_mint,
+        burn_msol_from: params.user_msol_address,
+        burn_msol_authority: params.user_sol.pubkey(),
+        new_ticket_account: params.ticket_account.pubkey(),
+        clock: clock::id(),
+        rent: rent::id(),
+        token_program: spl_token::ID,
+        //----
+        marinade_finance_program: marinade_finance::ID,
+        global_state: marinade_referral::states::GlobalState::find_address().0,
+        referral_state,
+        msol_token_partner_account,
+        ticket_record,
+        system_program: system_program::ID,
+    };
+    let ix_data = marinade_referral::instruction::OrderUnstake {
+        msol_amount: params.msol_burn_amount,
+        ticket_record_bump,
+    };
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    println!("marinade-referral order_unstake");
+    test.try_execute_instruction(
+        instruction,
+        vec![test.fee_payer_signer(), params.user_sol.clone()],
+    )
+    .await
+}
+
+// prepares user mSOL and an empty ticket account, then calls order_unstake through the referral program
+pub async fn do_referral_order_unstake(
+    params: &mut DelayedUnstakeParams,
+    test: &mut IntegrationTest,
+    marinade_referral_test_globals: &MarinadeReferralTestGlobals,
+) -> Result<(), u32> {
+    test.builder
+        .transfer_lamports(
+            test.fee_payer_signer(),
+            &params.user_sol.pubkey(),
+            params.user_sol_initial_balance,
+            "fee payer",
+            "user SOL",
+        )
+        .unwrap();
+    test.builder
+        .create_associated_token_account(
+            &params.user_sol.pubkey(),
+            &test.state.msol_mint,
+            "user mSOL",
+        )
+        .unwrap();
+    test.execute().await;
+
+    test.builder.deposit(
+        &test.state,
+        params.user_sol.clone(),
+        params.user_msol_address,
+        params.initial_stake_amount,
+    );
+    test.execute().await;
+
+    const TICKET_ACCOUNT_SPACE: usize = 8 + std::mem::size_of::<TicketAccountData>();
+    params.ticket_account_rent_exempt_lamports = test.rent.minimum_balance(TICKET_ACCOUNT_SPACE);
+    test.builder
+        .create_account(
+            params.ticket_account.clone(),
+            TICKET_ACCOUNT_SPACE,
+            &marinade_finance::ID,
+            &test.rent,
+            "ticket-account",
+        )
+        .unwrap();
+    test.execute().await;
+
+    try_referral_order_unstake(
+        params,
+        test,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        marinade_referral_test_globals.msol_partner_token_pubkey,
+    )
+    .await
+}
+
+#[test(tokio::test)]
+async fn test_referral_order_unstake() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+
+    let operation_fee: u16 = 20;
+    update_operation_fees(
+        &mut test,
+        marinade_referral_test_globals.global_state_pubkey,
+        &marinade_referral_test_globals.admin_key,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        None,
+        None,
+        None,
+        Some(operation_fee),
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    let partner_msol_balance_before = test
+        .get_token_balance(&marinade_referral_test_globals.msol_partner_token_pubkey)
+        .await;
+
+    let mut params = DelayedUnstakeParams::new(&test.state);
+    do_referral_order_unstake(&mut params, &mut test, &marinade_referral_test_globals)
+        .await
+        .unwrap();
+
+    let operation_fee_lamports = params.msol_burn_amount * operation_fee as u64 / 10_000;
+    let msol_burn_amount_fee_deducted = params.msol_burn_amount - operation_fee_lamports;
+
+    // ticket is created for the fee deducted amount
+    let ticket: TicketAccountData = test.get_account_data(&params.ticket_account.pubkey()).await;
+    assert_eq!(ticket.beneficiary, params.user_sol.pubkey());
+    assert_eq!(
+        ticket.lamports_amount,
+        test.state
+            .calc_lamports_from_msol_amount(msol_burn_amount_fee_deducted)
+            .unwrap()
+    );
+
+    assert_eq!(
+        test.get_token_balance(&params.user_msol_address).await,
+        params.initial_stake_amount - params.msol_burn_amount
+    );
+    assert_eq!(
+        test.get_token_balance(&marinade_referral_test_globals.msol_partner_token_pubkey)
+            .await,
+        partner_msol_balance_before + operation_fee_lamports,
+        "Partner is expected to receive mSOL in the amount of the operation fee"
+    );
+
+    let referral_state: marinade_referral::states::ReferralState = get_account(
+        &mut test,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+    )
+    .await;
+    assert_eq!(
+        referral_state.delayed_unstake_amount,
+        msol_burn_amount_fee_deducted as u128
+    );
+    assert_eq!(referral_state.delayed_unstake_operations, 1);
+    assert_eq!(
+        referral_state.accum_delayed_unstake_fee,
+        operation_fee_lamports
+    );
+
+    // the ticket is bound to the referral state it was ordered through
+    let ticket_record: marinade_referral::states::TicketRecord = get_account(
+        &mut test,
+        marinade_referral::states::TicketRecord::find_address(&params.ticket_account.pubkey()).0,
+    )
+    .await;
+    assert_eq!(
+        ticket_record.referral_state,
+        marinade_referral_test_globals.partner_referral_state_pubkey
+    );
+    assert_eq!(ticket_record.ticket_account, params.ticket_account.pubkey());
+    assert_eq!(ticket_record.rent_payer, params.user_sol.pubkey());
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_referral_order_unstake_fail_when_paused() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    marinade_referral_test_globals
+        .pause_referral_account(&mut test)
+        .await;
+
+    let mut params = DelayedUnstakeParams::new(&test.state);
+    match do_referral_order_unstake(&mut params, &mut test, &marinade_referral_test_globals).await
+    {
+        Ok(_) => panic!("Expected error happens when referral account is paused"),
+        Err(number) => {
+            // https://github.com/coral-xyz/anchor/blob/v0.14.0/lang/src/error.rs
+            assert_eq!(
+                143, number,
+                "Expected anchor error 'A raw constraint was violated'"
+            );
+        }
+    }
+    Ok(())
+}
+
+pub async fn try_referral_claim(
+    params: &DelayedUnstakeParams,
+    test: &mut IntegrationTest,
+    referral_state: Pubkey,
+    beneficiary: Pubkey,
+) -> Result<(), u32> {
+    let accounts = marinade_referral::accounts::Claim {
+        state: test.state.key,
+        reserve_pda: State::find_reserve_address(&test.state.key).0,
+        ticket_account: params.ticket_account.pubkey(),
+        transfer_sol_to: beneficiary,
+        clock: clock::id(),
+        system_program: system_program::ID,
+        //----
+        marinade_finance_program: marinade_finance::ID,
+        global_state: marinade_referral::states::GlobalState::find_address().0,
+        referral_state,
+        ticket_record: marinade_referral::states::TicketRecord::find_address(
+            &params.ticket_account.pubkey(),
+        )
+        .0,
+    };
+    let ix_data = marinade_referral::instruction::Claim {};
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    println!("marinade-referral claim");
+    test.try_execute_instruction(instruction, vec![test.fee_payer_signer()])
+        .await
+}
+
+#[test(tokio::test)]
+async fn test_referral_claim_ticket() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    marinade_referral_test_globals
+        .set_no_operation_fees(&mut test)
+        .await;
+
+    let mut params = DelayedUnstakeParams::new(&test.state);
+    do_referral_order_unstake(&mut params, &mut test, &marinade_referral_test_globals)
+        .await
+        .unwrap();
+    let ticket: TicketAccountData = test.get_account_data(&params.ticket_account.pubkey()).await;
+
+    // ticket is not due yet
+    const ERR_CODE_TICKET_NOT_DUE: u32 = 0x1103;
+    assert_eq!(
+        try_referral_claim(
+            &params,
+            &mut test,
+            marinade_referral_test_globals.partner_referral_state_pubkey,
+            params.user_sol.pubkey(),
+        )
+        .await,
+        Err(ERR_CODE_TICKET_NOT_DUE)
+    );
+
+    //move to epoch 3
+    let clock = test.move_to_slot(470).await;
+    assert_eq!(clock.epoch, 3);
+
+    let pre_balance = test.get_sol_balance(&params.user_sol.pubkey()).await;
+    try_referral_claim(
+        &params,
+        &mut test,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        params.user_sol.pubkey(),
+    )
+    .await
+    .unwrap();
+    let post_balance = test.get_sol_balance(&params.user_sol.pubkey()).await;
+    //user also gets params.ticket_account_rent_exempt_lamports and the ticket record rent
+    const TICKET_RECORD_SPACE: usize = 8 + marinade_referral::states::TicketRecord::LEN;
+    assert_eq!(
+        post_balance,
+        pre_balance
+            + ticket.lamports_amount
+            + params.ticket_account_rent_exempt_lamports
+            + test.rent.minimum_balance(TICKET_RECORD_SPACE)
+    );
+    assert_eq!(
+        test.get_sol_balance(
+            &marinade_referral::states::TicketRecord::find_address(
+                &params.ticket_account.pubkey()
+            )
+            .0
+        )
+        .await,
+        0,
+        "Ticket record is closed by the claim"
+    );
+
+    let referral_state: marinade_referral::states::ReferralState = get_account(
+        &mut test,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+    )
+    .await;
+    assert_eq!(
+        referral_state.delayed_unstake_claimed_lamports,
+        ticket.lamports_amount as u128
+    );
+    assert_eq!(referral_state.delayed_unstake_claim_operations, 1);
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_referral_claim_fail_through_other_referral_state() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    let mut params = DelayedUnstakeParams::new(&test.state);
+    do_referral_order_unstake(&mut params, &mut test, &marinade_referral_test_globals)
+        .await
+        .unwrap();
+
+    let other_partner = test
+        .create_test_user("other_partner", 200 * LAMPORTS_PER_SOL)
+        .await;
+    let other_partner_msol_acc = other_partner
+        .get_or_create_msol_account_instruction(&mut test)
+        .await;
+    test.execute().await;
+    let other_referral_state = create_referral_state_account(
+        &mut test,
+        &other_partner,
+        marinade_referral_test_globals.global_state_pubkey,
+        &marinade_referral_test_globals.admin_key,
+        other_partner_msol_acc.pubkey,
+    )
+    .await
+    .unwrap();
+
+    test.move_to_slot(470).await;
+    // https://github.com/coral-xyz/anchor/blob/v0.14.0/lang/src/error.rs
+    assert_eq!(
+        try_referral_claim(
+            &params,
+            &mut test,
+            other_referral_state,
+            params.user_sol.pubkey(),
+        )
+        .await,
+        Err(141),
+        "Expected anchor error 'A has_one constraint was violated'"
+    );
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_referral_claim_fail_when_paused() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    let mut params = DelayedUnstakeParams::new(&test.state);
+    do_referral_order_unstake(&mut params, &mut test, &marinade_referral_test_globals)
+        .await
+        .unwrap();
+    marinade_referral_test_globals
+        .pause_referral_account(&mut test)
+        .await;
+
+    test.move_to_slot(470).await;
+    match try_referral_claim(
+        &params,
+        &mut test,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        params.user_sol.pubkey(),
+    )
+    .await
+    {
+        Ok(_) => panic!("Expected error happens when referral account is paused"),
+        Err(number) => {
+            // https://github.com/coral-xyz/anchor/blob/v0.14.0/lang/src/error.rs
+            assert_eq!(
+                143, number,
+                "Expected anchor error 'A raw constraint was violated'"
+            );
+        }
+    }
+    Ok(())
+}
+
+pub async fn try_close_ticket_record(
+    params: &DelayedUnstakeParams,
+    test: &mut IntegrationTest,
+    rent_payer: Pubkey,
+) -> Result<(), u32> {
+    let accounts = marinade_referral::accounts::CloseTicketRecord {
+        ticket_account: params.ticket_account.pubkey(),
+        ticket_record: marinade_referral::states::TicketRecord::find_address(
+            &params.ticket_account.pubkey(),
+        )
+        .0,
+        rent_payer,
+    };
+    let ix_data = marinade_referral::instruction::CloseTicketRecord {};
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    println!("marinade-referral close_ticket_record");
+    test.try_execute_instruction(instruction, vec![test.fee_payer_signer()])
+        .await
+}
+
+#[test(tokio::test)]
+async fn test_close_ticket_record_claimed_through_marinade() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    let mut params = DelayedUnstakeParams::new(&test.state);
+    do_referral_order_unstake(&mut params, &mut test, &marinade_referral_test_globals)
+        .await
+        .unwrap();
+
+    // the record stays while the ticket can be claimed through the referral program
+    assert_eq!(
+        try_close_ticket_record(&params, &mut test, params.user_sol.pubkey()).await,
+        Err(316),
+        "Expected the ticket account not closed error"
+    );
+
+    // the user claims the ticket directly through Marinade
+    test.move_to_slot(470).await;
+    test.builder.claim(
+        &test.state,
+        params.ticket_account.pubkey(),
+        params.user_sol.pubkey(), //ticket beneficiary
+    );
+    test.execute().await;
+
+    // the rent goes back to the payer of the record only
+    let other_account = Keypair::new();
+    // https://github.com/coral-xyz/anchor/blob/v0.14.0/lang/src/error.rs
+    assert_eq!(
+        try_close_ticket_record(&params, &mut test, other_account.pubkey()).await,
+        Err(141),
+        "Expected anchor error 'A has_one constraint was violated'"
+    );
+
+    let ticket_record =
+        marinade_referral::states::TicketRecord::find_address(&params.ticket_account.pubkey()).0;
+    let record_lamports = test.get_sol_balance(&ticket_record).await;
+    assert_eq!(
+        record_lamports,
+        test.rent
+            .minimum_balance(8 + marinade_referral::states::TicketRecord::LEN)
+    );
+    let pre_balance = test.get_sol_balance(&params.user_sol.pubkey()).await;
+    try_close_ticket_record(&params, &mut test, params.user_sol.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(
+        test.get_sol_balance(&params.user_sol.pubkey()).await,
+        pre_balance + record_lamports
+    );
+    assert_eq!(test.get_sol_balance(&ticket_record).await, 0);
+    Ok(())
+}
@@ -5,6 +5,9 @@
 // use marinade_referral;
 
 use crate::integration_test::test_add_remove_liquidity::*;
+use marinade_referral::constant::PAUSE_LIQUID_UNSTAKE;
+use marinade_referral::events::{DepositEvent, LiquidUnstakeEvent};
+use marinade_referral::states::{FeeBeneficiary, GlobalState, RefereeRecord};
 use crate::{initialize::InitializeInputWithSeeds, integration_test::*};
 
 use marinade_finance_offchain_sdk::{
@@ -108,7 +111,7 @@ impl TestData {
     }
 }
 
-async fn try_deposit_execute(
+pub async fn try_deposit_execute(
     test: &mut IntegrationTest,
     user: &mut TestUser,
     marinade_instance_state: Pubkey,
@@ -117,6 +120,35 @@ async fn try_deposit_execute(
     partner_referral_state_pubkey: Pubkey,
     msol_token_partner_account: Pubkey,
     lamports: u64,
+    remaining_accounts: &[Pubkey],
+) -> Result<(), u32> {
+    let fee_authority = user.keypair.clone();
+    try_deposit_with_fee_authority_execute(
+        test,
+        user,
+        &fee_authority,
+        marinade_instance_state,
+        transfer_from,
+        mint_to,
+        partner_referral_state_pubkey,
+        msol_token_partner_account,
+        lamports,
+        remaining_accounts,
+    )
+    .await
+}
+
+async fn try_deposit_with_fee_authority_execute(
+    test: &mut IntegrationTest,
+    user: &mut TestUser,
+    fee_authority: &Arc<Keypair>,
+    marinade_instance_state: Pubkey,
+    transfer_from: Pubkey,
+    mint_to: Pubkey,
+    partner_referral_state_pubkey: Pubkey,
+    msol_token_partner_account: Pubkey,
+    lamports: u64,
+    remaining_accounts: &[Pubkey],
 ) -> Result<(), u32> {
     let accounts = marinade_referral::accounts::Deposit {
         state: marinade_instance_state,
@@ -132,20 +164,33 @@ async fn try_deposit_execute(
         token_program: spl_token::ID,
         //----
         marinade_finance_program: marinade_finance::ID,
+        global_state: GlobalState::find_address().0,
         referral_state: partner_referral_state_pubkey,
         msol_token_partner_account,
     };
     let ix_data = marinade_referral::instruction::Deposit { lamports };
+    let mut account_metas = accounts.to_account_metas(None);
+    // fee beneficiary token accounts and referee record as remaining accounts
+    account_metas.extend(
+        remaining_accounts
+            .iter()
+            .map(|account| AccountMeta::new(*account, false)),
+    );
+    let mut signers = vec![test.fee_payer_signer(), user.keypair.clone()];
+    // a fee authority other than the depositor is the trailing signer,
+    // the program id in place of the referee record skips it
+    if fee_authority.pubkey() != user.keypair.pubkey() {
+        account_metas.push(AccountMeta::new_readonly(marinade_referral::ID, false));
+        account_metas.push(AccountMeta::new_readonly(fee_authority.pubkey(), true));
+        signers.push(fee_authority.clone());
+    }
     let deposit_instruction = Instruction {
         program_id: marinade_referral::marinade_referral::ID,
-        accounts: accounts.to_account_metas(None),
+        accounts: account_metas,
         data: ix_data.data(),
     };
-    test.try_execute_instruction(
-        deposit_instruction,
-        vec![test.fee_payer_signer(), user.keypair.clone()],
-    )
-    .await
+    test.try_execute_instruction(deposit_instruction, signers)
+        .await
 }
 
 async fn try_liquid_unstake(
@@ -155,12 +200,39 @@ async fn try_liquid_unstake(
     partner_referral_state_pubkey: Pubkey,
     msol_token_partner_account: Pubkey,
     msol_lamports: u64,
+    remaining_accounts: &[Pubkey],
+) -> Result<(), u32> {
+    let get_msol_from_authority = user.keypair.clone();
+    try_liquid_unstake_with_authority(
+        test,
+        &get_msol_from_authority,
+        user_msol_account.pubkey,
+        partner_referral_state_pubkey,
+        msol_token_partner_account,
+        msol_lamports,
+        remaining_accounts,
+        None,
+    )
+    .await
+}
+
+// get_msol_from_authority is the owner or a delegate of get_msol_from and receives the SOL,
+// record_payer is the trailing signer paying the referee record when it is created
+async fn try_liquid_unstake_with_authority(
+    test: &mut IntegrationTest,
+    get_msol_from_authority: &Arc<Keypair>,
+    get_msol_from: Pubkey,
+    partner_referral_state_pubkey: Pubkey,
+    msol_token_partner_account: Pubkey,
+    msol_lamports: u64,
+    remaining_accounts: &[Pubkey],
+    record_payer: Option<&Arc<Keypair>>,
 ) -> Result<(), u32> {
     let accounts = marinade_referral::accounts::LiquidUnstake {
         state: test.state.key(),
-        get_msol_from: user_msol_account.pubkey,
-        get_msol_from_authority: user.keypair.pubkey(),
-        transfer_sol_to: user.keypair.pubkey(),
+        get_msol_from,
+        get_msol_from_authority: get_msol_from_authority.pubkey(),
+        transfer_sol_to: get_msol_from_authority.pubkey(),
         treasury_msol_account: test.state.treasury_msol_account,
         msol_mint: test.state.as_ref().msol_mint,
         liq_pool_sol_leg_pda: test.state.liq_pool_sol_leg_address(),
@@ -169,6 +241,7 @@ async fn try_liquid_unstake(
         token_program: spl_token::ID,
         //----
         marinade_finance_program: marinade_finance::ID,
+        global_state: GlobalState::find_address().0,
         referral_state: partner_referral_state_pubkey,
         msol_token_partner_account: msol_token_partner_account,
     };
@@ -176,25 +249,34 @@ async fn try_liquid_unstake(
     let ix_data = marinade_referral::instruction::LiquidUnstake {
         msol_amount: msol_lamports,
     };
+    let mut account_metas = accounts.to_account_metas(None);
+    // fee beneficiary token accounts and referee record as remaining accounts
+    account_metas.extend(
+        remaining_accounts
+            .iter()
+            .map(|account| AccountMeta::new(*account, false)),
+    );
+    let mut signers = vec![test.fee_payer_signer(), get_msol_from_authority.clone()];
+    if let Some(record_payer) = record_payer {
+        account_metas.push(AccountMeta::new(record_payer.pubkey(), true));
+        signers.push(record_payer.clone());
+    }
     let liquid_unstake_instruction = Instruction {
         program_id: marinade_referral::marinade_referral::ID,
-        accounts: accounts.to_account_metas(None),
+        accounts: account_metas,
         data: ix_data.data(),
     };
     println!("marinade-referral liquid_unstake");
-    test.try_execute_instruction(
-        liquid_unstake_instruction,
-        vec![test.fee_payer_signer(), user.keypair.clone()],
-    )
-    .await
+    test.try_execute_instruction(liquid_unstake_instruction, signers)
+        .await
 }
 
-async fn do_deposit_sol(
+pub async fn do_deposit_sol(
     user: &mut TestUser,
     lamports: u64,
     test: &mut IntegrationTest,
     marinade_referral_test_globals: &MarinadeReferralTestGlobals,
-    operation_fee_bps: u8,
+    operation_fee_bps: u16,
 ) -> Result<(), u32> {
     let data_before = TestData::get(test, user, marinade_referral_test_globals).await;
 
@@ -208,6 +290,8 @@ async fn do_deposit_sol(
         None,
         None,
         None,
+        None,
+        None,
     )
     .await?;
 
@@ -223,6 +307,7 @@ async fn do_deposit_sol(
         marinade_referral_test_globals.partner_referral_state_pubkey,
         marinade_referral_test_globals.msol_partner_token_pubkey,
         lamports,
+        &[],
     )
     .await?;
 
@@ -279,7 +364,7 @@ pub async fn do_liquid_unstake(
     msol_lamports: u64,
     test: &mut IntegrationTest,
     marinade_referral_test_globals: &MarinadeReferralTestGlobals,
-    operaration_fee_bps: u8,
+    operaration_fee_bps: u16,
 ) -> Result<(), u32> {
     println!(
         "--- do_liquid_unstake {} mSOL ----------",
@@ -326,6 +411,7 @@ pub async fn do_liquid_unstake(
         marinade_referral_test_globals.partner_referral_state_pubkey,
         marinade_referral_test_globals.msol_partner_token_pubkey,
         msol_lamports,
+        &[],
     )
     .await;
 
@@ -389,7 +475,7 @@ pub async fn do_liquid_unstake(
     );
     assert_eq!(
         data_after.referral_state.liq_unstake_msol_amount,
-        msol_lamports_fee_deducted
+        msol_lamports_fee_deducted as u128
     );
 
     // msol_amount in lamports
@@ -399,7 +485,7 @@ pub async fn do_liquid_unstake(
         .unwrap();
     assert_eq!(
         data_after.referral_state.liq_unstake_sol_amount,
-        user_remove_lamports
+        user_remove_lamports as u128
     );
 
     // Check post-conditions.
@@ -452,9 +538,10 @@ async fn test_deposit_sol_operation_fee() -> anyhow::Result<()> {
         .create_test_user("test_dep_sol_user", 200 * LAMPORTS_PER_SOL)
         .await;
 
+    let lamports = random_amount(1, 100, &mut rng);
     do_deposit_sol(
         &mut user,
-        random_amount(1, 100, &mut rng),
+        lamports,
         &mut test,
         &marinade_referral_test_globals,
         27,
@@ -462,6 +549,258 @@ async fn test_deposit_sol_operation_fee() -> anyhow::Result<()> {
     .await
     .unwrap();
 
+    // the deposit is reported with the accumulators after it
+    let operation_fee = lamports * 27 / 10_000;
+    let referral_state: marinade_referral::states::ReferralState = get_account(
+        &mut test,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+    )
+    .await;
+    let deposit_events: Vec<DepositEvent> = emitted_events::<DepositEvent>()
+        .into_iter()
+        .filter(|event| event.user == user.keypair.pubkey())
+        .collect();
+    assert_eq!(1, deposit_events.len());
+    let deposit_event = &deposit_events[0];
+    assert_eq!(
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        deposit_event.referral_state
+    );
+    assert_eq!(lamports, deposit_event.lamports);
+    assert_eq!(lamports, deposit_event.minted_msol);
+    assert_eq!(operation_fee, deposit_event.operation_fee);
+    assert_eq!(
+        referral_state.deposit_sol_amount,
+        deposit_event.deposit_sol_amount
+    );
+    assert_eq!(1, deposit_event.deposit_sol_operations);
+    assert_eq!(operation_fee, deposit_event.accum_deposit_sol_fee);
+
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_deposit_sol_fee_beneficiaries() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    let mut user = test
+        .create_test_user("test_dep_sol_user", 200 * LAMPORTS_PER_SOL)
+        .await;
+    let user_msol_account = user.get_or_create_msol_account_instruction(&mut test).await;
+    let wallet = test.create_test_user("wallet", LAMPORTS_PER_SOL).await;
+    let wallet_msol_account = wallet.get_or_create_msol_account_instruction(&mut test).await;
+    let integrator = test.create_test_user("integrator", LAMPORTS_PER_SOL).await;
+    let integrator_msol_account = integrator
+        .get_or_create_msol_account_instruction(&mut test)
+        .await;
+    test.execute().await;
+
+    let operation_fee_bps: u16 = 50;
+    update_operation_fees(
+        &mut test,
+        marinade_referral_test_globals.global_state_pubkey,
+        &marinade_referral_test_globals.admin_key,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        Some(operation_fee_bps),
+        None,
+        None,
+        None,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    let beneficiary_accounts = [wallet_msol_account.pubkey, integrator_msol_account.pubkey];
+    update_fee_beneficiaries_execute(
+        &mut test,
+        marinade_referral_test_globals.global_state_pubkey,
+        &marinade_referral_test_globals.admin_key,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        vec![
+            FeeBeneficiary {
+                msol_token_account: wallet_msol_account.pubkey,
+                fee_share: 3_000,
+            },
+            FeeBeneficiary {
+                msol_token_account: integrator_msol_account.pubkey,
+                fee_share: 2_000,
+            },
+        ],
+        &beneficiary_accounts,
+    )
+    .await
+    .unwrap();
+
+    // beneficiary token accounts have to be passed
+    let marinade_instance_state = test.state.key();
+    let depositor = user.keypair.pubkey();
+    let deposit_result = try_deposit_execute(
+        &mut test,
+        &mut user,
+        marinade_instance_state,
+        depositor,
+        user_msol_account.pubkey,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        marinade_referral_test_globals.msol_partner_token_pubkey,
+        LAMPORTS_PER_SOL,
+        &[],
+    )
+    .await;
+    match deposit_result {
+        Err(error_number) => assert_eq!(331, error_number, "Expected beneficiary accounts error"),
+        _ => panic!("Expected the transaction fails with the beneficiary accounts error."),
+    }
+
+    let partner_msol_before = test
+        .get_token_balance_or_zero(&marinade_referral_test_globals.msol_partner_token_pubkey)
+        .await;
+    // not divisible amounts, the rounding remainder goes to the partner
+    let lamports = LAMPORTS_PER_SOL + 333;
+    try_deposit_execute(
+        &mut test,
+        &mut user,
+        marinade_instance_state,
+        depositor,
+        user_msol_account.pubkey,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        marinade_referral_test_globals.msol_partner_token_pubkey,
+        lamports,
+        &beneficiary_accounts,
+    )
+    .await
+    .unwrap();
+
+    // mSOL price is 1, minted mSOL is equal to the deposited lamports
+    let operation_fee = lamports * operation_fee_bps as u64 / 10_000;
+    let wallet_share = operation_fee * 3_000 / 10_000;
+    let integrator_share = operation_fee * 2_000 / 10_000;
+    assert_eq!(
+        wallet_share,
+        test.get_token_balance_or_zero(&wallet_msol_account.pubkey)
+            .await
+    );
+    assert_eq!(
+        integrator_share,
+        test.get_token_balance_or_zero(&integrator_msol_account.pubkey)
+            .await
+    );
+    assert_eq!(
+        partner_msol_before + operation_fee - wallet_share - integrator_share,
+        test.get_token_balance_or_zero(&marinade_referral_test_globals.msol_partner_token_pubkey)
+            .await
+    );
+    let referral_state: marinade_referral::states::ReferralState = get_account(
+        &mut test,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+    )
+    .await;
+    assert_eq!(operation_fee, referral_state.accum_deposit_sol_fee);
+
+    // the deposit is reported with the accumulators after it
+    let deposit_events: Vec<DepositEvent> = emitted_events::<DepositEvent>()
+        .into_iter()
+        .filter(|event| {
+            event.referral_state == marinade_referral_test_globals.partner_referral_state_pubkey
+        })
+        .collect();
+    assert_eq!(1, deposit_events.len());
+    let deposit_event = &deposit_events[0];
+    assert_eq!(depositor, deposit_event.user);
+    assert_eq!(lamports, deposit_event.lamports);
+    assert_eq!(lamports, deposit_event.minted_msol);
+    assert_eq!(operation_fee, deposit_event.operation_fee);
+    assert_eq!(
+        referral_state.deposit_sol_amount,
+        deposit_event.deposit_sol_amount
+    );
+    assert_eq!(1, deposit_event.deposit_sol_operations);
+    assert_eq!(operation_fee, deposit_event.accum_deposit_sol_fee);
+
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_deposit_sol_referee_record() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    let referral_state_pubkey = marinade_referral_test_globals.partner_referral_state_pubkey;
+    let marinade_instance_state = test.state.key();
+    let mut alice = test.create_test_user("alice", 200 * LAMPORTS_PER_SOL).await;
+    let alice_msol_account = alice.get_or_create_msol_account_instruction(&mut test).await;
+    let mut bob = test.create_test_user("bob", 200 * LAMPORTS_PER_SOL).await;
+    let bob_msol_account = bob.get_or_create_msol_account_instruction(&mut test).await;
+    test.execute().await;
+    let (alice_record, alice_record_bump) =
+        RefereeRecord::find_address(&referral_state_pubkey, &alice.keypair.pubkey());
+    let (bob_record, _) =
+        RefereeRecord::find_address(&referral_state_pubkey, &bob.keypair.pubkey());
+
+    // the referee record has to be the PDA of the depositor
+    let depositor = bob.keypair.pubkey();
+    let deposit_result = try_deposit_execute(
+        &mut test,
+        &mut bob,
+        marinade_instance_state,
+        depositor,
+        bob_msol_account.pubkey,
+        referral_state_pubkey,
+        marinade_referral_test_globals.msol_partner_token_pubkey,
+        LAMPORTS_PER_SOL,
+        &[alice_record],
+    )
+    .await;
+    match deposit_result {
+        Err(error_number) => assert_eq!(332, error_number, "Expected invalid referee record error"),
+        _ => panic!("Expected the transaction fails with the invalid referee record error."),
+    }
+
+    // the record is created at the first deposit and updated at the next ones
+    let depositor = alice.keypair.pubkey();
+    for &lamports in [LAMPORTS_PER_SOL, 2 * LAMPORTS_PER_SOL].iter() {
+        try_deposit_execute(
+            &mut test,
+            &mut alice,
+            marinade_instance_state,
+            depositor,
+            alice_msol_account.pubkey,
+            referral_state_pubkey,
+            marinade_referral_test_globals.msol_partner_token_pubkey,
+            lamports,
+            &[alice_record],
+        )
+        .await
+        .unwrap();
+    }
+    let depositor = bob.keypair.pubkey();
+    try_deposit_execute(
+        &mut test,
+        &mut bob,
+        marinade_instance_state,
+        depositor,
+        bob_msol_account.pubkey,
+        referral_state_pubkey,
+        marinade_referral_test_globals.msol_partner_token_pubkey,
+        LAMPORTS_PER_SOL,
+        &[bob_record],
+    )
+    .await
+    .unwrap();
+
+    let alice_referee_record: RefereeRecord = get_account(&mut test, alice_record).await;
+    assert_eq!(referral_state_pubkey, alice_referee_record.referral_state);
+    assert_eq!(alice.keypair.pubkey(), alice_referee_record.user);
+    assert_eq!(alice_record_bump, alice_referee_record.bump);
+    assert_eq!(
+        3 * LAMPORTS_PER_SOL as u128,
+        alice_referee_record.deposit_sol_amount
+    );
+    assert_eq!(0, alice_referee_record.liq_unstake_sol_amount);
+    let bob_referee_record: RefereeRecord = get_account(&mut test, bob_record).await;
+    assert_eq!(LAMPORTS_PER_SOL as u128, bob_referee_record.deposit_sol_amount);
+
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, referral_state_pubkey).await;
+    assert_eq!(2, referral_state.unique_referees);
+    assert_eq!(3, referral_state.deposit_sol_operations);
+
     Ok(())
 }
 
@@ -482,15 +821,97 @@ async fn test_deposit_sol_operation_fail_when_paused() {
     match deposit_result {
         Ok(_) => panic!("Expected error happens when referral account is paused"),
         Err(number) => {
-            // https://github.com/coral-xyz/anchor/blob/v0.14.0/lang/src/error.rs
-            assert_eq!(
-                143, number,
-                "Expected anchor error 'A raw constraint was violated'"
-            );
+            assert_eq!(301, number, "Expected the partner paused error");
         }
     }
 }
 
+#[test(tokio::test)]
+async fn test_deposit_sol_operation_fail_when_globally_paused() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    let global_state_pubkey = marinade_referral_test_globals.global_state_pubkey;
+    let mut user = test
+        .create_test_user("test_dep_sol_user", 200 * LAMPORTS_PER_SOL)
+        .await;
+
+    // only admin or foreman
+    let txn_result = set_global_pause_execute(
+        &mut test,
+        global_state_pubkey,
+        &marinade_referral_test_globals.partner.keypair,
+        true,
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(143, error_number, "Expected admin or foreman constraint"),
+        _ => panic!("Expected the transaction fails with the constraint violation."),
+    }
+
+    set_global_pause_execute(
+        &mut test,
+        global_state_pubkey,
+        &marinade_referral_test_globals.admin_key,
+        true,
+    )
+    .await
+    .unwrap();
+    let global_state: GlobalState = get_account(&mut test, global_state_pubkey).await;
+    assert!(global_state.paused);
+
+    let deposit_result = do_deposit_sol(
+        &mut user,
+        LAMPORTS_PER_SOL,
+        &mut test,
+        &marinade_referral_test_globals,
+        0,
+    )
+    .await;
+    match deposit_result {
+        Ok(_) => panic!("Expected error happens when globally paused"),
+        Err(number) => assert_eq!(335, number, "Expected the global paused error"),
+    }
+
+    // the global pause is reported before the partner pause
+    marinade_referral_test_globals
+        .pause_referral_account(&mut test)
+        .await;
+    let deposit_result = do_deposit_sol(
+        &mut user,
+        LAMPORTS_PER_SOL,
+        &mut test,
+        &marinade_referral_test_globals,
+        0,
+    )
+    .await;
+    match deposit_result {
+        Ok(_) => panic!("Expected error happens when globally paused"),
+        Err(number) => assert_eq!(335, number, "Expected the global paused error"),
+    }
+
+    set_global_pause_execute(
+        &mut test,
+        global_state_pubkey,
+        &marinade_referral_test_globals.admin_key,
+        false,
+    )
+    .await
+    .unwrap();
+    let deposit_result = do_deposit_sol(
+        &mut user,
+        LAMPORTS_PER_SOL,
+        &mut test,
+        &marinade_referral_test_globals,
+        0,
+    )
+    .await;
+    match deposit_result {
+        Ok(_) => panic!("Expected error happens when referral account is paused"),
+        Err(number) => assert_eq!(301, number, "Expected the partner paused error"),
+    }
+
+    Ok(())
+}
+
 #[test(tokio::test)]
 async fn test_deposit_sol_wrong_referral() -> anyhow::Result<()> {
     let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
@@ -510,6 +931,7 @@ async fn test_deposit_sol_wrong_referral() -> anyhow::Result<()> {
         marinade_referral_test_globals.partner_referral_state_pubkey,
         user_msol_account.pubkey,
         22,
+        &[],
     )
     .await;
     match deposit_result {
@@ -526,32 +948,319 @@ async fn test_deposit_sol_wrong_referral() -> anyhow::Result<()> {
 }
 
 #[test(tokio::test)]
-async fn test_liquid_unstake() -> anyhow::Result<()> {
+async fn test_deposit_sol_caps() -> anyhow::Result<()> {
     let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
-
-    let mut alice = test
-        .create_test_user("alice", 1000 * LAMPORTS_PER_SOL)
+    let referral_state_pubkey = marinade_referral_test_globals.partner_referral_state_pubkey;
+    let mut user = test
+        .create_test_user("test_dep_sol_user", 200 * LAMPORTS_PER_SOL)
         .await;
 
-    let alice_deposit_amount = 26 * LAMPORTS_PER_SOL;
-    do_deposit_sol(
-        &mut alice,
-        alice_deposit_amount,
+    // only admin
+    let txn_result = update_deposit_caps_execute(
         &mut test,
-        &marinade_referral_test_globals,
-        0,
+        marinade_referral_test_globals.global_state_pubkey,
+        &marinade_referral_test_globals.partner.keypair,
+        referral_state_pubkey,
+        3 * LAMPORTS_PER_SOL,
+        2 * LAMPORTS_PER_SOL,
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(141, error_number, "Expected has_one constraint error"),
+        _ => panic!("Expected the transaction fails with the has_one constraint violation."),
+    }
+    update_deposit_caps_execute(
+        &mut test,
+        marinade_referral_test_globals.global_state_pubkey,
+        &marinade_referral_test_globals.admin_key,
+        referral_state_pubkey,
+        3 * LAMPORTS_PER_SOL,
+        2 * LAMPORTS_PER_SOL,
     )
     .await
     .unwrap();
 
-    let alice_liquid_unstake_amount = 10 * LAMPORTS_PER_SOL;
-
-    // 1st one should fail with Insufficient Liquidity in the Liquidity Pool
-    const ERR_CODE_INSUFFICIENT_LIQUIDITY: u32 = 0x1199;
-    match do_liquid_unstake(
-        &mut alice,
-        alice_liquid_unstake_amount,
-        &mut test,
+    for _ in 0..2 {
+        do_deposit_sol(
+            &mut user,
+            LAMPORTS_PER_SOL,
+            &mut test,
+            &marinade_referral_test_globals,
+            0,
+        )
+        .await
+        .unwrap();
+    }
+    let deposit_result = do_deposit_sol(
+        &mut user,
+        LAMPORTS_PER_SOL,
+        &mut test,
+        &marinade_referral_test_globals,
+        0,
+    )
+    .await;
+    match deposit_result {
+        Err(number) => assert_eq!(341, number, "Expected the deposit cap error"),
+        _ => panic!("Expected the per-epoch cap stops the deposit"),
+    }
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, referral_state_pubkey).await;
+    assert_eq!(2 * LAMPORTS_PER_SOL as u128, referral_state.total_deposit_amount);
+    assert_eq!(2 * LAMPORTS_PER_SOL, referral_state.epoch_deposit_amount);
+
+    // the epoch counter restarts, the total cap still applies
+    let clock = test.move_to_next_epoch().await;
+    do_deposit_sol(
+        &mut user,
+        LAMPORTS_PER_SOL,
+        &mut test,
+        &marinade_referral_test_globals,
+        0,
+    )
+    .await
+    .unwrap();
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, referral_state_pubkey).await;
+    assert_eq!(clock.epoch, referral_state.deposit_epoch);
+    assert_eq!(LAMPORTS_PER_SOL, referral_state.epoch_deposit_amount);
+    assert_eq!(3 * LAMPORTS_PER_SOL as u128, referral_state.total_deposit_amount);
+    let deposit_result = do_deposit_sol(
+        &mut user,
+        1,
+        &mut test,
+        &marinade_referral_test_globals,
+        0,
+    )
+    .await;
+    match deposit_result {
+        Err(number) => assert_eq!(341, number, "Expected the deposit cap error"),
+        _ => panic!("Expected the total cap stops the deposit"),
+    }
+
+    // zero means unlimited
+    update_deposit_caps_execute(
+        &mut test,
+        marinade_referral_test_globals.global_state_pubkey,
+        &marinade_referral_test_globals.admin_key,
+        referral_state_pubkey,
+        0,
+        0,
+    )
+    .await
+    .unwrap();
+    do_deposit_sol(
+        &mut user,
+        LAMPORTS_PER_SOL,
+        &mut test,
+        &marinade_referral_test_globals,
+        0,
+    )
+    .await
+    .unwrap();
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_deposit_sol_custodial_fee_authority() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    update_operation_fees(
+        &mut test,
+        marinade_referral_test_globals.global_state_pubkey,
+        &marinade_referral_test_globals.admin_key,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        Some(50),
+        None,
+        None,
+        None,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    let mut user = test
+        .create_test_user("test_dep_sol_user", 200 * LAMPORTS_PER_SOL)
+        .await;
+    // the minted mSOL goes to the account of a custodian
+    let custodian = test
+        .create_test_user("test_custodian", LAMPORTS_PER_SOL)
+        .await;
+    let custodian_msol_account = custodian
+        .get_or_create_msol_account_instruction(&mut test)
+        .await;
+    test.execute().await;
+
+    let marinade_instance_state = test.state.key();
+    let depositor = user.keypair.clone().pubkey();
+    let deposit_result = try_deposit_execute(
+        &mut test,
+        &mut user,
+        marinade_instance_state,
+        depositor,
+        custodian_msol_account.pubkey,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        marinade_referral_test_globals.msol_partner_token_pubkey,
+        LAMPORTS_PER_SOL,
+        &[],
+    )
+    .await;
+    match deposit_result {
+        Err(number) => assert_eq!(339, number, "Expected the fee authority error"),
+        _ => panic!("Expected the depositor can't pay the fee from the custodian account"),
+    }
+
+    // mint_to must be an mSOL token account
+    let deposit_result = try_deposit_with_fee_authority_execute(
+        &mut test,
+        &mut user,
+        &custodian.keypair,
+        marinade_instance_state,
+        depositor,
+        custodian.keypair.pubkey(),
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        marinade_referral_test_globals.msol_partner_token_pubkey,
+        LAMPORTS_PER_SOL,
+        &[],
+    )
+    .await;
+    match deposit_result {
+        Err(number) => assert_eq!(338, number, "Expected the mint_to account error"),
+        _ => panic!("Expected a system account is rejected as mint_to"),
+    }
+
+    let partner_msol_before = test
+        .get_token_balance(&marinade_referral_test_globals.msol_partner_token_pubkey)
+        .await;
+    try_deposit_with_fee_authority_execute(
+        &mut test,
+        &mut user,
+        &custodian.keypair,
+        marinade_instance_state,
+        depositor,
+        custodian_msol_account.pubkey,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        marinade_referral_test_globals.msol_partner_token_pubkey,
+        LAMPORTS_PER_SOL,
+        &[],
+    )
+    .await
+    .unwrap();
+    let partner_msol_after = test
+        .get_token_balance(&marinade_referral_test_globals.msol_partner_token_pubkey)
+        .await;
+    let custodian_msol = test.get_token_balance(&custodian_msol_account.pubkey).await;
+    assert!(partner_msol_after > partner_msol_before);
+    let referral_state: marinade_referral::states::ReferralState = get_account(
+        &mut test,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+    )
+    .await;
+    assert_eq!(
+        partner_msol_after - partner_msol_before,
+        referral_state.accum_deposit_sol_fee
+    );
+    assert!(custodian_msol > 0);
+
+    // a delegate of the custodian account pays the fee up to the delegated amount
+    let operator = test
+        .create_test_user("test_custodian_operator", LAMPORTS_PER_SOL)
+        .await;
+    for (delegated_amount, expected_error) in vec![(1, Some(340)), (LAMPORTS_PER_SOL, None)] {
+        let approve_instruction = spl_token::instruction::approve(
+            &spl_token::ID,
+            &custodian_msol_account.pubkey,
+            &operator.keypair.pubkey(),
+            &custodian.keypair.pubkey(),
+            &[],
+            delegated_amount,
+        )?;
+        test.try_execute_instruction(
+            approve_instruction,
+            vec![test.fee_payer_signer(), custodian.keypair.clone()],
+        )
+        .await
+        .unwrap();
+        let deposit_result = try_deposit_with_fee_authority_execute(
+            &mut test,
+            &mut user,
+            &operator.keypair,
+            marinade_instance_state,
+            depositor,
+            custodian_msol_account.pubkey,
+            marinade_referral_test_globals.partner_referral_state_pubkey,
+            marinade_referral_test_globals.msol_partner_token_pubkey,
+            LAMPORTS_PER_SOL,
+            &[],
+        )
+        .await;
+        match (deposit_result, expected_error) {
+            (Err(number), Some(error)) => {
+                assert_eq!(error, number, "Expected the fee over delegated amount error")
+            }
+            (Ok(()), None) => {}
+            (result, _) => panic!("Unexpected deposit result {:?} by the delegate", result),
+        }
+    }
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_deposit_sol_forged_marinade_state() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    let mut user = test
+        .create_test_user("test_dep_sol_user", 200 * LAMPORTS_PER_SOL)
+        .await;
+    let user_msol_account = user.get_or_create_msol_account_instruction(&mut test).await;
+
+    // any state but the one pinned in the global state
+    let depositor = user.keypair.clone().pubkey();
+    let deposit_result = try_deposit_execute(
+        &mut test,
+        &mut user,
+        Pubkey::new_unique(),
+        depositor,                // transfer_from
+        user_msol_account.pubkey, // mint_to
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        marinade_referral_test_globals.msol_partner_token_pubkey,
+        LAMPORTS_PER_SOL,
+        &[],
+    )
+    .await;
+    match deposit_result {
+        Ok(_) => panic!("Expected error happens when the Marinade state is forged"),
+        Err(number) => {
+            assert_eq!(334, number, "Expected invalid Marinade state error");
+        }
+    }
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_liquid_unstake() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+
+    let mut alice = test
+        .create_test_user("alice", 1000 * LAMPORTS_PER_SOL)
+        .await;
+
+    let alice_deposit_amount = 26 * LAMPORTS_PER_SOL;
+    do_deposit_sol(
+        &mut alice,
+        alice_deposit_amount,
+        &mut test,
+        &marinade_referral_test_globals,
+        0,
+    )
+    .await
+    .unwrap();
+
+    let alice_liquid_unstake_amount = 10 * LAMPORTS_PER_SOL;
+
+    // 1st one should fail with Insufficient Liquidity in the Liquidity Pool
+    const ERR_CODE_INSUFFICIENT_LIQUIDITY: u32 = 0x1199;
+    match do_liquid_unstake(
+        &mut alice,
+        alice_liquid_unstake_amount,
+        &mut test,
         &marinade_referral_test_globals,
         0,
     )
@@ -580,6 +1289,8 @@ async fn test_liquid_unstake() -> anyhow::Result<()> {
         Some(0),
         Some(operation_fee),
         Some(0),
+        None,
+        None,
     )
     .await
     .unwrap();
@@ -593,15 +1304,271 @@ async fn test_liquid_unstake() -> anyhow::Result<()> {
         .unwrap();
 
     // 2nd should work ok
+    let alice_liquid_unstake_amount = 15 * LAMPORTS_PER_SOL;
     do_liquid_unstake(
         &mut alice,
-        15 * LAMPORTS_PER_SOL,
+        alice_liquid_unstake_amount,
         &mut test,
         &marinade_referral_test_globals,
         operation_fee,
     )
     .await
     .unwrap();
+
+    // only the successful liquid-unstake is reported, with the accumulators after it
+    let operation_fee_lamports = alice_liquid_unstake_amount * operation_fee as u64 / 10_000;
+    let referral_state: marinade_referral::states::ReferralState = get_account(
+        &mut test,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+    )
+    .await;
+    let liquid_unstake_events: Vec<LiquidUnstakeEvent> = emitted_events::<LiquidUnstakeEvent>()
+        .into_iter()
+        .filter(|event| event.user == alice.keypair.pubkey())
+        .collect();
+    assert_eq!(1, liquid_unstake_events.len());
+    let liquid_unstake_event = &liquid_unstake_events[0];
+    assert_eq!(
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        liquid_unstake_event.referral_state
+    );
+    assert_eq!(
+        alice_liquid_unstake_amount - operation_fee_lamports,
+        liquid_unstake_event.msol_amount
+    );
+    assert_eq!(operation_fee_lamports, liquid_unstake_event.operation_fee);
+    assert_eq!(
+        referral_state.liq_unstake_sol_amount,
+        liquid_unstake_event.lamports as u128
+    );
+    assert_eq!(
+        referral_state.liq_unstake_msol_amount,
+        liquid_unstake_event.liq_unstake_msol_amount
+    );
+    assert_eq!(
+        referral_state.liq_unstake_msol_fees,
+        liquid_unstake_event.treasury_msol_cut
+    );
+    assert_eq!(1, liquid_unstake_event.liq_unstake_operations);
+    assert_eq!(
+        operation_fee_lamports,
+        liquid_unstake_event.accum_liquid_unstake_fee
+    );
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_liquid_unstake_referee_record() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    let referral_state_pubkey = marinade_referral_test_globals.partner_referral_state_pubkey;
+    let mut alice = test.create_test_user("alice", 200 * LAMPORTS_PER_SOL).await;
+    do_deposit_sol(
+        &mut alice,
+        10 * LAMPORTS_PER_SOL,
+        &mut test,
+        &marinade_referral_test_globals,
+        0,
+    )
+    .await
+    .unwrap();
+    let mut bob = test
+        .create_test_user("bob", 50_000 * LAMPORTS_PER_SOL)
+        .await;
+    do_add_liquidity(&mut bob, 25 * LAMPORTS_PER_SOL, &mut test)
+        .await
+        .unwrap();
+    let alice_msol_account = alice.get_or_create_msol_account_instruction(&mut test).await;
+    let (alice_record, alice_record_bump) =
+        RefereeRecord::find_address(&referral_state_pubkey, &alice.keypair.pubkey());
+
+    // lamports sent to the record address beforehand do not block its creation
+    test.builder
+        .transfer_lamports(
+            test.fee_payer_signer(),
+            &alice_record,
+            1_000,
+            "fee payer",
+            "alice referee record",
+        )
+        .unwrap();
+    test.execute().await;
+
+    // the record is created at the first liquid-unstake, paid by the trailing signer
+    let result = try_liquid_unstake(
+        &mut test,
+        &mut alice,
+        &alice_msol_account,
+        referral_state_pubkey,
+        marinade_referral_test_globals.msol_partner_token_pubkey,
+        LAMPORTS_PER_SOL,
+        &[alice_record],
+    )
+    .await;
+    match result {
+        Err(error_number) => assert_eq!(332, error_number, "Expected invalid referee record error"),
+        _ => panic!("Expected the referee record can't be created without a payer."),
+    }
+    let payer = test.create_test_user("payer", LAMPORTS_PER_SOL).await;
+    let alice_keypair = alice.keypair.clone();
+    let record_space = 8 + RefereeRecord::LEN;
+    let payer_balance_before = test.get_sol_balance(&payer.keypair.pubkey()).await;
+    try_liquid_unstake_with_authority(
+        &mut test,
+        &alice_keypair,
+        alice_msol_account.pubkey,
+        referral_state_pubkey,
+        marinade_referral_test_globals.msol_partner_token_pubkey,
+        LAMPORTS_PER_SOL,
+        &[alice_record],
+        Some(&payer.keypair),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        payer_balance_before - (test.rent.minimum_balance(record_space) - 1_000),
+        test.get_sol_balance(&payer.keypair.pubkey()).await,
+        "Payer tops the pre-funded record up to the rent exemption"
+    );
+    // no payer is needed once the record exists
+    try_liquid_unstake(
+        &mut test,
+        &mut alice,
+        &alice_msol_account,
+        referral_state_pubkey,
+        marinade_referral_test_globals.msol_partner_token_pubkey,
+        2 * LAMPORTS_PER_SOL,
+        &[alice_record],
+    )
+    .await
+    .unwrap();
+
+    let alice_referee_record: RefereeRecord = get_account(&mut test, alice_record).await;
+    assert_eq!(referral_state_pubkey, alice_referee_record.referral_state);
+    assert_eq!(alice.keypair.pubkey(), alice_referee_record.user);
+    assert_eq!(alice_record_bump, alice_referee_record.bump);
+    assert_eq!(0, alice_referee_record.deposit_sol_amount);
+    assert!(alice_referee_record.liq_unstake_sol_amount > 0);
+    assert_eq!(
+        test.rent.minimum_balance(record_space),
+        test.get_sol_balance(&alice_record).await,
+        "Pre-funded referee record should be topped up to the rent exemption"
+    );
+
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, referral_state_pubkey).await;
+    assert_eq!(1, referral_state.unique_referees);
+    assert_eq!(
+        referral_state.liq_unstake_sol_amount,
+        alice_referee_record.liq_unstake_sol_amount
+    );
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_liquid_unstake_delegated_fee_authority() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    let referral_state_pubkey = marinade_referral_test_globals.partner_referral_state_pubkey;
+    let mut alice = test.create_test_user("alice", 200 * LAMPORTS_PER_SOL).await;
+    do_deposit_sol(
+        &mut alice,
+        10 * LAMPORTS_PER_SOL,
+        &mut test,
+        &marinade_referral_test_globals,
+        0,
+    )
+    .await
+    .unwrap();
+    let mut bob = test
+        .create_test_user("bob", 50_000 * LAMPORTS_PER_SOL)
+        .await;
+    do_add_liquidity(&mut bob, 25 * LAMPORTS_PER_SOL, &mut test)
+        .await
+        .unwrap();
+    update_operation_fees(
+        &mut test,
+        marinade_referral_test_globals.global_state_pubkey,
+        &marinade_referral_test_globals.admin_key,
+        referral_state_pubkey,
+        None,
+        None,
+        Some(50),
+        None,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    let alice_msol_account = alice.get_or_create_msol_account_instruction(&mut test).await;
+    let operator = test
+        .create_test_user("alice_operator", LAMPORTS_PER_SOL)
+        .await;
+
+    // the delegate of the mSOL account pays the fee up to the delegated amount
+    for (delegated_amount, expected_error) in vec![(1, Some(340)), (LAMPORTS_PER_SOL, None)] {
+        let approve_instruction = spl_token::instruction::approve(
+            &spl_token::ID,
+            &alice_msol_account.pubkey,
+            &operator.keypair.pubkey(),
+            &alice.keypair.pubkey(),
+            &[],
+            delegated_amount,
+        )?;
+        test.try_execute_instruction(
+            approve_instruction,
+            vec![test.fee_payer_signer(), alice.keypair.clone()],
+        )
+        .await
+        .unwrap();
+        let result = try_liquid_unstake_with_authority(
+            &mut test,
+            &operator.keypair,
+            alice_msol_account.pubkey,
+            referral_state_pubkey,
+            marinade_referral_test_globals.msol_partner_token_pubkey,
+            LAMPORTS_PER_SOL,
+            &[],
+            None,
+        )
+        .await;
+        match (result, expected_error) {
+            (Err(number), Some(error)) => {
+                assert_eq!(error, number, "Expected the fee over delegated amount error")
+            }
+            (Ok(()), None) => {}
+            (result, _) => panic!(
+                "Unexpected liquid-unstake result {:?} by the delegate",
+                result
+            ),
+        }
+    }
+    let operation_fee = LAMPORTS_PER_SOL * 50 / 10_000;
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, referral_state_pubkey).await;
+    assert_eq!(operation_fee, referral_state.accum_liquid_unstake_fee);
+
+    // the liquid-unstake is reported for the delegate with the accumulators after it
+    let liquid_unstake_events: Vec<LiquidUnstakeEvent> = emitted_events::<LiquidUnstakeEvent>()
+        .into_iter()
+        .filter(|event| event.referral_state == referral_state_pubkey)
+        .collect();
+    assert_eq!(1, liquid_unstake_events.len());
+    let liquid_unstake_event = &liquid_unstake_events[0];
+    assert_eq!(operator.keypair.pubkey(), liquid_unstake_event.user);
+    assert_eq!(
+        LAMPORTS_PER_SOL - operation_fee,
+        liquid_unstake_event.msol_amount
+    );
+    assert_eq!(operation_fee, liquid_unstake_event.operation_fee);
+    assert_eq!(
+        referral_state.liq_unstake_sol_amount,
+        liquid_unstake_event.lamports as u128
+    );
+    assert_eq!(
+        referral_state.liq_unstake_msol_amount,
+        liquid_unstake_event.liq_unstake_msol_amount
+    );
+    assert_eq!(1, liquid_unstake_event.liq_unstake_operations);
+    assert_eq!(operation_fee, liquid_unstake_event.accum_liquid_unstake_fee);
     Ok(())
 }
 
@@ -621,6 +1588,7 @@ async fn test_liquid_unstake_wrong_referral() -> anyhow::Result<()> {
         marinade_referral_test_globals.partner_referral_state_pubkey,
         user_msol_account.pubkey,
         22,
+        &[],
     )
     .await;
     match unstake_result {
@@ -650,16 +1618,267 @@ async fn test_liquid_unstake_fail_when_paused() {
         marinade_referral_test_globals.partner_referral_state_pubkey,
         user_msol_account.pubkey,
         0,
+        &[],
     )
     .await;
     match unstake_result {
         Ok(_) => panic!("Expected error happens when referral account is paused"),
         Err(number) => {
-            // https://github.com/coral-xyz/anchor/blob/v0.14.0/lang/src/error.rs
-            assert_eq!(
-                143, number,
-                "Expected anchor error 'A raw constraint was violated'"
-            );
+            assert_eq!(301, number, "Expected the partner paused error");
+        }
+    }
+}
+
+#[test(tokio::test)]
+async fn test_liquid_unstake_fail_when_globally_paused() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    let mut alice = test.create_test_user("alice", 200 * LAMPORTS_PER_SOL).await;
+    do_deposit_sol(
+        &mut alice,
+        10 * LAMPORTS_PER_SOL,
+        &mut test,
+        &marinade_referral_test_globals,
+        0,
+    )
+    .await
+    .unwrap();
+    let mut bob = test
+        .create_test_user("bob", 50_000 * LAMPORTS_PER_SOL)
+        .await;
+    do_add_liquidity(&mut bob, 25 * LAMPORTS_PER_SOL, &mut test)
+        .await
+        .unwrap();
+
+    for &(paused, expected_error) in [(true, Some(335)), (false, None)].iter() {
+        set_global_pause_execute(
+            &mut test,
+            marinade_referral_test_globals.global_state_pubkey,
+            &marinade_referral_test_globals.admin_key,
+            paused,
+        )
+        .await
+        .unwrap();
+        let result = do_liquid_unstake(
+            &mut alice,
+            LAMPORTS_PER_SOL,
+            &mut test,
+            &marinade_referral_test_globals,
+            0,
+        )
+        .await;
+        match (result, expected_error) {
+            (Err(number), Some(error)) => {
+                assert_eq!(error, number, "Expected the global paused error")
+            }
+            (Ok(()), None) => {}
+            (result, _) => panic!(
+                "Unexpected liquid-unstake result {:?}, paused {}",
+                result, paused
+            ),
         }
     }
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_liquid_unstake_paused_deposit_open() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    update_referral_execute(
+        &mut test,
+        marinade_referral_test_globals.global_state_pubkey,
+        &marinade_referral_test_globals.admin_key,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        marinade_referral_test_globals.partner.keypair.pubkey(),
+        marinade_referral_test_globals.msol_partner_token_pubkey,
+        PAUSE_LIQUID_UNSTAKE,
+    )
+    .await
+    .unwrap();
+
+    // deposits stay open
+    let mut user = test
+        .create_test_user("test_dep_sol_user", 200 * LAMPORTS_PER_SOL)
+        .await;
+    do_deposit_sol(
+        &mut user,
+        LAMPORTS_PER_SOL,
+        &mut test,
+        &marinade_referral_test_globals,
+        0,
+    )
+    .await
+    .unwrap();
+
+    let user_msol_account = user.get_or_create_msol_account_instruction(&mut test).await;
+    let unstake_result = try_liquid_unstake(
+        &mut test,
+        &mut user,
+        &user_msol_account, // msol unstaked from here
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        user_msol_account.pubkey,
+        0,
+        &[],
+    )
+    .await;
+    match unstake_result {
+        Ok(_) => panic!("Expected error happens when liquid unstake is paused"),
+        Err(number) => assert_eq!(301, number, "Expected the partner paused error"),
+    }
+    Ok(())
+}
+
+async fn try_deposit_with_min_out(
+    test: &mut IntegrationTest,
+    user: &mut TestUser,
+    mint_to: Pubkey,
+    marinade_referral_test_globals: &MarinadeReferralTestGlobals,
+    lamports: u64,
+    min_msol_out: u64,
+) -> Result<(), u32> {
+    let marinade_instance_state = test.state.key();
+    let accounts = marinade_referral::accounts::Deposit {
+        state: marinade_instance_state,
+        msol_mint: test.state.as_ref().msol_mint,
+        liq_pool_sol_leg_pda: test.state.liq_pool_sol_leg_address(),
+        liq_pool_msol_leg: test.state.as_ref().liq_pool.msol_leg,
+        liq_pool_msol_leg_authority: test.state.liq_pool_msol_leg_authority(),
+        reserve_pda: State::find_reserve_address(&marinade_instance_state).0,
+        transfer_from: user.keypair.pubkey(),
+        mint_to,
+        msol_mint_authority: State::find_msol_mint_authority(&marinade_instance_state).0,
+        system_program: system_program::ID,
+        token_program: spl_token::ID,
+        //----
+        marinade_finance_program: marinade_finance::ID,
+        global_state: GlobalState::find_address().0,
+        referral_state: marinade_referral_test_globals.partner_referral_state_pubkey,
+        msol_token_partner_account: marinade_referral_test_globals.msol_partner_token_pubkey,
+    };
+    let ix_data = marinade_referral::instruction::DepositWithMinOut {
+        lamports,
+        min_msol_out,
+    };
+    let deposit_instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    test.try_execute_instruction(
+        deposit_instruction,
+        vec![test.fee_payer_signer(), user.keypair.clone()],
+    )
+    .await
+}
+
+#[test(tokio::test)]
+async fn test_deposit_sol_with_min_out() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, mut rng) = IntegrationTest::init_test().await?;
+    marinade_referral_test_globals
+        .set_no_operation_fees(&mut test)
+        .await;
+    let mut user = test
+        .create_test_user("test_dep_sol_user", 200 * LAMPORTS_PER_SOL)
+        .await;
+    let user_msol_account = user.get_or_create_msol_account_instruction(&mut test).await;
+    test.execute().await;
+
+    // mSOL price=1, user gets exactly the deposited lamports in mSOL
+    let lamports = random_amount(1, 100, &mut rng);
+    const ERR_CODE_SLIPPAGE_EXCEEDED: u32 = 318;
+    assert_eq!(
+        try_deposit_with_min_out(
+            &mut test,
+            &mut user,
+            user_msol_account.pubkey,
+            &marinade_referral_test_globals,
+            lamports,
+            lamports + 1,
+        )
+        .await,
+        Err(ERR_CODE_SLIPPAGE_EXCEEDED)
+    );
+    assert_eq!(test.get_token_balance_or_zero(&user_msol_account.pubkey).await, 0);
+
+    try_deposit_with_min_out(
+        &mut test,
+        &mut user,
+        user_msol_account.pubkey,
+        &marinade_referral_test_globals,
+        lamports,
+        lamports,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        test.get_token_balance(&user_msol_account.pubkey).await,
+        lamports
+    );
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_liquid_unstake_with_min_out() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    marinade_referral_test_globals
+        .set_no_operation_fees(&mut test)
+        .await;
+
+    let mut alice = test
+        .create_test_user("alice", 1000 * LAMPORTS_PER_SOL)
+        .await;
+    do_deposit_sol(
+        &mut alice,
+        26 * LAMPORTS_PER_SOL,
+        &mut test,
+        &marinade_referral_test_globals,
+        0,
+    )
+    .await
+    .unwrap();
+    let mut bob = test
+        .create_test_user("bob", 50_000 * LAMPORTS_PER_SOL)
+        .await;
+    do_add_liquidity(&mut bob, 25 * LAMPORTS_PER_SOL, &mut test)
+        .await
+        .unwrap();
+
+    // the liquid-unstake fee is > 0, so the user can not receive the whole mSOL amount in lamports
+    let msol_amount = 10 * LAMPORTS_PER_SOL;
+    let alice_msol_account = alice.get_or_create_msol_account_instruction(&mut test).await;
+    let accounts = marinade_referral::accounts::LiquidUnstake {
+        state: test.state.key(),
+        get_msol_from: alice_msol_account.pubkey,
+        get_msol_from_authority: alice.keypair.pubkey(),
+        transfer_sol_to: alice.keypair.pubkey(),
+        treasury_msol_account: test.state.treasury_msol_account,
+        msol_mint: test.state.as_ref().msol_mint,
+        liq_pool_sol_leg_pda: test.state.liq_pool_sol_leg_address(),
+        liq_pool_msol_leg: test.state.as_ref().liq_pool.msol_leg,
+        system_program: system_program::ID,
+        token_program: spl_token::ID,
+        //----
+        marinade_finance_program: marinade_finance::ID,
+        global_state: GlobalState::find_address().0,
+        referral_state: marinade_referral_test_globals.partner_referral_state_pubkey,
+        msol_token_partner_account: marinade_referral_test_globals.msol_partner_token_pubkey,
+    };
+    let ix_data = marinade_referral::instruction::LiquidUnstakeWithMinOut {
+        msol_amount,
+        min_lamports_out: msol_amount,
+    };
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    const ERR_CODE_SLIPPAGE_EXCEEDED: u32 = 318;
+    assert_eq!(
+        test.try_execute_instruction(
+            instruction,
+            vec![test.fee_payer_signer(), alice.keypair.clone()],
+        )
+        .await,
+        Err(ERR_CODE_SLIPPAGE_EXCEEDED)
+    );
+    Ok(())
 }
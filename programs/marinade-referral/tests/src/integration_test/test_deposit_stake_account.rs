@@ -1,10 +1,13 @@
 #![allow(unused_imports)]
 use crate::integration_test::{
-    get_account, init_marinade_referral_test_globals, update_operation_fees, IntegrationTest,
+    emitted_events, get_account, init_marinade_referral_test_globals,
+    init_referral_account_execute, refresh_collateral_execute, set_global_pause_execute,
+    settle_period_execute, update_deposit_caps_execute, update_operation_fees, IntegrationTest,
     MarinadeReferralTestGlobals, TestUser,
 };
 
 use marinade_finance_offchain_sdk::anchor_lang::solana_program::native_token::LAMPORTS_PER_SOL;
+use marinade_referral::events::{DepositStakeAccountEvent, RefreshCollateralEvent};
 use marinade_finance_offchain_sdk::anchor_lang::InstructionData;
 use marinade_finance_offchain_sdk::anchor_lang::ToAccountMetas;
 use marinade_finance_offchain_sdk::marinade_finance;
@@ -24,9 +27,9 @@ use rand::SeedableRng;
 use rand_chacha::ChaChaRng;
 use solana_sdk::stake;
 use solana_sdk::stake::instruction::LockupArgs;
-use solana_sdk::stake::state::Lockup;
+use solana_sdk::stake::state::{Lockup, StakeState};
 use solana_sdk::{
-    instruction::Instruction,
+    instruction::{AccountMeta, Instruction},
     native_token::sol_to_lamports,
     pubkey::Pubkey,
     signature::{Keypair, Signer},
@@ -74,6 +77,8 @@ async fn test_deposit_stake_account_with_fees() -> anyhow::Result<()> {
         Some(22), // deposit stake account
         Some(0),  // unstake liquid
         Some(0),  // unstake delayed
+        None,     // add liquidity
+        None,     // remove liquidity
     )
     .await
     .unwrap();
@@ -124,6 +129,35 @@ async fn test_deposit_stake_account_with_fees() -> anyhow::Result<()> {
         0,
         "Deposit stake account operation accumulator fee does not increased by exepected amount"
     );
+
+    // the deposit is reported with the accumulators after it
+    let deposit_events: Vec<DepositStakeAccountEvent> =
+        emitted_events::<DepositStakeAccountEvent>()
+            .into_iter()
+            .filter(|event| {
+                event.referral_state
+                    == marinade_referral_test_globals.partner_referral_state_pubkey
+            })
+            .collect();
+    assert_eq!(1, deposit_events.len());
+    let deposit_event = &deposit_events[0];
+    assert_eq!(test.fee_payer(), deposit_event.user);
+    assert_eq!(simple_stake.pubkey(), deposit_event.stake_account);
+    assert_eq!(
+        simple_stake_state.delegation().unwrap().stake,
+        deposit_event.lamports
+    );
+    assert_eq!(0, deposit_event.kept_stake);
+    assert_eq!(operation_fee_lamports, deposit_event.operation_fee);
+    assert_eq!(
+        referral_state_after.deposit_stake_account_amount,
+        deposit_event.deposit_stake_account_amount
+    );
+    assert_eq!(1, deposit_event.deposit_stake_account_operations);
+    assert_eq!(
+        operation_fee_lamports,
+        deposit_event.accum_deposit_stake_account_fee
+    );
     Ok(())
 }
 
@@ -180,16 +214,234 @@ async fn test_deposit_stake_account_fail_when_paused() -> anyhow::Result<()> {
     match deposit_stake_account_result {
         Ok(_) => panic!("Expected error happens when referral account is paused"),
         Err(number) => {
-            // https://github.com/coral-xyz/anchor/blob/v0.14.0/lang/src/error.rs
-            assert_eq!(
-                143, number,
-                "Expected anchor error 'A raw constraint was violated'"
-            );
+            assert_eq!(301, number, "Expected the partner paused error");
         }
     }
     Ok(())
 }
 
+#[test(tokio::test)]
+async fn test_deposit_stake_account_fail_when_globally_paused() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, mut rng) = IntegrationTest::init_test().await?;
+    let global_state_pubkey = marinade_referral_test_globals.global_state_pubkey;
+    let (vote, simple_stake, user_msol) = create_staked_validator(&mut test, &mut rng).await?;
+
+    for &(paused, expected_error) in [(true, Some(335)), (false, None)].iter() {
+        set_global_pause_execute(
+            &mut test,
+            global_state_pubkey,
+            &marinade_referral_test_globals.admin_key,
+            paused,
+        )
+        .await
+        .unwrap();
+        let tx = referral_deposit_stake_account_txn(
+            simple_stake.pubkey(),
+            test.fee_payer(),
+            user_msol,
+            0,
+            vote.pubkey(),
+            &mut test,
+            marinade_referral_test_globals.partner_referral_state_pubkey,
+            marinade_referral_test_globals.msol_partner_token_pubkey,
+        );
+        let deposit_stake_account_result =
+            test.try_execute_txn(tx, vec![test.fee_payer_signer()]).await;
+        match (deposit_stake_account_result, expected_error) {
+            (Err(number), Some(error)) => {
+                assert_eq!(error, number, "Expected the global paused error")
+            }
+            (Ok(()), None) => {}
+            (result, _) => panic!("Unexpected deposit result {:?}, paused {}", result, paused),
+        }
+    }
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_deposit_stake_account_caps() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, mut rng) = IntegrationTest::init_test().await?;
+    let referral_state_pubkey = marinade_referral_test_globals.partner_referral_state_pubkey;
+    let (vote, simple_stake, user_msol) = create_staked_validator(&mut test, &mut rng).await?;
+    let simple_stake_state: StakeWrapper = test.get_account_data(&simple_stake.pubkey()).await;
+    let stake = simple_stake_state.delegation().unwrap().stake;
+
+    // the per-epoch cap and then the total cap stop the deposit of the whole stake
+    for &(max_total_deposit, max_deposit_per_epoch, expected_error) in [
+        (2 * stake, stake - 1, Some(341)),
+        (2 * stake - 1, 0, None),
+    ]
+    .iter()
+    {
+        update_deposit_caps_execute(
+            &mut test,
+            marinade_referral_test_globals.global_state_pubkey,
+            &marinade_referral_test_globals.admin_key,
+            referral_state_pubkey,
+            max_total_deposit,
+            max_deposit_per_epoch,
+        )
+        .await
+        .unwrap();
+        let tx = referral_deposit_stake_account_txn(
+            simple_stake.pubkey(),
+            test.fee_payer(),
+            user_msol,
+            0,
+            vote.pubkey(),
+            &mut test,
+            referral_state_pubkey,
+            marinade_referral_test_globals.msol_partner_token_pubkey,
+        );
+        let deposit_stake_account_result =
+            test.try_execute_txn(tx, vec![test.fee_payer_signer()]).await;
+        match (deposit_stake_account_result, expected_error) {
+            (Err(number), Some(error)) => {
+                assert_eq!(error, number, "Expected the deposit cap error")
+            }
+            (Ok(()), None) => {}
+            (result, _) => panic!("Unexpected deposit result {:?}", result),
+        }
+    }
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, referral_state_pubkey).await;
+    assert_eq!(stake as u128, referral_state.total_deposit_amount);
+
+    // a second stake of the same size goes over the total cap
+    let other_stake = test
+        .create_activated_stake_account(&vote.pubkey(), 10 * LAMPORTS_PER_SOL)
+        .await;
+    let tx = referral_deposit_stake_account_txn(
+        other_stake.pubkey(),
+        test.fee_payer(),
+        user_msol,
+        0,
+        vote.pubkey(),
+        &mut test,
+        referral_state_pubkey,
+        marinade_referral_test_globals.msol_partner_token_pubkey,
+    );
+    match test.try_execute_txn(tx, vec![test.fee_payer_signer()]).await {
+        Err(number) => assert_eq!(341, number, "Expected the deposit cap error"),
+        _ => panic!("Expected the total cap stops the deposit"),
+    }
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_deposit_stake_account_custodial_fee_authority() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, mut rng) = IntegrationTest::init_test().await?;
+    let referral_state_pubkey = marinade_referral_test_globals.partner_referral_state_pubkey;
+    update_operation_fees(
+        &mut test,
+        marinade_referral_test_globals.global_state_pubkey,
+        &marinade_referral_test_globals.admin_key,
+        referral_state_pubkey,
+        None,
+        Some(50),
+        None,
+        None,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    let (vote, simple_stake, _) = create_staked_validator(&mut test, &mut rng).await?;
+    // the minted mSOL goes to the account of a custodian
+    let custodian = test
+        .create_test_user("test_custodian", LAMPORTS_PER_SOL)
+        .await;
+    let custodian_msol_account = custodian
+        .get_or_create_msol_account_instruction(&mut test)
+        .await;
+    test.execute().await;
+
+    // the stake authority can't pay the fee from the custodian account
+    let tx = referral_deposit_stake_account_txn(
+        simple_stake.pubkey(),
+        test.fee_payer(),
+        custodian_msol_account.pubkey,
+        0,
+        vote.pubkey(),
+        &mut test,
+        referral_state_pubkey,
+        marinade_referral_test_globals.msol_partner_token_pubkey,
+    );
+    match test.try_execute_txn(tx, vec![test.fee_payer_signer()]).await {
+        Err(number) => assert_eq!(339, number, "Expected the fee authority error"),
+        _ => panic!("Expected the stake authority can't pay the fee from the custodian account"),
+    }
+
+    let tx = referral_deposit_stake_account_with_fee_authority_txn(
+        simple_stake.pubkey(),
+        test.fee_payer(),
+        custodian_msol_account.pubkey,
+        0,
+        vote.pubkey(),
+        &mut test,
+        referral_state_pubkey,
+        marinade_referral_test_globals.msol_partner_token_pubkey,
+        custodian.keypair.pubkey(),
+    );
+    test.try_execute_txn(tx, vec![test.fee_payer_signer(), custodian.keypair.clone()])
+        .await
+        .unwrap();
+    let simple_stake_state: StakeWrapper = test.get_account_data(&simple_stake.pubkey()).await;
+    let operation_fee = simple_stake_state.delegation().unwrap().stake * 50 / 10_000;
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, referral_state_pubkey).await;
+    assert_eq!(operation_fee, referral_state.accum_deposit_stake_account_fee);
+
+    // a delegate of the custodian account pays the fee up to the delegated amount
+    let operator = test
+        .create_test_user("test_custodian_operator", LAMPORTS_PER_SOL)
+        .await;
+    let other_stake = test
+        .create_activated_stake_account(&vote.pubkey(), 10 * LAMPORTS_PER_SOL)
+        .await;
+    for (delegated_amount, expected_error) in vec![(1, Some(340)), (LAMPORTS_PER_SOL, None)] {
+        let approve_instruction = spl_token::instruction::approve(
+            &spl_token::ID,
+            &custodian_msol_account.pubkey,
+            &operator.keypair.pubkey(),
+            &custodian.keypair.pubkey(),
+            &[],
+            delegated_amount,
+        )?;
+        test.try_execute_instruction(
+            approve_instruction,
+            vec![test.fee_payer_signer(), custodian.keypair.clone()],
+        )
+        .await
+        .unwrap();
+        let tx = referral_deposit_stake_account_with_fee_authority_txn(
+            other_stake.pubkey(),
+            test.fee_payer(),
+            custodian_msol_account.pubkey,
+            0,
+            vote.pubkey(),
+            &mut test,
+            referral_state_pubkey,
+            marinade_referral_test_globals.msol_partner_token_pubkey,
+            operator.keypair.pubkey(),
+        );
+        let deposit_stake_account_result = test
+            .try_execute_txn(tx, vec![test.fee_payer_signer(), operator.keypair.clone()])
+            .await;
+        match (deposit_stake_account_result, expected_error) {
+            (Err(number), Some(error)) => {
+                assert_eq!(error, number, "Expected the fee over delegated amount error")
+            }
+            (Ok(()), None) => {}
+            (result, _) => panic!("Unexpected deposit result {:?} by the delegate", result),
+        }
+    }
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, referral_state_pubkey).await;
+    assert_eq!(2, referral_state.deposit_stake_account_operations);
+    Ok(())
+}
+
 #[test(tokio::test)]
 async fn test_deposit_stake_account() -> anyhow::Result<()> {
     let (mut test, marinade_referral_test_globals, mut rng) = IntegrationTest::init_test().await?;
@@ -381,21 +633,194 @@ async fn test_deposit_stake_account() -> anyhow::Result<()> {
     Ok(())
 }
 
-pub fn referral_deposit_stake_account_txn(
+#[test(tokio::test)]
+async fn test_deposit_stake_account_as_collateral() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, mut rng) = IntegrationTest::init_test().await?;
+    let (vote, simple_stake, user_msol) = create_staked_validator(&mut test, &mut rng).await?;
+
+    // stake-as-collateral partner keeping 20%, the fee payer owns the stake account
+    let fee_payer = test.fee_payer();
+    let collateral_referral_state = init_referral_account_execute(
+        &mut test,
+        fee_payer,
+        marinade_referral_test_globals.global_state_pubkey,
+        &marinade_referral_test_globals.admin_key,
+        user_msol,
+        Some(vote.pubkey()),
+        20,
+    )
+    .await
+    .unwrap();
+
+    let simple_stake_state: StakeWrapper = test.get_account_data(&simple_stake.pubkey()).await;
+    let stake = simple_stake_state.delegation().unwrap().stake;
+
+    // the whole stake account can't be deposited, the kept part has to be split off
+    let tx = referral_deposit_stake_account_txn(
+        simple_stake.pubkey(),
+        test.fee_payer(),
+        user_msol,
+        0,
+        vote.pubkey(),
+        &mut test,
+        collateral_referral_state,
+        user_msol,
+    );
+    match test.try_execute_txn(tx, vec![test.fee_payer_signer()]).await {
+        Err(error_number) => assert_eq!(333, error_number, "Expected split required error"),
+        _ => panic!("Expected the transaction fails with the split required error."),
+    }
+
+    let split_stake = Arc::new(Keypair::new());
+    let tx = referral_deposit_stake_account_as_collateral_txn(
+        simple_stake.pubkey(),
+        split_stake.pubkey(),
+        test.fee_payer(),
+        user_msol,
+        0,
+        vote.pubkey(),
+        &mut test,
+        collateral_referral_state,
+        user_msol,
+    );
+    test.execute_txn(tx, vec![test.fee_payer_signer(), split_stake.clone()])
+        .await;
+
+    // 80% went to Marinade, 20% stays delegated to the partner validator
+    // (less the rent reserve of the split stake account)
+    let split_lamports = stake * 20 / 100;
+    let deposited_stake = stake - split_lamports;
+    assert_eq!(
+        test.get_token_balance_or_zero(&user_msol).await,
+        deposited_stake
+    );
+    let split_stake_state: StakeWrapper = test.get_account_data(&split_stake.pubkey()).await;
+    let kept_delegation = split_stake_state.delegation().unwrap();
+    assert_eq!(vote.pubkey(), kept_delegation.voter_pubkey);
+    assert_eq!(
+        split_lamports - test.rent.minimum_balance(std::mem::size_of::<StakeState>()),
+        kept_delegation.stake
+    );
+
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, collateral_referral_state).await;
+    assert_eq!(
+        deposited_stake as u128,
+        referral_state.deposit_stake_account_amount
+    );
+    assert_eq!(1, referral_state.deposit_stake_account_operations);
+    assert_eq!(
+        kept_delegation.stake as u128,
+        referral_state.keep_self_stake_amount
+    );
+    assert_eq!(
+        deposited_stake as u128,
+        referral_state.total_deposit_stake_account_amount
+    );
+
+    // the partner holds all the mSOL minted for the deposit
+    let global_state_pubkey = marinade_referral_test_globals.global_state_pubkey;
+    refresh_collateral_execute(
+        &mut test,
+        global_state_pubkey,
+        collateral_referral_state,
+        user_msol,
+    )
+    .await
+    .unwrap();
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, collateral_referral_state).await;
+    assert_eq!(deposited_stake, referral_state.current_collateral_lamports);
+    assert_eq!(
+        test.get_clock().await.slot,
+        referral_state.last_refresh_slot
+    );
+
+    // half of the mSOL moved away, the collateral follows
+    let other = test.create_test_user("other", LAMPORTS_PER_SOL).await;
+    let other_msol = other.get_or_create_msol_account_instruction(&mut test).await;
+    test.execute().await;
+    test.builder.add_instruction(
+        spl_token::instruction::transfer(
+            &spl_token::ID,
+            &user_msol,
+            &other_msol.pubkey,
+            &test.fee_payer(),
+            &[],
+            deposited_stake / 2,
+        )?,
+        format!("move mSOL away from the partner"),
+    )?;
+    test.execute().await;
+    refresh_collateral_execute(
+        &mut test,
+        global_state_pubkey,
+        collateral_referral_state,
+        user_msol,
+    )
+    .await
+    .unwrap();
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, collateral_referral_state).await;
+    assert_eq!(
+        deposited_stake - deposited_stake / 2,
+        referral_state.current_collateral_lamports
+    );
+
+    // the shortfall is reported for the monitoring
+    let refresh_events: Vec<RefreshCollateralEvent> = emitted_events::<RefreshCollateralEvent>()
+        .into_iter()
+        .filter(|event| event.referral_state == collateral_referral_state)
+        .collect();
+    assert_eq!(2, refresh_events.len());
+    assert_eq!(0, refresh_events[0].collateral_shortfall_lamports);
+    assert_eq!(
+        (deposited_stake / 2) as u128,
+        refresh_events[1].collateral_shortfall_lamports
+    );
+
+    // settlements restart the period amounts, the collateral still covers all the deposits
+    settle_period_execute(
+        &mut test,
+        global_state_pubkey,
+        &marinade_referral_test_globals.admin_key,
+        collateral_referral_state,
+    )
+    .await
+    .unwrap();
+    let referral_state: marinade_referral::states::ReferralState =
+        get_account(&mut test, collateral_referral_state).await;
+    assert_eq!(0, referral_state.deposit_stake_account_amount);
+    assert_eq!(
+        deposited_stake as u128,
+        referral_state.total_deposit_stake_account_amount
+    );
+
+    // only stake-as-collateral partners have a collateral
+    let txn_result = refresh_collateral_execute(
+        &mut test,
+        global_state_pubkey,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+        marinade_referral_test_globals.msol_partner_token_pubkey,
+    )
+    .await;
+    match txn_result {
+        Err(error_number) => assert_eq!(314, error_number, "Expected collateral partner error"),
+        _ => panic!("Expected the transaction fails with the collateral partner error."),
+    }
+    Ok(())
+}
+
+fn referral_deposit_stake_account_accounts(
     stake_account: Pubkey,
     stake_authority: Pubkey,
     user_msol_account: Pubkey,
-    validator_index: u32,
     validator_vote: Pubkey,
     test: &mut IntegrationTest,
     referral_key: Pubkey,
     msol_token_partner_account: Pubkey,
-) -> Transaction {
-    // -----------------------------------------
-    // Create a referral DepositStakeAccount instruction.
-    // -----------------------------------------
-
-    let accounts = marinade_referral::accounts::DepositStakeAccount {
+) -> marinade_referral::accounts::DepositStakeAccount {
+    marinade_referral::accounts::DepositStakeAccount {
         state: test.state.key,
         validator_list: *test.state.validator_system.validator_list_address(),
         stake_list: *test.state.stake_system.stake_list_address(),
@@ -414,9 +839,35 @@ pub fn referral_deposit_stake_account_txn(
         stake_program: stake::program::ID,
         //----
         marinade_finance_program: marinade_finance::ID,
+        global_state: marinade_referral::states::GlobalState::find_address().0,
         referral_state: referral_key,
         msol_token_partner_account,
     }
+}
+
+pub fn referral_deposit_stake_account_txn(
+    stake_account: Pubkey,
+    stake_authority: Pubkey,
+    user_msol_account: Pubkey,
+    validator_index: u32,
+    validator_vote: Pubkey,
+    test: &mut IntegrationTest,
+    referral_key: Pubkey,
+    msol_token_partner_account: Pubkey,
+) -> Transaction {
+    // -----------------------------------------
+    // Create a referral DepositStakeAccount instruction.
+    // -----------------------------------------
+
+    let accounts = referral_deposit_stake_account_accounts(
+        stake_account,
+        stake_authority,
+        user_msol_account,
+        validator_vote,
+        test,
+        referral_key,
+        msol_token_partner_account,
+    )
     .to_account_metas(None);
 
     let ix_data = marinade_referral::instruction::DepositStakeAccount { validator_index };
@@ -428,3 +879,78 @@ pub fn referral_deposit_stake_account_txn(
 
     return Transaction::new_with_payer(&[deposit_stake_acc_instruction], Some(&test.fee_payer()));
 }
+
+// fee_authority (owner or delegate of user_msol_account) signs the fee transfer
+// in place of the stake authority, the program id skips the referee record before it
+pub fn referral_deposit_stake_account_with_fee_authority_txn(
+    stake_account: Pubkey,
+    stake_authority: Pubkey,
+    user_msol_account: Pubkey,
+    validator_index: u32,
+    validator_vote: Pubkey,
+    test: &mut IntegrationTest,
+    referral_key: Pubkey,
+    msol_token_partner_account: Pubkey,
+    fee_authority: Pubkey,
+) -> Transaction {
+    let mut accounts = referral_deposit_stake_account_accounts(
+        stake_account,
+        stake_authority,
+        user_msol_account,
+        validator_vote,
+        test,
+        referral_key,
+        msol_token_partner_account,
+    )
+    .to_account_metas(None);
+    accounts.push(AccountMeta::new_readonly(
+        marinade_referral::marinade_referral::ID,
+        false,
+    ));
+    accounts.push(AccountMeta::new_readonly(fee_authority, true));
+
+    let ix_data = marinade_referral::instruction::DepositStakeAccount { validator_index };
+    let deposit_stake_acc_instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts,
+        data: ix_data.data(),
+    };
+
+    return Transaction::new_with_payer(&[deposit_stake_acc_instruction], Some(&test.fee_payer()));
+}
+
+pub fn referral_deposit_stake_account_as_collateral_txn(
+    stake_account: Pubkey,
+    split_stake_account: Pubkey,
+    stake_authority: Pubkey,
+    user_msol_account: Pubkey,
+    validator_index: u32,
+    validator_vote: Pubkey,
+    test: &mut IntegrationTest,
+    referral_key: Pubkey,
+    msol_token_partner_account: Pubkey,
+) -> Transaction {
+    let accounts = marinade_referral::accounts::DepositStakeAccountAsCollateral {
+        deposit_stake_account: referral_deposit_stake_account_accounts(
+            stake_account,
+            stake_authority,
+            user_msol_account,
+            validator_vote,
+            test,
+            referral_key,
+            msol_token_partner_account,
+        ),
+        split_stake_account,
+    }
+    .to_account_metas(None);
+
+    let ix_data =
+        marinade_referral::instruction::DepositStakeAccountAsCollateral { validator_index };
+    let deposit_stake_acc_instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts,
+        data: ix_data.data(),
+    };
+
+    return Transaction::new_with_payer(&[deposit_stake_acc_instruction], Some(&test.fee_payer()));
+}
@@ -0,0 +1,258 @@
+//
+// Integration Test
+// partner share of liquid-unstake fees paid from the treasury vault
+//
+use crate::integration_test::test_add_remove_liquidity::*;
+use crate::integration_test::test_deposit_sol_liquid_unstake::*;
+use crate::integration_test::*;
+
+use marinade_finance_offchain_sdk::{
+    anchor_lang::InstructionData, instruction_helpers::InstructionHelpers, spl_token,
+};
+use marinade_referral::constant::TREASURY_VAULT_AUTHORITY_SEED;
+
+use solana_program::native_token::LAMPORTS_PER_SOL;
+use solana_sdk::{
+    instruction::Instruction,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction,
+};
+use std::sync::Arc;
+use test_env_log::test;
+
+fn treasury_vault_authority() -> Pubkey {
+    Pubkey::find_program_address(
+        &[TREASURY_VAULT_AUTHORITY_SEED],
+        &marinade_referral::marinade_referral::ID,
+    )
+    .0
+}
+
+// creates the mSOL treasury vault owned by the treasury vault authority PDA
+// and funds it with a marinade deposit of `lamports`
+async fn create_treasury_vault(test: &mut IntegrationTest, lamports: u64) -> Pubkey {
+    create_msol_vault(test, &treasury_vault_authority(), lamports).await
+}
+
+async fn create_msol_vault(test: &mut IntegrationTest, owner: &Pubkey, lamports: u64) -> Pubkey {
+    let vault_key = Keypair::new();
+    let vault_pubkey = vault_key.pubkey();
+    test.builder.add_signer(Arc::new(vault_key));
+    test.builder
+        .add_instruction(
+            system_instruction::create_account(
+                &test.builder.fee_payer(),
+                &vault_pubkey,
+                test.rent
+                    .minimum_balance(spl_token::state::Account::LEN),
+                spl_token::state::Account::LEN as u64,
+                &spl_token::ID,
+            ),
+            format!("create treasury vault {}", vault_pubkey),
+        )
+        .unwrap();
+    test.builder
+        .add_instruction(
+            spl_token::instruction::initialize_account(
+                &spl_token::ID,
+                &vault_pubkey,
+                &test.state.msol_mint,
+                owner,
+            )
+            .unwrap(),
+            format!("initialize treasury vault {}", vault_pubkey),
+        )
+        .unwrap();
+    test.execute().await;
+
+    if lamports > 0 {
+        let funder = test
+            .create_test_user("treasury_funder", lamports + LAMPORTS_PER_SOL)
+            .await;
+        test.builder
+            .deposit(&test.state, funder.keypair.clone(), vault_pubkey, lamports);
+        test.execute().await;
+    }
+    vault_pubkey
+}
+
+async fn try_claim_referral_rewards(
+    test: &mut IntegrationTest,
+    marinade_referral_test_globals: &MarinadeReferralTestGlobals,
+    treasury_vault: Pubkey,
+) -> Result<(), u32> {
+    try_claim_referral_rewards_with_authority(
+        test,
+        marinade_referral_test_globals,
+        treasury_vault,
+        treasury_vault_authority(),
+    )
+    .await
+}
+
+async fn try_claim_referral_rewards_with_authority(
+    test: &mut IntegrationTest,
+    marinade_referral_test_globals: &MarinadeReferralTestGlobals,
+    treasury_vault: Pubkey,
+    treasury_vault_authority: Pubkey,
+) -> Result<(), u32> {
+    let accounts = marinade_referral::accounts::ClaimReferralRewards {
+        global_state: marinade_referral_test_globals.global_state_pubkey,
+        referral_state: marinade_referral_test_globals.partner_referral_state_pubkey,
+        treasury_vault,
+        treasury_vault_authority,
+        msol_token_partner_account: marinade_referral_test_globals.msol_partner_token_pubkey,
+        token_program: spl_token::ID,
+    };
+    let ix_data = marinade_referral::instruction::ClaimReferralRewards {};
+    let instruction = Instruction {
+        program_id: marinade_referral::marinade_referral::ID,
+        accounts: accounts.to_account_metas(None),
+        data: ix_data.data(),
+    };
+    println!("marinade-referral claim_referral_rewards");
+    test.try_execute_instruction(instruction, vec![test.fee_payer_signer()])
+        .await
+}
+
+// alice deposits and liquid-unstakes through the referral to generate treasury fees
+pub async fn generate_liquid_unstake_fees(
+    test: &mut IntegrationTest,
+    marinade_referral_test_globals: &MarinadeReferralTestGlobals,
+) {
+    marinade_referral_test_globals
+        .set_no_operation_fees(test)
+        .await;
+    let mut alice = test
+        .create_test_user("alice", 1000 * LAMPORTS_PER_SOL)
+        .await;
+    do_deposit_sol(
+        &mut alice,
+        26 * LAMPORTS_PER_SOL,
+        test,
+        marinade_referral_test_globals,
+        0,
+    )
+    .await
+    .unwrap();
+    let mut bob = test
+        .create_test_user("bob", 50_000 * LAMPORTS_PER_SOL)
+        .await;
+    do_add_liquidity(&mut bob, 25 * LAMPORTS_PER_SOL, test)
+        .await
+        .unwrap();
+    do_liquid_unstake(
+        &mut alice,
+        15 * LAMPORTS_PER_SOL,
+        test,
+        marinade_referral_test_globals,
+        0,
+    )
+    .await
+    .unwrap();
+}
+
+#[test(tokio::test)]
+async fn test_claim_referral_rewards() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    generate_liquid_unstake_fees(&mut test, &marinade_referral_test_globals).await;
+
+    let referral_state_before: marinade_referral::states::ReferralState = get_account(
+        &mut test,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+    )
+    .await;
+    assert!(referral_state_before.liq_unstake_msol_fees > 0);
+    let share_amount = referral_state_before
+        .get_liq_unstake_share_amount()
+        .unwrap();
+    assert!(share_amount > 0);
+
+    let treasury_vault = create_treasury_vault(&mut test, 10 * LAMPORTS_PER_SOL).await;
+    let vault_balance_before = test.get_token_balance(&treasury_vault).await;
+    let partner_msol_balance_before = test
+        .get_token_balance(&marinade_referral_test_globals.msol_partner_token_pubkey)
+        .await;
+
+    try_claim_referral_rewards(&mut test, &marinade_referral_test_globals, treasury_vault)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        test.get_token_balance(&marinade_referral_test_globals.msol_partner_token_pubkey)
+            .await,
+        partner_msol_balance_before + share_amount
+    );
+    assert_eq!(
+        test.get_token_balance(&treasury_vault).await,
+        vault_balance_before - share_amount
+    );
+    let referral_state_after: marinade_referral::states::ReferralState = get_account(
+        &mut test,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+    )
+    .await;
+    assert_eq!(referral_state_after.liq_unstake_msol_fees, 0);
+    assert_eq!(referral_state_after.claimed_liq_unstake_share, share_amount);
+
+    // nothing more to pay, claiming again is a no-op
+    try_claim_referral_rewards(&mut test, &marinade_referral_test_globals, treasury_vault)
+        .await
+        .unwrap();
+    assert_eq!(
+        test.get_token_balance(&treasury_vault).await,
+        vault_balance_before - share_amount
+    );
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_claim_referral_rewards_empty_vault() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    generate_liquid_unstake_fees(&mut test, &marinade_referral_test_globals).await;
+
+    let treasury_vault = create_treasury_vault(&mut test, 0).await;
+    const ERR_CODE_INSUFFICIENT_TREASURY_VAULT_BALANCE: u32 = 320;
+    assert_eq!(
+        try_claim_referral_rewards(&mut test, &marinade_referral_test_globals, treasury_vault)
+            .await,
+        Err(ERR_CODE_INSUFFICIENT_TREASURY_VAULT_BALANCE)
+    );
+
+    // accumulators are kept for a later claim
+    let referral_state: marinade_referral::states::ReferralState = get_account(
+        &mut test,
+        marinade_referral_test_globals.partner_referral_state_pubkey,
+    )
+    .await;
+    assert!(referral_state.liq_unstake_msol_fees > 0);
+    assert_eq!(referral_state.claimed_liq_unstake_share, 0);
+    Ok(())
+}
+
+#[test(tokio::test)]
+async fn test_claim_referral_rewards_fail_other_vault_authority() -> anyhow::Result<()> {
+    let (mut test, marinade_referral_test_globals, _) = IntegrationTest::init_test().await?;
+    generate_liquid_unstake_fees(&mut test, &marinade_referral_test_globals).await;
+
+    // vault of another program PDA, only the canonical treasury vault authority can pay
+    let (other_authority, _) = Pubkey::find_program_address(
+        &[b"other_vault_authority"],
+        &marinade_referral::marinade_referral::ID,
+    );
+    let other_vault = create_msol_vault(&mut test, &other_authority, 10 * LAMPORTS_PER_SOL).await;
+    const ERR_CODE_INVALID_TREASURY_VAULT_AUTHORITY: u32 = 319;
+    assert_eq!(
+        try_claim_referral_rewards_with_authority(
+            &mut test,
+            &marinade_referral_test_globals,
+            other_vault,
+            other_authority,
+        )
+        .await,
+        Err(ERR_CODE_INVALID_TREASURY_VAULT_AUTHORITY)
+    );
+    Ok(())
+}